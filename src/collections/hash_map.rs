@@ -0,0 +1,614 @@
+use std::rc::Rc;
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+
+const BITS: u32 = 5;
+const MASK: u32 = (1 << BITS) - 1;
+
+/// Maps with at most this many entries are kept as a flat array of pairs.
+const ARRAY_MAP_LIMIT: usize = 8;
+
+/// Hashes a value with a fixed-key hasher, so iteration order is the same on every run.
+pub fn hash_of<T: Hash + ?Sized>(value: &T) -> u32 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    let hash = hasher.finish();
+    (hash ^ (hash >> 32)) as u32
+}
+
+#[derive(Debug, Clone)]
+enum Entry<K, V> {
+    Pair(K, V),
+    Node(Rc<TrieNode<K, V>>)
+}
+
+#[derive(Debug, Clone)]
+enum TrieNode<K, V> {
+    Bitmap(u32, Vec<Entry<K, V>>),
+    Collision(u32, Vec<(K, V)>)
+}
+
+#[derive(Debug, Clone)]
+enum Root<K, V> {
+    Array(Rc<Vec<(K, V)>>),
+    Trie(Rc<TrieNode<K, V>>)
+}
+
+/// Persistent hash map implemented as a hash array mapped trie.
+///
+/// Small maps are stored as an array of pairs in insertion order and are promoted to a
+/// trie once they outgrow `ARRAY_MAP_LIMIT`. Keys whose hashes collide completely share
+/// a collision node. As with `PersistentVector`, the `*_mut` methods copy on write.
+#[derive(Debug, Clone)]
+pub struct PersistentHashMap<K, V> {
+    len: usize,
+    root: Root<K, V>
+}
+
+fn bit_for(hash: u32, shift: u32) -> u32 {
+    1 << ((hash >> shift) & MASK)
+}
+
+fn index_for(bitmap: u32, bit: u32) -> usize {
+    (bitmap & (bit - 1)).count_ones() as usize
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PersistentHashMap<K, V> {
+
+    pub fn new() -> Self {
+        Self {
+            len: 0,
+            root: Root::Array(Rc::new(Vec::new()))
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.get_entry(key).map(|(_, v)| v)
+    }
+
+    pub fn get_entry(&self, key: &K) -> Option<(&K, &V)> {
+        match &self.root {
+            Root::Array(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(k, v)| (k, v)),
+            Root::Trie(node) => node.get(0, hash_of(key), key)
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        match &self.root {
+            Root::Array(pairs) => Iter {
+                pairs: pairs.iter(),
+                stack: Vec::new()
+            },
+            Root::Trie(node) => Iter {
+                pairs: [].iter(),
+                stack: vec![NodeCursor::new(node)]
+            }
+        }
+    }
+
+    /// Iterates over clones of the entries, holding the parts of the map it still has to go
+    /// through rather than borrowing it.
+    pub fn cursor(&self) -> Cursor<K, V> {
+        match &self.root {
+            Root::Array(pairs) => Cursor {
+                pairs: Some(pairs.clone()),
+                index: 0,
+                stack: Vec::new()
+            },
+            Root::Trie(node) => Cursor {
+                pairs: None,
+                index: 0,
+                stack: vec![(node.clone(), 0)]
+            }
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    pub fn assoc(&self, key: K, value: V) -> Self {
+        let mut map = self.clone();
+        map.insert_mut(key, value);
+        map
+    }
+
+    /// Inserts or replaces the value for `key`. Returns `true` if the key was not present.
+    pub fn insert_mut(&mut self, key: K, value: V) -> bool {
+        let added = match &mut self.root {
+            Root::Array(pairs) => {
+                if let Some(pair) = pairs.iter().position(|(k, _)| *k == key) {
+                    Rc::make_mut(pairs)[pair].1 = value;
+                    false
+                } else if pairs.len() < ARRAY_MAP_LIMIT {
+                    Rc::make_mut(pairs).push((key, value));
+                    true
+                } else {
+                    let mut node = TrieNode::Bitmap(0, Vec::new());
+                    for (k, v) in pairs.iter() {
+                        node.insert(0, hash_of(k), k.clone(), v.clone());
+                    }
+                    node.insert(0, hash_of(&key), key, value);
+                    self.root = Root::Trie(Rc::new(node));
+                    true
+                }
+            },
+            Root::Trie(node) => Rc::make_mut(node).insert(0, hash_of(&key), key, value)
+        };
+        if added {
+            self.len += 1;
+        }
+        added
+    }
+
+    /// Removes `key` from the map. Returns `true` if it was present.
+    pub fn remove_mut(&mut self, key: &K) -> bool {
+        let removed = match &mut self.root {
+            Root::Array(pairs) => {
+                if let Some(pair) = pairs.iter().position(|(k, _)| k == key) {
+                    Rc::make_mut(pairs).remove(pair);
+                    true
+                } else {
+                    false
+                }
+            },
+            Root::Trie(node) => {
+                if node.get(0, hash_of(key), key).is_some() {
+                    Rc::make_mut(node).remove(0, hash_of(key), key);
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        if removed {
+            self.len -= 1;
+        }
+        removed
+    }
+
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TrieNode<K, V> {
+
+    fn get(&self, shift: u32, hash: u32, key: &K) -> Option<(&K, &V)> {
+        match self {
+            TrieNode::Bitmap(bitmap, entries) => {
+                let bit = bit_for(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                match &entries[index_for(*bitmap, bit)] {
+                    Entry::Pair(k, v) if k == key => Some((k, v)),
+                    Entry::Pair(_, _) => None,
+                    Entry::Node(node) => node.get(shift + BITS, hash, key)
+                }
+            },
+            TrieNode::Collision(_, pairs) => pairs.iter().find(|(k, _)| k == key).map(|(k, v)| (k, v))
+        }
+    }
+
+    fn insert(&mut self, shift: u32, hash: u32, key: K, value: V) -> bool {
+        match self {
+            TrieNode::Bitmap(bitmap, entries) => {
+                let bit = bit_for(hash, shift);
+                let index = index_for(*bitmap, bit);
+                if *bitmap & bit == 0 {
+                    entries.insert(index, Entry::Pair(key, value));
+                    *bitmap |= bit;
+                    return true;
+                }
+                match &mut entries[index] {
+                    Entry::Pair(k, v) if *k == key => {
+                        *v = value;
+                        false
+                    },
+                    Entry::Pair(k, v) => {
+                        let node = Self::pair_node(shift + BITS, (k.clone(), v.clone()), (hash, key, value));
+                        entries[index] = Entry::Node(Rc::new(node));
+                        true
+                    },
+                    Entry::Node(node) => Rc::make_mut(node).insert(shift + BITS, hash, key, value)
+                }
+            },
+            TrieNode::Collision(collision_hash, pairs) if *collision_hash == hash => {
+                if let Some(pair) = pairs.iter_mut().find(|(k, _)| *k == key) {
+                    pair.1 = value;
+                    false
+                } else {
+                    pairs.push((key, value));
+                    true
+                }
+            },
+            TrieNode::Collision(collision_hash, _) => {
+                // A different hash reached this collision node, push it one level down.
+                let bit = bit_for(*collision_hash, shift);
+                let collision = std::mem::replace(self, TrieNode::Bitmap(bit, Vec::new()));
+                if let TrieNode::Bitmap(_, entries) = self {
+                    entries.push(Entry::Node(Rc::new(collision)));
+                }
+                self.insert(shift, hash, key, value)
+            }
+        }
+    }
+
+    /// Builds the node that replaces a pair when a second key lands on the same slot.
+    fn pair_node(shift: u32, existing: (K, V), (hash, key, value): (u32, K, V)) -> Self {
+        let existing_hash = hash_of(&existing.0);
+        if existing_hash == hash {
+            TrieNode::Collision(hash, vec![existing, (key, value)])
+        } else {
+            let mut node = TrieNode::Bitmap(0, Vec::new());
+            node.insert(shift, existing_hash, existing.0, existing.1);
+            node.insert(shift, hash, key, value);
+            node
+        }
+    }
+
+    /// Removes a key known to be present in this node.
+    fn remove(&mut self, shift: u32, hash: u32, key: &K) {
+        match self {
+            TrieNode::Bitmap(bitmap, entries) => {
+                let bit = bit_for(hash, shift);
+                let index = index_for(*bitmap, bit);
+                let remove_entry = match &mut entries[index] {
+                    Entry::Pair(_, _) => true,
+                    Entry::Node(node) => {
+                        let node = Rc::make_mut(node);
+                        node.remove(shift + BITS, hash, key);
+                        if let Some(pair) = node.single_pair() {
+                            entries[index] = Entry::Pair(pair.0, pair.1);
+                        }
+                        false
+                    }
+                };
+                if remove_entry {
+                    entries.remove(index);
+                    *bitmap &= !bit;
+                }
+            },
+            TrieNode::Collision(_, pairs) => {
+                pairs.retain(|(k, _)| k != key);
+            }
+        }
+    }
+
+    /// Returns the only pair of a node that holds nothing else, so it can be inlined into its parent.
+    fn single_pair(&self) -> Option<(K, V)> {
+        match self {
+            TrieNode::Bitmap(_, entries) if entries.len() == 1 => match &entries[0] {
+                Entry::Pair(k, v) => Some((k.clone(), v.clone())),
+                Entry::Node(_) => None
+            },
+            TrieNode::Collision(_, pairs) if pairs.len() == 1 => Some(pairs[0].clone()),
+            _ => None
+        }
+    }
+
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for PersistentHashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> std::iter::FromIterator<(K, V)> for PersistentHashMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        for (key, value) in iter {
+            map.insert_mut(key, value);
+        }
+        map
+    }
+}
+
+struct NodeCursor<'a, K, V> {
+    node: &'a TrieNode<K, V>,
+    index: usize
+}
+
+impl<'a, K, V> NodeCursor<'a, K, V> {
+    fn new(node: &'a TrieNode<K, V>) -> Self {
+        Self { node, index: 0 }
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    pairs: std::slice::Iter<'a, (K, V)>,
+    stack: Vec<NodeCursor<'a, K, V>>
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, v)) = self.pairs.next() {
+                return Some((k, v));
+            }
+            let cursor = self.stack.last_mut()?;
+            match cursor.node {
+                TrieNode::Bitmap(_, entries) => {
+                    match entries.get(cursor.index) {
+                        Some(Entry::Pair(k, v)) => {
+                            cursor.index += 1;
+                            return Some((k, v));
+                        },
+                        Some(Entry::Node(node)) => {
+                            cursor.index += 1;
+                            self.stack.push(NodeCursor::new(node));
+                        },
+                        None => {
+                            self.stack.pop();
+                        }
+                    }
+                },
+                TrieNode::Collision(_, pairs) => {
+                    self.pairs = pairs.iter();
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Owned iterator over a map, see `PersistentHashMap::cursor`. Cloning it is cheap, and the
+/// clone goes on from the same entry.
+#[derive(Clone)]
+pub struct Cursor<K, V> {
+    /// Pairs of a small map, with the index of the next one.
+    pairs: Option<Rc<Vec<(K, V)>>>,
+    index: usize,
+    /// Trie nodes being walked, with the index of their next entry.
+    stack: Vec<(Rc<TrieNode<K, V>>, usize)>
+}
+
+impl<K: Clone, V: Clone> Iterator for Cursor<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(pairs) = &self.pairs {
+            let (k, v) = pairs.get(self.index)?;
+            self.index += 1;
+            return Some((k.clone(), v.clone()));
+        }
+        loop {
+            let (node, index) = self.stack.last_mut()?;
+            let child = match node.as_ref() {
+                TrieNode::Bitmap(_, entries) => match entries.get(*index) {
+                    Some(Entry::Pair(k, v)) => {
+                        *index += 1;
+                        return Some((k.clone(), v.clone()));
+                    },
+                    Some(Entry::Node(child)) => {
+                        *index += 1;
+                        Some(child.clone())
+                    },
+                    None => None
+                },
+                TrieNode::Collision(_, pairs) => match pairs.get(*index) {
+                    Some((k, v)) => {
+                        *index += 1;
+                        return Some((k.clone(), v.clone()));
+                    },
+                    None => None
+                }
+            };
+            match child {
+                Some(child) => self.stack.push((child, 0)),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Persistent hash set, a `PersistentHashMap` whose values are all `()`.
+#[derive(Debug, Clone)]
+pub struct PersistentHashSet<T> {
+    map: PersistentHashMap<T, ()>
+}
+
+impl<T: Hash + Eq + Clone> PersistentHashSet<T> {
+
+    pub fn new() -> Self {
+        Self {
+            map: PersistentHashMap::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.map.contains_key(value)
+    }
+
+    pub fn get(&self, value: &T) -> Option<&T> {
+        self.map.get_entry(value).map(|(k, _)| k)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.map.keys()
+    }
+
+    /// Owned iterator over clones of the elements, see `PersistentHashMap::cursor`.
+    pub fn cursor(&self) -> impl Iterator<Item = T> + Clone {
+        self.map.cursor().map(|(value, _)| value)
+    }
+
+    pub fn conj(&self, value: T) -> Self {
+        Self {
+            map: self.map.assoc(value, ())
+        }
+    }
+
+    pub fn insert_mut(&mut self, value: T) -> bool {
+        self.map.insert_mut(value, ())
+    }
+
+    pub fn remove_mut(&mut self, value: &T) -> bool {
+        self.map.remove_mut(value)
+    }
+
+}
+
+impl<T: Hash + Eq + Clone> Default for PersistentHashSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Hash + Eq + Clone> std::iter::FromIterator<T> for PersistentHashSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for value in iter {
+            set.insert_mut(value);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Key whose hash is that of its group alone, so keys of a group collide completely.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Colliding(u32, u32);
+
+    impl Hash for Colliding {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.hash(state);
+        }
+    }
+
+    fn trie<K, V>(map: &PersistentHashMap<K, V>) -> &TrieNode<K, V> {
+        match &map.root {
+            Root::Trie(node) => node,
+            Root::Array(_) => panic!("the map is still an array map")
+        }
+    }
+
+    fn depth<K, V>(node: &TrieNode<K, V>) -> usize {
+        match node {
+            TrieNode::Bitmap(_, entries) => 1 + entries.iter().map(|entry| match entry {
+                Entry::Pair(_, _) => 0,
+                Entry::Node(node) => depth(node)
+            }).max().unwrap_or(0),
+            TrieNode::Collision(_, _) => 1
+        }
+    }
+
+    #[test]
+    fn small_maps_are_arrays_in_insertion_order() {
+        let keys = [5, 3, 8, 1, 9, 2, 7, 4];
+        let map: PersistentHashMap<i32, i32> = keys.iter().map(|&key| (key, key * 10)).collect();
+        assert!(matches!(map.root, Root::Array(_)));
+        assert!(map.keys().copied().eq(keys.iter().copied()));
+        let promoted = map.assoc(6, 60);
+        trie(&promoted);
+        assert_eq!(promoted.len(), 9);
+        assert!((1..=9).all(|key| promoted.get(&key) == Some(&(key * 10))));
+        assert!(matches!(map.root, Root::Array(_)));
+    }
+
+    #[test]
+    fn colliding_keys_share_a_collision_node() {
+        let mut map: PersistentHashMap<Colliding, u32> = (0..20).map(|group| (Colliding(group, 0), group)).collect();
+        for id in 1..4 {
+            assert!(map.insert_mut(Colliding(7, id), 100 + id));
+        }
+        assert!(!map.insert_mut(Colliding(7, 2), 200));
+        assert_eq!(map.len(), 23);
+        assert_eq!(map.get(&Colliding(7, 0)), Some(&7));
+        assert_eq!(map.get(&Colliding(7, 2)), Some(&200));
+        assert_eq!(map.get(&Colliding(7, 4)), None);
+        assert_eq!(map.iter().count(), 23);
+        assert!(map.cursor().eq(map.iter().map(|(k, v)| (k.clone(), *v))));
+
+        let before = map.clone();
+        for id in 0..3 {
+            assert!(map.remove_mut(&Colliding(7, id)));
+        }
+        assert!(!map.remove_mut(&Colliding(7, 0)));
+        assert_eq!(map.len(), 20);
+        assert_eq!(map.get(&Colliding(7, 3)), Some(&103));
+        assert_eq!(before.get(&Colliding(7, 0)), Some(&7));
+        assert_eq!(before.len(), 23);
+    }
+
+    #[test]
+    fn collision_node_moves_down_for_another_hash() {
+        // Find a group whose hash starts with the same bits as that of group 0.
+        let other = (1..).find(|group| hash_of(group) & MASK == hash_of(&0u32) & MASK && hash_of(group) != hash_of(&0u32)).unwrap();
+        let mut map: PersistentHashMap<Colliding, u32> = (100..110).map(|group| (Colliding(group, 0), group)).collect();
+        map.insert_mut(Colliding(0, 0), 0);
+        map.insert_mut(Colliding(0, 1), 1);
+        map.insert_mut(Colliding(other, 0), other);
+        assert_eq!(map.get(&Colliding(0, 0)), Some(&0));
+        assert_eq!(map.get(&Colliding(0, 1)), Some(&1));
+        assert_eq!(map.get(&Colliding(other, 0)), Some(&other));
+        assert_eq!(map.iter().count(), 13);
+    }
+
+    #[test]
+    fn removal_collapses_nodes() {
+        let mut map: PersistentHashMap<u32, u32> = (0..5000).map(|key| (key, key)).collect();
+        assert!(depth(trie(&map)) > 2);
+        for key in 1..5000 {
+            assert!(map.remove_mut(&key));
+        }
+        assert_eq!(map.len(), 1);
+        assert_eq!(depth(trie(&map)), 1);
+        assert_eq!(map.get(&0), Some(&0));
+
+        // Removing a key of a collision node leaves its last pair inline in the parent.
+        let mut map: PersistentHashMap<Colliding, u32> = (0..20).map(|group| (Colliding(group, 0), group)).collect();
+        map.insert_mut(Colliding(3, 1), 31);
+        map.remove_mut(&Colliding(3, 0));
+        assert_eq!(depth(trie(&map)), depth(trie(&(0..20).map(|group| (Colliding(group, 0), group)).collect())));
+        assert_eq!(map.get(&Colliding(3, 1)), Some(&31));
+    }
+
+    #[test]
+    fn cursors_go_on_from_where_they_are_cloned() {
+        for len in [5, 5000] {
+            let map: PersistentHashMap<u32, u32> = (0..len).map(|key| (key, key * 2)).collect();
+            let mut cursor = map.cursor();
+            let first: Vec<_> = cursor.by_ref().take(3).collect();
+            let rest: Vec<_> = cursor.clone().collect();
+            assert_eq!(first.len() + rest.len(), len as usize);
+            assert!(first.into_iter().chain(rest).eq(map.iter().map(|(&k, &v)| (k, v))));
+            assert!(cursor.eq(map.iter().skip(3).map(|(&k, &v)| (k, v))));
+        }
+    }
+
+    #[test]
+    fn sets() {
+        let set: PersistentHashSet<u32> = (0..100).collect();
+        let mut smaller = set.clone();
+        for value in 0..50 {
+            assert!(smaller.remove_mut(&value));
+        }
+        assert_eq!(smaller.len(), 50);
+        assert!(!smaller.contains(&10) && smaller.contains(&60));
+        assert_eq!(set.len(), 100);
+        assert_eq!(set.conj(5).len(), 100);
+    }
+}
@@ -0,0 +1,11 @@
+mod vector;
+mod hash_map;
+mod tree_map;
+mod queue;
+mod transient;
+
+pub use vector::PersistentVector;
+pub use hash_map::{PersistentHashMap, PersistentHashSet, hash_of};
+pub use tree_map::{PersistentTreeMap, PersistentTreeSet};
+pub use queue::{PersistentQueue, PersistentPriorityQueue};
+pub use transient::Transient;
@@ -0,0 +1,193 @@
+use std::cmp::Ordering;
+use super::{PersistentVector, PersistentTreeMap};
+
+/// Persistent FIFO queue.
+///
+/// Elements are taken from the front vector and added to the rear one. Popping narrows the
+/// front vector with `subvec`, and once it runs out the rear vector becomes the new front,
+/// so every operation is effectively constant time.
+#[derive(Debug, Clone)]
+pub struct PersistentQueue<T> {
+    front: PersistentVector<T>,
+    rear: PersistentVector<T>
+}
+
+impl<T: Clone> PersistentQueue<T> {
+
+    pub fn new() -> Self {
+        Self {
+            front: PersistentVector::new(),
+            rear: PersistentVector::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.front.len() + self.rear.len()
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.front.first()
+    }
+
+    pub fn conj(&self, value: T) -> Self {
+        let mut queue = self.clone();
+        if queue.front.is_empty() {
+            queue.front.push_mut(value);
+        } else {
+            queue.rear.push_mut(value);
+        }
+        queue
+    }
+
+    /// Returns the queue without its first element. Popping an empty queue returns it as is.
+    pub fn pop(&self) -> Self {
+        if self.front.len() > 1 {
+            Self {
+                front: self.front.subvec(1, self.front.len()).unwrap(),
+                rear: self.rear.clone()
+            }
+        } else {
+            Self {
+                front: self.rear.clone(),
+                rear: PersistentVector::new()
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.front.iter().chain(self.rear.iter())
+    }
+
+}
+
+impl<T: Clone> Default for PersistentQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> std::iter::FromIterator<T> for PersistentQueue<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut iter = iter.into_iter();
+        match iter.next() {
+            Some(first) => Self {
+                front: std::iter::once(first).collect(),
+                rear: iter.collect()
+            },
+            None => Self::new()
+        }
+    }
+}
+
+/// Persistent priority queue, ordered by a comparator given on insertion.
+///
+/// Elements are kept in a `PersistentTreeMap` from the first element inserted with a
+/// given priority to a FIFO queue of all the elements that compare equal to it. Iterating
+/// the tree therefore gives the elements in priority order without calling the comparator,
+/// and elements with the same priority come out in insertion order.
+#[derive(Debug, Clone)]
+pub struct PersistentPriorityQueue<T> {
+    len: usize,
+    tree: PersistentTreeMap<T, PersistentQueue<T>>
+}
+
+impl<T: Clone> PersistentPriorityQueue<T> {
+
+    pub fn new() -> Self {
+        Self {
+            len: 0,
+            tree: PersistentTreeMap::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.tree.first().and_then(|(_, bucket)| bucket.peek())
+    }
+
+    pub fn conj<E>(&self, value: T, compare: &mut dyn FnMut(&T, &T) -> Result<Ordering, E>) -> Result<Self, E> {
+        let bucket = match self.tree.get(&value, compare)? {
+            Some((_, bucket)) => bucket.conj(value.clone()),
+            None => PersistentQueue::new().conj(value.clone())
+        };
+        Ok(Self {
+            len: self.len + 1,
+            tree: self.tree.assoc(value, bucket, compare)?
+        })
+    }
+
+    /// Returns the queue without its first element. Popping an empty queue returns it as is.
+    pub fn pop(&self) -> Self {
+        match self.tree.first() {
+            Some((_, bucket)) if bucket.len() > 1 => Self {
+                len: self.len - 1,
+                tree: self.tree.with_first_value(bucket.pop())
+            },
+            Some(_) => Self {
+                len: self.len - 1,
+                tree: self.tree.without_first()
+            },
+            None => self.clone()
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.tree.iter().flat_map(|(_, bucket)| bucket.iter())
+    }
+
+}
+
+impl<T: Clone> Default for PersistentPriorityQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_is_first_in_first_out() {
+        let mut queue = PersistentQueue::new();
+        let mut expected = std::collections::VecDeque::new();
+        // Interleave pushes and pops so the rear vector becomes the front several times.
+        for value in 0..200 {
+            queue = queue.conj(value);
+            expected.push_back(value);
+            if value % 3 == 0 {
+                assert_eq!(queue.peek(), expected.front());
+                queue = queue.pop();
+                expected.pop_front();
+            }
+            assert_eq!(queue.len(), expected.len());
+            assert!(queue.iter().eq(expected.iter()));
+        }
+        let popped = queue.pop();
+        assert_eq!(queue.len(), popped.len() + 1);
+        assert_eq!(PersistentQueue::<u32>::new().pop().len(), 0);
+        assert_eq!(PersistentQueue::<u32>::new().peek(), None);
+    }
+
+    #[test]
+    fn priority_queue_keeps_insertion_order_among_equals() {
+        // Order by the first element only, so pairs with the same first element tie.
+        let mut compare = |a: &(u32, u32), b: &(u32, u32)| -> Result<Ordering, ()> { Ok(a.0.cmp(&b.0)) };
+        let mut queue = PersistentPriorityQueue::new();
+        for (index, priority) in [3, 1, 2, 1, 3, 1].iter().enumerate() {
+            queue = queue.conj((*priority, index as u32), &mut compare).unwrap();
+        }
+        assert_eq!(queue.len(), 6);
+        assert!(queue.iter().copied().eq([(1, 1), (1, 3), (1, 5), (2, 2), (3, 0), (3, 4)].iter().copied()));
+        let mut popped = Vec::new();
+        while let Some(&value) = queue.peek() {
+            popped.push(value);
+            queue = queue.pop();
+        }
+        assert_eq!(popped, [(1, 1), (1, 3), (1, 5), (2, 2), (3, 0), (3, 4)]);
+        assert_eq!(queue.pop().len(), 0);
+    }
+}
@@ -0,0 +1,38 @@
+use std::cell::RefCell;
+
+/// Mutable wrapper around a persistent collection, used to build it without creating a
+/// new version on every step.
+///
+/// The wrapped collection is owned by the transient until `persistent` takes it back out,
+/// after which any further use fails instead of touching data that is now shared. Edits
+/// go through the collection's copy-on-write `*_mut` methods, so after the first change to
+/// a node the transient owns it and updates it in place.
+#[derive(Debug)]
+pub struct Transient<T> {
+    value: RefCell<Option<T>>
+}
+
+impl<T> Transient<T> {
+
+    pub fn new(value: T) -> Self {
+        Self {
+            value: RefCell::new(Some(value))
+        }
+    }
+
+    /// Runs `edit` on the wrapped collection. Returns `None` if it was already made persistent.
+    pub fn edit<R>(&self, edit: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.value.borrow_mut().as_mut().map(edit)
+    }
+
+    /// Reads the wrapped collection. Returns `None` if it was already made persistent.
+    pub fn read<R>(&self, read: impl FnOnce(&T) -> R) -> Option<R> {
+        self.value.borrow().as_ref().map(read)
+    }
+
+    /// Takes the collection out, leaving the transient unusable.
+    pub fn persistent(&self) -> Option<T> {
+        self.value.borrow_mut().take()
+    }
+
+}
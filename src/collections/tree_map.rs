@@ -0,0 +1,486 @@
+use std::rc::Rc;
+use std::cmp::Ordering;
+
+type Link<K, V> = Option<Rc<TreeNode<K, V>>>;
+
+#[derive(Debug)]
+struct TreeNode<K, V> {
+    key: K,
+    value: V,
+    height: usize,
+    left: Link<K, V>,
+    right: Link<K, V>
+}
+
+/// Persistent sorted map implemented as an AVL tree with path copying.
+///
+/// The tree doesn't own its ordering: every operation that needs to compare keys takes the
+/// comparator as an argument. This lets comparators be user functions that need the
+/// evaluation context and can fail, in which case the error is passed back to the caller.
+#[derive(Debug, Clone)]
+pub struct PersistentTreeMap<K, V> {
+    len: usize,
+    root: Link<K, V>
+}
+
+fn height<K, V>(link: &Link<K, V>) -> usize {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+fn make<K, V>(key: K, value: V, left: Link<K, V>, right: Link<K, V>) -> Rc<TreeNode<K, V>> {
+    Rc::new(TreeNode {
+        height: 1 + height(&left).max(height(&right)),
+        key,
+        value,
+        left,
+        right
+    })
+}
+
+/// Builds a node out of its parts, rotating if the heights of both sides differ by more than one.
+fn balance<K: Clone, V: Clone>(key: K, value: V, left: Link<K, V>, right: Link<K, V>) -> Rc<TreeNode<K, V>> {
+    let (left_height, right_height) = (height(&left), height(&right));
+    if left_height > right_height + 1 {
+        let left = left.unwrap();
+        if height(&left.left) >= height(&left.right) {
+            let right = make(key, value, left.right.clone(), right);
+            make(left.key.clone(), left.value.clone(), left.left.clone(), Some(right))
+        } else {
+            let middle = left.right.as_ref().unwrap();
+            let new_left = make(left.key.clone(), left.value.clone(), left.left.clone(), middle.left.clone());
+            let new_right = make(key, value, middle.right.clone(), right);
+            make(middle.key.clone(), middle.value.clone(), Some(new_left), Some(new_right))
+        }
+    } else if right_height > left_height + 1 {
+        let right = right.unwrap();
+        if height(&right.right) >= height(&right.left) {
+            let left = make(key, value, left, right.left.clone());
+            make(right.key.clone(), right.value.clone(), Some(left), right.right.clone())
+        } else {
+            let middle = right.left.as_ref().unwrap();
+            let new_left = make(key, value, left, middle.left.clone());
+            let new_right = make(right.key.clone(), right.value.clone(), middle.right.clone(), right.right.clone());
+            make(middle.key.clone(), middle.value.clone(), Some(new_left), Some(new_right))
+        }
+    } else {
+        make(key, value, left, right)
+    }
+}
+
+impl<K: Clone, V: Clone> PersistentTreeMap<K, V> {
+
+    pub fn new() -> Self {
+        Self {
+            len: 0,
+            root: None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get<E>(&self, key: &K, compare: &mut dyn FnMut(&K, &K) -> Result<Ordering, E>) -> Result<Option<(&K, &V)>, E> {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            link = match compare(key, &node.key)? {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Ok(Some((&node.key, &node.value)))
+            };
+        }
+        Ok(None)
+    }
+
+    /// Returns a map with `key` set to `value`. If a key that compares equal to `key` is
+    /// already present, it is kept and only its value is replaced.
+    pub fn assoc<E>(&self, key: K, value: V, compare: &mut dyn FnMut(&K, &K) -> Result<Ordering, E>) -> Result<Self, E> {
+        let (root, added) = insert(&self.root, key, value, compare)?;
+        Ok(Self {
+            len: if added { self.len + 1 } else { self.len },
+            root: Some(root)
+        })
+    }
+
+    pub fn dissoc<E>(&self, key: &K, compare: &mut dyn FnMut(&K, &K) -> Result<Ordering, E>) -> Result<Self, E> {
+        match remove(&self.root, key, compare)? {
+            Some(root) => Ok(Self {
+                len: self.len - 1,
+                root
+            }),
+            None => Ok(self.clone())
+        }
+    }
+
+    /// Returns the smallest entry of the map.
+    pub fn first(&self) -> Option<(&K, &V)> {
+        let mut node = self.root.as_ref()?;
+        while let Some(left) = &node.left {
+            node = left;
+        }
+        Some((&node.key, &node.value))
+    }
+
+    /// Returns the map without its smallest entry. Needs no comparator.
+    pub fn without_first(&self) -> Self {
+        match &self.root {
+            Some(root) => Self {
+                len: self.len - 1,
+                root: remove_min(root).2
+            },
+            None => self.clone()
+        }
+    }
+
+    /// Returns the map with the value of its smallest entry replaced. Needs no comparator.
+    pub fn with_first_value(&self, value: V) -> Self {
+        Self {
+            len: self.len,
+            root: self.root.as_ref().map(|root| replace_min_value(root, value))
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            ascending: true
+        };
+        iter.push_branch(&self.root);
+        iter
+    }
+
+    /// Iterates in ascending order over clones of the entries, holding the parts of the map
+    /// it still has to go through rather than borrowing it.
+    pub fn cursor(&self) -> Cursor<K, V> {
+        let mut cursor = Cursor {
+            stack: Vec::new()
+        };
+        cursor.push_branch(&self.root);
+        cursor
+    }
+
+    pub fn iter_rev(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            ascending: false
+        };
+        iter.push_branch(&self.root);
+        iter
+    }
+
+    /// Iterates starting at `key`, in ascending or descending order. The first entry is the
+    /// closest one to `key` in the direction of iteration, which includes `key` itself only
+    /// if `inclusive` is set.
+    pub fn iter_from<E>(
+        &self,
+        key: &K,
+        inclusive: bool,
+        ascending: bool,
+        compare: &mut dyn FnMut(&K, &K) -> Result<Ordering, E>
+    ) -> Result<Iter<'_, K, V>, E> {
+        let mut stack = Vec::new();
+        let mut link = &self.root;
+        while let Some(node) = link {
+            let ordering = compare(&node.key, key)?;
+            let candidate = match ordering {
+                Ordering::Equal => inclusive,
+                Ordering::Greater => ascending,
+                Ordering::Less => !ascending
+            };
+            if candidate {
+                stack.push(node.as_ref());
+                link = if ascending { &node.left } else { &node.right };
+            } else {
+                link = if ascending { &node.right } else { &node.left };
+            }
+        }
+        Ok(Iter {
+            stack,
+            ascending
+        })
+    }
+
+}
+
+fn insert<K: Clone, V: Clone, E>(
+    link: &Link<K, V>,
+    key: K,
+    value: V,
+    compare: &mut dyn FnMut(&K, &K) -> Result<Ordering, E>
+) -> Result<(Rc<TreeNode<K, V>>, bool), E> {
+    match link {
+        None => Ok((make(key, value, None, None), true)),
+        Some(node) => match compare(&key, &node.key)? {
+            Ordering::Less => {
+                let (left, added) = insert(&node.left, key, value, compare)?;
+                Ok((balance(node.key.clone(), node.value.clone(), Some(left), node.right.clone()), added))
+            },
+            Ordering::Greater => {
+                let (right, added) = insert(&node.right, key, value, compare)?;
+                Ok((balance(node.key.clone(), node.value.clone(), node.left.clone(), Some(right)), added))
+            },
+            Ordering::Equal => {
+                Ok((make(node.key.clone(), value, node.left.clone(), node.right.clone()), false))
+            }
+        }
+    }
+}
+
+/// Removes `key` below `link`. Returns `None` if the key wasn't found.
+fn remove<K: Clone, V: Clone, E>(
+    link: &Link<K, V>,
+    key: &K,
+    compare: &mut dyn FnMut(&K, &K) -> Result<Ordering, E>
+) -> Result<Option<Link<K, V>>, E> {
+    let node = match link {
+        Some(node) => node,
+        None => return Ok(None)
+    };
+    let removed = match compare(key, &node.key)? {
+        Ordering::Less => remove(&node.left, key, compare)?.map(|left| {
+            balance(node.key.clone(), node.value.clone(), left, node.right.clone())
+        }),
+        Ordering::Greater => remove(&node.right, key, compare)?.map(|right| {
+            balance(node.key.clone(), node.value.clone(), node.left.clone(), right)
+        }),
+        Ordering::Equal => {
+            return Ok(Some(match (&node.left, &node.right) {
+                (None, right) => right.clone(),
+                (left, None) => left.clone(),
+                (left, Some(right)) => {
+                    let (key, value, right) = remove_min(right);
+                    Some(balance(key, value, left.clone(), right))
+                }
+            }));
+        }
+    };
+    Ok(removed.map(Some))
+}
+
+fn remove_min<K: Clone, V: Clone>(node: &Rc<TreeNode<K, V>>) -> (K, V, Link<K, V>) {
+    match &node.left {
+        None => (node.key.clone(), node.value.clone(), node.right.clone()),
+        Some(left) => {
+            let (key, value, left) = remove_min(left);
+            (key, value, Some(balance(node.key.clone(), node.value.clone(), left, node.right.clone())))
+        }
+    }
+}
+
+fn replace_min_value<K: Clone, V: Clone>(node: &Rc<TreeNode<K, V>>, value: V) -> Rc<TreeNode<K, V>> {
+    match &node.left {
+        None => make(node.key.clone(), value, None, node.right.clone()),
+        Some(left) => make(node.key.clone(), node.value.clone(), Some(replace_min_value(left, value)), node.right.clone())
+    }
+}
+
+impl<K: Clone, V: Clone> Default for PersistentTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a TreeNode<K, V>>,
+    ascending: bool
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_branch(&mut self, mut link: &'a Link<K, V>) {
+        while let Some(node) = link {
+            self.stack.push(node);
+            link = if self.ascending { &node.left } else { &node.right };
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_branch(if self.ascending { &node.right } else { &node.left });
+        Some((&node.key, &node.value))
+    }
+}
+
+/// Owned iterator over a map, see `PersistentTreeMap::cursor`. Cloning it is cheap, and the
+/// clone goes on from the same entry.
+pub struct Cursor<K, V> {
+    stack: Vec<Rc<TreeNode<K, V>>>
+}
+
+impl<K, V> Clone for Cursor<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            stack: self.stack.clone()
+        }
+    }
+}
+
+impl<K, V> Cursor<K, V> {
+    fn push_branch(&mut self, mut link: &Link<K, V>) {
+        while let Some(node) = link {
+            self.stack.push(node.clone());
+            link = &node.left;
+        }
+    }
+}
+
+impl<K: Clone, V: Clone> Iterator for Cursor<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_branch(&node.right);
+        Some((node.key.clone(), node.value.clone()))
+    }
+}
+
+/// Persistent sorted set, a `PersistentTreeMap` whose values are all `()`.
+#[derive(Debug, Clone)]
+pub struct PersistentTreeSet<T> {
+    map: PersistentTreeMap<T, ()>
+}
+
+impl<T: Clone> PersistentTreeSet<T> {
+
+    pub fn new() -> Self {
+        Self {
+            map: PersistentTreeMap::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn get<E>(&self, value: &T, compare: &mut dyn FnMut(&T, &T) -> Result<Ordering, E>) -> Result<Option<&T>, E> {
+        Ok(self.map.get(value, compare)?.map(|(value, _)| value))
+    }
+
+    pub fn conj<E>(&self, value: T, compare: &mut dyn FnMut(&T, &T) -> Result<Ordering, E>) -> Result<Self, E> {
+        Ok(Self {
+            map: self.map.assoc(value, (), compare)?
+        })
+    }
+
+    pub fn disj<E>(&self, value: &T, compare: &mut dyn FnMut(&T, &T) -> Result<Ordering, E>) -> Result<Self, E> {
+        Ok(Self {
+            map: self.map.dissoc(value, compare)?
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.map.iter().map(|(value, _)| value)
+    }
+
+    pub fn iter_rev(&self) -> impl Iterator<Item = &T> {
+        self.map.iter_rev().map(|(value, _)| value)
+    }
+
+    /// Owned iterator over clones of the elements, see `PersistentTreeMap::cursor`.
+    pub fn cursor(&self) -> impl Iterator<Item = T> + Clone {
+        self.map.cursor().map(|(value, _)| value)
+    }
+
+    pub fn iter_from<E>(
+        &self,
+        value: &T,
+        inclusive: bool,
+        ascending: bool,
+        compare: &mut dyn FnMut(&T, &T) -> Result<Ordering, E>
+    ) -> Result<impl Iterator<Item = &T>, E> {
+        Ok(self.map.iter_from(value, inclusive, ascending, compare)?.map(|(value, _)| value))
+    }
+
+}
+
+impl<T: Clone> Default for PersistentTreeSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(a: &u32, b: &u32) -> Result<Ordering, ()> {
+        Ok(a.cmp(b))
+    }
+
+    /// Checks the heights and balance of every node, and returns the height of the tree.
+    fn check_balance(link: &Link<u32, u32>) -> usize {
+        match link {
+            None => 0,
+            Some(node) => {
+                let (left, right) = (check_balance(&node.left), check_balance(&node.right));
+                assert!(left.max(right) - left.min(right) <= 1, "unbalanced at {}", node.key);
+                assert_eq!(node.height, 1 + left.max(right));
+                node.height
+            }
+        }
+    }
+
+    fn build(keys: impl Iterator<Item = u32>) -> PersistentTreeMap<u32, u32> {
+        keys.fold(PersistentTreeMap::new(), |map, key| map.assoc(key, key * 2, &mut compare).unwrap())
+    }
+
+    /// Scrambles 0..1000 with a multiplier coprime to 1000.
+    fn scrambled() -> impl Iterator<Item = u32> {
+        (0..1000).map(|index| index * 337 % 1000)
+    }
+
+    #[test]
+    fn stays_balanced_and_sorted() {
+        for map in [build(0..1000), build((0..1000).rev()), build(scrambled())] {
+            assert_eq!(map.len(), 1000);
+            // An AVL tree of 1000 nodes is at most 1.44 log2(1000) high.
+            assert!(check_balance(&map.root) <= 14);
+            assert!(map.iter().map(|(&key, _)| key).eq(0..1000));
+            assert!(map.iter_rev().map(|(&key, _)| key).eq((0..1000).rev()));
+            assert!(map.cursor().map(|(key, _)| key).eq(0..1000));
+        }
+    }
+
+    #[test]
+    fn stays_balanced_through_removals() {
+        let full = build(scrambled());
+        let mut map = full.clone();
+        for key in (0..1000).filter(|key| key % 3 != 0) {
+            map = map.dissoc(&key, &mut compare).unwrap();
+            check_balance(&map.root);
+        }
+        assert_eq!(map.len(), 334);
+        assert!(map.iter().map(|(&key, _)| key).eq((0..1000).filter(|key| key % 3 == 0)));
+        assert_eq!(map.dissoc(&1, &mut compare).unwrap().len(), 334);
+        assert_eq!(full.len(), 1000);
+        assert_eq!(full.get(&1, &mut compare).unwrap(), Some((&1, &2)));
+    }
+
+    #[test]
+    fn iteration_starts_at_bounds() {
+        let map = build((0..100).map(|key| key * 2));
+        let from = |key, inclusive, ascending| -> Vec<u32> {
+            map.iter_from(&key, inclusive, ascending, &mut compare).unwrap().map(|(&key, _)| key).take(3).collect()
+        };
+        assert_eq!(from(10, true, true), [10, 12, 14]);
+        assert_eq!(from(10, false, true), [12, 14, 16]);
+        assert_eq!(from(11, true, true), [12, 14, 16]);
+        assert_eq!(from(10, true, false), [10, 8, 6]);
+        assert_eq!(from(10, false, false), [8, 6, 4]);
+        assert_eq!(from(11, false, false), [10, 8, 6]);
+        assert_eq!(from(0, false, false), [] as [u32; 0]);
+        assert_eq!(from(198, false, true), [] as [u32; 0]);
+        assert_eq!(from(500, true, false), [198, 196, 194]);
+    }
+
+    #[test]
+    fn comparator_errors_are_passed_back() {
+        let map = build(0..10);
+        let mut failing = |_: &u32, _: &u32| -> Result<Ordering, &str> { Err("no") };
+        assert_eq!(map.assoc(3, 3, &mut failing).err(), Some("no"));
+        assert_eq!(map.get(&3, &mut failing).err(), Some("no"));
+    }
+}
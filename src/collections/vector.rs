@@ -0,0 +1,432 @@
+use std::rc::Rc;
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Debug, Clone)]
+enum TrieNode<T> {
+    Branch(Vec<Rc<TrieNode<T>>>),
+    Leaf(Vec<T>)
+}
+
+/// Persistent vector implemented as a 32-way bit-partitioned trie with a tail buffer.
+///
+/// Updates copy only the path from the root to the modified leaf, so every version shares
+/// most of its structure with the one it was derived from. Mutating methods (`*_mut`) use
+/// copy-on-write, which makes them update in place whenever a node is not shared.
+///
+/// A vector can also be a window (`start..end`) over a larger trie, which is what makes
+/// `subvec` constant time.
+#[derive(Debug, Clone)]
+pub struct PersistentVector<T> {
+    count: usize,
+    shift: u32,
+    root: Rc<TrieNode<T>>,
+    tail: Rc<Vec<T>>,
+    start: usize,
+    end: usize
+}
+
+impl<T: Clone> PersistentVector<T> {
+
+    pub fn new() -> Self {
+        Self {
+            count: 0,
+            shift: BITS,
+            root: Rc::new(TrieNode::Branch(Vec::new())),
+            tail: Rc::new(Vec::new()),
+            start: 0,
+            end: 0
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index < self.len() {
+            let index = self.start + index;
+            Some(&self.chunk_for(index)[index & MASK])
+        } else {
+            None
+        }
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        if self.is_empty() {
+            None
+        } else {
+            self.get(self.len() - 1)
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            vector: self,
+            index: self.start,
+            end: self.end,
+            chunk: &[],
+            chunk_start: 0
+        }
+    }
+
+    /// Returns a new vector with `value` appended at the end.
+    pub fn conj(&self, value: T) -> Self {
+        let mut vector = self.clone();
+        vector.push_mut(value);
+        vector
+    }
+
+    /// Returns a new vector with the element at `index` replaced. An `index` equal to the
+    /// length appends, like `conj`. Returns `None` if `index` is out of bounds.
+    pub fn assoc(&self, index: usize, value: T) -> Option<Self> {
+        let mut vector = self.clone();
+        if vector.set_mut(index, value) {
+            Some(vector)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a new vector without its last element, or `None` if it is empty.
+    pub fn pop(&self) -> Option<Self> {
+        let mut vector = self.clone();
+        if vector.pop_mut() {
+            Some(vector)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the elements in `start..end` as a vector sharing this vector's trie.
+    pub fn subvec(&self, start: usize, end: usize) -> Option<Self> {
+        if start <= end && end <= self.len() {
+            let mut vector = self.clone();
+            vector.end = self.start + end;
+            vector.start = self.start + start;
+            Some(vector)
+        } else {
+            None
+        }
+    }
+
+    pub fn push_mut(&mut self, value: T) {
+        if self.end < self.count {
+            // A window over a larger trie overwrites the element past its end.
+            self.trie_set(self.end, value);
+        } else {
+            self.trie_push(value);
+        }
+        self.end += 1;
+    }
+
+    pub fn set_mut(&mut self, index: usize, value: T) -> bool {
+        if index < self.len() {
+            self.trie_set(self.start + index, value);
+            true
+        } else if index == self.len() {
+            self.push_mut(value);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn pop_mut(&mut self) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        if self.start == 0 && self.end == self.count {
+            self.trie_pop();
+        }
+        self.end -= 1;
+        true
+    }
+
+    fn tail_offset(&self) -> usize {
+        if self.count < WIDTH {
+            0
+        } else {
+            ((self.count - 1) >> BITS) << BITS
+        }
+    }
+
+    fn chunk_for(&self, index: usize) -> &[T] {
+        if index >= self.tail_offset() {
+            return &self.tail;
+        }
+        let mut node = self.root.as_ref();
+        let mut level = self.shift;
+        loop {
+            match node {
+                TrieNode::Branch(children) => {
+                    node = &children[(index >> level) & MASK];
+                    level -= BITS;
+                },
+                TrieNode::Leaf(values) => return values
+            }
+        }
+    }
+
+    fn trie_set(&mut self, index: usize, value: T) {
+        if index >= self.tail_offset() {
+            Rc::make_mut(&mut self.tail)[index & MASK] = value;
+            return;
+        }
+        let mut node = Rc::make_mut(&mut self.root);
+        let mut level = self.shift;
+        loop {
+            match node {
+                TrieNode::Branch(children) => {
+                    node = Rc::make_mut(&mut children[(index >> level) & MASK]);
+                    level -= BITS;
+                },
+                TrieNode::Leaf(values) => {
+                    values[index & MASK] = value;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn trie_push(&mut self, value: T) {
+        if self.count - self.tail_offset() < WIDTH {
+            Rc::make_mut(&mut self.tail).push(value);
+            self.count += 1;
+            return;
+        }
+
+        let tail = std::mem::replace(&mut self.tail, Rc::new(vec![value]));
+        let tail = Rc::new(TrieNode::Leaf(Rc::try_unwrap(tail).unwrap_or_else(|tail| (*tail).clone())));
+
+        if (self.count >> BITS) > (1 << self.shift) {
+            // The root is full, grow the trie by one level.
+            let path = new_path(self.shift, tail);
+            let root = std::mem::replace(&mut self.root, Rc::new(TrieNode::Branch(Vec::new())));
+            self.root = Rc::new(TrieNode::Branch(vec![root, path]));
+            self.shift += BITS;
+        } else {
+            push_tail(Rc::make_mut(&mut self.root), self.shift, self.count, tail);
+        }
+        self.count += 1;
+    }
+
+    fn trie_pop(&mut self) {
+        if self.count == 1 {
+            self.root = Rc::new(TrieNode::Branch(Vec::new()));
+            self.tail = Rc::new(Vec::new());
+            self.shift = BITS;
+            self.count = 0;
+            return;
+        }
+        if self.count - self.tail_offset() > 1 {
+            Rc::make_mut(&mut self.tail).pop();
+            self.count -= 1;
+            return;
+        }
+
+        let tail = self.chunk_for(self.count - 2).to_vec();
+        if !pop_tail(Rc::make_mut(&mut self.root), self.shift, self.count) {
+            self.root = Rc::new(TrieNode::Branch(Vec::new()));
+        }
+        if self.shift > BITS {
+            if let TrieNode::Branch(children) = self.root.as_ref() {
+                if children.len() == 1 {
+                    self.root = children[0].clone();
+                    self.shift -= BITS;
+                }
+            }
+        }
+        self.tail = Rc::new(tail);
+        self.count -= 1;
+    }
+
+}
+
+fn new_path<T>(level: u32, node: Rc<TrieNode<T>>) -> Rc<TrieNode<T>> {
+    if level == 0 {
+        node
+    } else {
+        Rc::new(TrieNode::Branch(vec![new_path(level - BITS, node)]))
+    }
+}
+
+fn push_tail<T: Clone>(parent: &mut TrieNode<T>, level: u32, count: usize, tail: Rc<TrieNode<T>>) {
+    let index = ((count - 1) >> level) & MASK;
+    if let TrieNode::Branch(children) = parent {
+        if level == BITS {
+            children.push(tail);
+        } else if index < children.len() {
+            push_tail(Rc::make_mut(&mut children[index]), level - BITS, count, tail);
+        } else {
+            children.push(new_path(level - BITS, tail));
+        }
+    }
+}
+
+/// Removes the rightmost leaf below `node`. Returns `false` if `node` is left empty.
+fn pop_tail<T: Clone>(node: &mut TrieNode<T>, level: u32, count: usize) -> bool {
+    let index = ((count - 2) >> level) & MASK;
+    if let TrieNode::Branch(children) = node {
+        let child_emptied = level > BITS && !pop_tail(Rc::make_mut(&mut children[index]), level - BITS, count);
+        if level == BITS || child_emptied {
+            children.truncate(index);
+        }
+        !children.is_empty()
+    } else {
+        true
+    }
+}
+
+impl<T: Clone> Default for PersistentVector<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> std::iter::FromIterator<T> for PersistentVector<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vector = Self::new();
+        for value in iter {
+            vector.push_mut(value);
+        }
+        vector
+    }
+}
+
+impl<'a, T: Clone> IntoIterator for &'a PersistentVector<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a, T> {
+    vector: &'a PersistentVector<T>,
+    index: usize,
+    end: usize,
+    chunk: &'a [T],
+    chunk_start: usize
+}
+
+impl<'a, T: Clone> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.end {
+            return None;
+        }
+        if self.index - self.chunk_start >= self.chunk.len() {
+            self.chunk = self.vector.chunk_for(self.index);
+            self.chunk_start = self.index & !MASK;
+        }
+        let value = &self.chunk[self.index - self.chunk_start];
+        self.index += 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.end - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(len: usize) -> PersistentVector<usize> {
+        (0..len).collect()
+    }
+
+    fn check(vector: &PersistentVector<usize>, len: usize) {
+        assert_eq!(vector.len(), len);
+        assert!(vector.iter().copied().eq(0..len));
+        assert!((0..len).all(|index| vector.get(index) == Some(&index)));
+        assert_eq!(vector.get(len), None);
+    }
+
+    #[test]
+    fn conj_fills_the_tail_then_the_trie() {
+        // The tail holds 32; a root of depth one adds 1024 more and one of depth two 32768.
+        for len in [0, 1, 31, 32, 33, 64, 65, 1055, 1056, 1057, 32800, 32801] {
+            let vector = range(len);
+            check(&vector, len);
+            let expected_shift = if len > 32800 { 3 * BITS } else if len > 1056 { 2 * BITS } else { BITS };
+            assert_eq!(vector.shift, expected_shift, "shift of a vector of {}", len);
+        }
+    }
+
+    #[test]
+    fn tail_is_promoted_into_the_trie() {
+        let full = range(32);
+        assert_eq!(full.tail.len(), 32);
+        let promoted = full.conj(32);
+        assert_eq!(promoted.tail.len(), 1);
+        assert_eq!(promoted.tail_offset(), 32);
+        check(&promoted, 33);
+        check(&full, 32);
+    }
+
+    #[test]
+    fn pop_shrinks_across_levels() {
+        let mut vector = range(1057);
+        assert_eq!(vector.shift, 2 * BITS);
+        for len in (0..1057).rev() {
+            vector = vector.pop().unwrap();
+            assert_eq!(vector.len(), len);
+            assert_eq!(vector.last(), len.checked_sub(1).as_ref());
+            if len == 1056 {
+                assert_eq!(vector.shift, BITS);
+                check(&vector, len);
+            }
+            if len % 97 == 0 || len == 32 || len == 33 {
+                check(&vector, len);
+            }
+        }
+        assert!(vector.pop().is_none());
+        // A vector emptied by pops grows again like a new one.
+        check(&vector.conj(0).conj(1), 2);
+    }
+
+    #[test]
+    fn versions_share_structure_without_changing() {
+        let original = range(2000);
+        let changed = original.assoc(5, 500).unwrap().assoc(1990, 19900).unwrap();
+        check(&original, 2000);
+        assert_eq!(changed.get(5), Some(&500));
+        assert_eq!(changed.get(1990), Some(&19900));
+        assert!(!Rc::ptr_eq(&original.root, &changed.root));
+        assert!(Rc::ptr_eq(&original.tail, &original.assoc(5, 500).unwrap().tail));
+        assert_eq!(original.assoc(2000, 2000).map(|vector| vector.len()), Some(2001));
+        assert!(original.assoc(2001, 0).is_none());
+    }
+
+    #[test]
+    fn subvec_is_a_window_over_the_same_trie() {
+        let original = range(100);
+        let window = original.subvec(10, 20).unwrap();
+        assert!(Rc::ptr_eq(&original.root, &window.root));
+        assert!(window.iter().copied().eq(10..20));
+        assert_eq!(window.get(10), None);
+        // Growing the window overwrites a copy of the elements past its end.
+        let grown = window.conj(1000);
+        assert_eq!(grown.get(10), Some(&1000));
+        check(&original, 100);
+        assert!(window.pop().unwrap().iter().copied().eq(10..19));
+        assert!(original.subvec(5, 4).is_none());
+        assert!(original.subvec(0, 101).is_none());
+    }
+}
@@ -0,0 +1,649 @@
+use crate::nodes::{NodePtr, Node, IntoListIter, list_from, map_entry};
+use crate::intern::{Symbol, SymbolMap};
+use crate::eval::{EvalResult, EvalError, seq::{self, SeqIter}};
+use crate::context::EvalContext;
+use crate::collections::{PersistentHashMap, PersistentTreeMap, PersistentTreeSet, PersistentQueue, PersistentPriorityQueue};
+use std::rc::Rc;
+
+use super::{Builtin, arguments, index_arg, elements};
+use super::transients::read_transient;
+use super::compare::compare_with;
+
+fn vector(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    Ok(Rc::new(Node::Vector(args.list_elements()?.into_iter().collect())))
+}
+
+fn vec(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("vec", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Vector(_) => Ok(args[0].clone()),
+        Node::Transient(_) => Err(EvalError::type_error(&format!("vec: cannot create a vector from {}", args[0]))),
+        _ => Ok(Rc::new(Node::Vector(seq::collect(context, &args[0])?.into_iter().collect())))
+    }
+}
+
+fn hash_map(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = args.list_elements()?;
+    if args.len() % 2 != 0 {
+        return Err(EvalError::other("hash-map: expected an even number of keys and values"));
+    }
+    let map = args.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+    Ok(Rc::new(Node::Map(map)))
+}
+
+fn hash_set(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    Ok(Rc::new(Node::Set(args.list_elements()?.into_iter().collect())))
+}
+
+fn set(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("set", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Set(_) => Ok(args[0].clone()),
+        Node::Transient(_) => Err(EvalError::type_error(&format!("set: cannot create a set from {}", args[0]))),
+        _ => Ok(Rc::new(Node::Set(seq::collect(context, &args[0])?.into_iter().collect())))
+    }
+}
+
+/// Adds a `[key value]` vector or all the entries of a map to `map`.
+fn conj_map_entry(map: &PersistentHashMap<NodePtr, NodePtr>, entry: &NodePtr) -> Result<PersistentHashMap<NodePtr, NodePtr>, EvalError> {
+    match entry.as_ref() {
+        Node::Vector(vec) if vec.len() == 2 => {
+            Ok(map.assoc(vec.get(0).unwrap().clone(), vec.get(1).unwrap().clone()))
+        },
+        Node::Map(entries) => {
+            let mut map = map.clone();
+            for (key, value) in entries.iter() {
+                map.insert_mut(key.clone(), value.clone());
+            }
+            Ok(map)
+        },
+        Node::Nil => Ok(map.clone()),
+        node => Err(EvalError::type_error(&format!("conj: expected a [key value] vector but got {}", node)))
+    }
+}
+
+fn conj(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("conj", args, 1, None)?;
+    let mut coll = args[0].clone();
+    for value in &args[1..] {
+        coll = match coll.as_ref() {
+            Node::Vector(vec) => Rc::new(Node::Vector(vec.conj(value.clone()))),
+            Node::Seq(vec) if vec.is_empty() => seq::cons(value.clone(), Rc::new(Node::Nil)),
+            Node::Nil | Node::List(_, _) | Node::Seq(_) | Node::LazySeq(_) => seq::cons(value.clone(), coll.clone()),
+            Node::Set(set) => Rc::new(Node::Set(set.conj(value.clone()))),
+            Node::Map(map) => Rc::new(Node::Map(conj_map_entry(map, value)?)),
+            Node::SortedSet(set, comparator) => {
+                let set = set.conj(value.clone(), &mut |a, b| compare_with(context, comparator, a, b))?;
+                Rc::new(Node::SortedSet(set, comparator.clone()))
+            },
+            Node::SortedMap(map, comparator) => {
+                let (key, value) = match value.as_ref() {
+                    Node::Vector(vec) if vec.len() == 2 => (vec.get(0).unwrap().clone(), vec.get(1).unwrap().clone()),
+                    node => return Err(EvalError::type_error(&format!("conj: expected a [key value] vector but got {}", node)))
+                };
+                let map = map.assoc(key, value, &mut |a, b| compare_with(context, comparator, a, b))?;
+                Rc::new(Node::SortedMap(map, comparator.clone()))
+            },
+            Node::Queue(queue) => Rc::new(Node::Queue(queue.conj(value.clone()))),
+            Node::PriorityQueue(queue, comparator) => {
+                let queue = queue.conj(value.clone(), &mut |a, b| compare_with(context, comparator, a, b))?;
+                Rc::new(Node::PriorityQueue(queue, comparator.clone()))
+            },
+            node => return Err(EvalError::type_error(&format!("conj: cannot add elements to {}", node)))
+        };
+    }
+    Ok(coll)
+}
+
+fn nth(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let mut args = arguments("nth", args, 2, Some(3))?;
+    args[0] = read_transient("nth", &args[0])?;
+    let index = index_arg("nth", &args[1])?;
+    let found = match args[0].as_ref() {
+        Node::Vector(vec) | Node::Seq(vec) => vec.get(index).cloned(),
+        Node::Nil | Node::List(_, _) | Node::LazySeq(_) => {
+            let mut iter = SeqIter::new(&args[0]);
+            let mut found = None;
+            for _ in 0..=index {
+                found = iter.next(context)?;
+                if found.is_none() {
+                    break;
+                }
+            }
+            found
+        },
+        node => return Err(EvalError::type_error(&format!("nth: not supported on {}", node)))
+    };
+    match (found, args.get(2)) {
+        (Some(found), _) => Ok(found),
+        (None, Some(default)) => Ok(default.clone()),
+        (None, None) => Err(EvalError::out_of_bounds(&format!("nth: index {} out of bounds", index)))
+    }
+}
+
+fn get(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let mut args = arguments("get", args, 2, Some(3))?;
+    args[0] = read_transient("get", &args[0])?;
+    let found = match (args[0].as_ref(), args[1].as_ref()) {
+        (Node::Vector(vec), Node::Integer(index)) if *index >= 0 => vec.get(*index as usize).cloned(),
+        (Node::Map(map), _) => map.get(&args[1]).cloned(),
+        (Node::Set(set), _) => set.get(&args[1]).cloned(),
+        (Node::SortedMap(map, comparator), _) => {
+            map.get(&args[1], &mut |a, b| compare_with(context, comparator, a, b))?.map(|(_, value)| value.clone())
+        },
+        (Node::SortedSet(set, comparator), _) => {
+            set.get(&args[1], &mut |a, b| compare_with(context, comparator, a, b))?.cloned()
+        },
+        _ => None
+    };
+    Ok(found.or_else(|| args.get(2).cloned()).unwrap_or_else(|| Rc::new(Node::Nil)))
+}
+
+fn assoc(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("assoc", args, 3, None)?;
+    if args.len() % 2 == 0 {
+        return Err(EvalError::other("assoc: expected an even number of keys and values"));
+    }
+    let mut coll = args[0].clone();
+    for pair in args[1..].chunks(2) {
+        coll = match coll.as_ref() {
+            Node::Vector(vec) => {
+                let index = index_arg("assoc", &pair[0])?;
+                match vec.assoc(index, pair[1].clone()) {
+                    Some(vec) => Rc::new(Node::Vector(vec)),
+                    None => return Err(EvalError::out_of_bounds(&format!("assoc: index {} out of bounds", index)))
+                }
+            },
+            Node::Map(map) => Rc::new(Node::Map(map.assoc(pair[0].clone(), pair[1].clone()))),
+            Node::SortedMap(map, comparator) => {
+                let map = map.assoc(pair[0].clone(), pair[1].clone(), &mut |a, b| compare_with(context, comparator, a, b))?;
+                Rc::new(Node::SortedMap(map, comparator.clone()))
+            },
+            Node::Nil => Rc::new(Node::Map(PersistentHashMap::new().assoc(pair[0].clone(), pair[1].clone()))),
+            node => return Err(EvalError::type_error(&format!("assoc: not supported on {}", node)))
+        };
+    }
+    Ok(coll)
+}
+
+fn dissoc(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("dissoc", args, 1, None)?;
+    match args[0].as_ref() {
+        Node::Map(map) => {
+            let mut map = map.clone();
+            for key in &args[1..] {
+                map.remove_mut(key);
+            }
+            Ok(Rc::new(Node::Map(map)))
+        },
+        Node::SortedMap(map, comparator) => {
+            let mut map = map.clone();
+            for key in &args[1..] {
+                map = map.dissoc(key, &mut |a, b| compare_with(context, comparator, a, b))?;
+            }
+            Ok(Rc::new(Node::SortedMap(map, comparator.clone())))
+        },
+        Node::Nil => Ok(args[0].clone()),
+        node => Err(EvalError::type_error(&format!("dissoc: not supported on {}", node)))
+    }
+}
+
+fn disj(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("disj", args, 1, None)?;
+    match args[0].as_ref() {
+        Node::Set(set) => {
+            let mut set = set.clone();
+            for value in &args[1..] {
+                set.remove_mut(value);
+            }
+            Ok(Rc::new(Node::Set(set)))
+        },
+        Node::SortedSet(set, comparator) => {
+            let mut set = set.clone();
+            for value in &args[1..] {
+                set = set.disj(value, &mut |a, b| compare_with(context, comparator, a, b))?;
+            }
+            Ok(Rc::new(Node::SortedSet(set, comparator.clone())))
+        },
+        Node::Nil => Ok(args[0].clone()),
+        node => Err(EvalError::type_error(&format!("disj: not supported on {}", node)))
+    }
+}
+
+fn contains(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let mut args = arguments("contains?", args, 2, Some(2))?;
+    args[0] = read_transient("contains?", &args[0])?;
+    let contains = match (args[0].as_ref(), args[1].as_ref()) {
+        (Node::Map(map), _) => map.contains_key(&args[1]),
+        (Node::Set(set), _) => set.contains(&args[1]),
+        (Node::SortedMap(map, comparator), _) => {
+            map.get(&args[1], &mut |a, b| compare_with(context, comparator, a, b))?.is_some()
+        },
+        (Node::SortedSet(set, comparator), _) => {
+            set.get(&args[1], &mut |a, b| compare_with(context, comparator, a, b))?.is_some()
+        },
+        (Node::Vector(vec), Node::Integer(index)) => *index >= 0 && (*index as usize) < vec.len(),
+        (Node::Vector(_), _) | (Node::Nil, _) => false,
+        (node, _) => return Err(EvalError::type_error(&format!("contains?: not supported on {}", node)))
+    };
+    Ok(Rc::new(Node::Bool(contains)))
+}
+
+fn keys(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("keys", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Map(map) => Ok(list_from(map.keys().cloned().collect::<Vec<NodePtr>>())),
+        Node::SortedMap(map, _) => Ok(list_from(map.iter().map(|(key, _)| key.clone()).collect::<Vec<NodePtr>>())),
+        Node::Nil => Ok(args[0].clone()),
+        node => Err(EvalError::type_error(&format!("keys: not supported on {}", node)))
+    }
+}
+
+fn vals(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("vals", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Map(map) => Ok(list_from(map.values().cloned().collect::<Vec<NodePtr>>())),
+        Node::SortedMap(map, _) => Ok(list_from(map.iter().map(|(_, value)| value.clone()).collect::<Vec<NodePtr>>())),
+        Node::Nil => Ok(args[0].clone()),
+        node => Err(EvalError::type_error(&format!("vals: not supported on {}", node)))
+    }
+}
+
+fn pop(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("pop", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Vector(vec) => match vec.pop() {
+            Some(vec) => Ok(Rc::new(Node::Vector(vec))),
+            None => Err(EvalError::out_of_bounds("pop: can't pop an empty vector"))
+        },
+        Node::List(_, right) => Ok(right.clone()),
+        Node::Nil => Err(EvalError::out_of_bounds("pop: can't pop an empty list")),
+        Node::Queue(queue) => Ok(Rc::new(Node::Queue(queue.pop()))),
+        Node::PriorityQueue(queue, comparator) => Ok(Rc::new(Node::PriorityQueue(queue.pop(), comparator.clone()))),
+        node => Err(EvalError::type_error(&format!("pop: not supported on {}", node)))
+    }
+}
+
+fn peek(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("peek", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Vector(vec) => Ok(vec.last().cloned().unwrap_or_else(|| Rc::new(Node::Nil))),
+        Node::List(left, _) => Ok(left.clone()),
+        Node::Nil => Ok(args[0].clone()),
+        Node::Queue(queue) => Ok(queue.peek().cloned().unwrap_or_else(|| Rc::new(Node::Nil))),
+        Node::PriorityQueue(queue, _) => Ok(queue.peek().cloned().unwrap_or_else(|| Rc::new(Node::Nil))),
+        node => Err(EvalError::type_error(&format!("peek: not supported on {}", node)))
+    }
+}
+
+fn subvec(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("subvec", args, 2, Some(3))?;
+    if let Node::Vector(vec) = args[0].as_ref() {
+        let start = index_arg("subvec", &args[1])?;
+        let end = match args.get(2) {
+            Some(end) => index_arg("subvec", end)?,
+            None => vec.len()
+        };
+        match vec.subvec(start, end) {
+            Some(vec) => Ok(Rc::new(Node::Vector(vec))),
+            None => Err(EvalError::out_of_bounds(&format!("subvec: range {}..{} out of bounds", start, end)))
+        }
+    } else {
+        Err(EvalError::type_error(&format!("subvec: expected a vector but got {}", args[0])))
+    }
+}
+
+fn count(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let mut args = arguments("count", args, 1, Some(1))?;
+    args[0] = read_transient("count", &args[0])?;
+    match (args[0].len(), args[0].as_ref()) {
+        (Some(len), _) => Ok(Rc::new(Node::Integer(len as i64))),
+        (None, Node::List(_, _) | Node::LazySeq(_)) => {
+            let mut iter = SeqIter::new(&args[0]);
+            let mut len = 0;
+            while iter.next(context)?.is_some() {
+                len += 1;
+            }
+            Ok(Rc::new(Node::Integer(len)))
+        },
+        (None, _) => Err(EvalError::type_error(&format!("count: not supported on {}", args[0])))
+    }
+}
+
+fn empty(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("empty", args, 1, Some(1))?;
+    let empty = match args[0].as_ref() {
+        Node::List(_, _) | Node::Seq(_) | Node::LazySeq(_) | Node::Nil => Node::Nil,
+        Node::Vector(_) => Node::Vector(Default::default()),
+        Node::Set(_) => Node::Set(Default::default()),
+        Node::Map(_) => Node::Map(Default::default()),
+        Node::SortedSet(_, comparator) => Node::SortedSet(Default::default(), comparator.clone()),
+        Node::SortedMap(_, comparator) => Node::SortedMap(Default::default(), comparator.clone()),
+        Node::Queue(_) => Node::Queue(Default::default()),
+        Node::PriorityQueue(_, comparator) => Node::PriorityQueue(Default::default(), comparator.clone()),
+        _ => Node::Nil
+    };
+    Ok(Rc::new(empty))
+}
+
+fn into(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("into", args, 0, Some(2))?;
+    match args.len() {
+        0 => Ok(Rc::new(Node::Vector(Default::default()))),
+        1 => Ok(args[0].clone()),
+        _ => {
+            let mut values = elements(context, "into", &args[1])?;
+            // Hash collections and vectors are filled in place, the way a transient would.
+            match args[0].as_ref() {
+                Node::Vector(vec) => {
+                    let mut vec = vec.clone();
+                    for value in values {
+                        vec.push_mut(value);
+                    }
+                    Ok(Rc::new(Node::Vector(vec)))
+                },
+                Node::Set(set) => {
+                    let mut set = set.clone();
+                    for value in values {
+                        set.insert_mut(value);
+                    }
+                    Ok(Rc::new(Node::Set(set)))
+                },
+                Node::Map(map) => {
+                    let mut map = map.clone();
+                    for value in values {
+                        match value.as_ref() {
+                            Node::Vector(entry) if entry.len() == 2 => {
+                                map.insert_mut(entry.get(0).unwrap().clone(), entry.get(1).unwrap().clone());
+                            },
+                            _ => map = conj_map_entry(&map, &value)?
+                        }
+                    }
+                    Ok(Rc::new(Node::Map(map)))
+                },
+                _ => {
+                    values.insert(0, args[0].clone());
+                    conj(context, &list_from(values))
+                }
+            }
+        }
+    }
+}
+
+fn queue(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    Ok(Rc::new(Node::Queue(args.list_elements()?.into_iter().collect::<PersistentQueue<NodePtr>>())))
+}
+
+fn build_priority_queue(context: &mut EvalContext, comparator: Option<NodePtr>, args: &[NodePtr]) -> EvalResult {
+    let mut queue = PersistentPriorityQueue::new();
+    for value in args {
+        queue = queue.conj(value.clone(), &mut |a, b| compare_with(context, &comparator, a, b))?;
+    }
+    Ok(Rc::new(Node::PriorityQueue(queue, comparator)))
+}
+
+fn priority_queue(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = args.list_elements()?;
+    build_priority_queue(context, None, &args)
+}
+
+fn priority_queue_by(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("priority-queue-by", args, 1, None)?;
+    build_priority_queue(context, Some(args[0].clone()), &args[1..])
+}
+
+fn build_sorted_map(context: &mut EvalContext, name: &str, comparator: Option<NodePtr>, args: &[NodePtr]) -> EvalResult {
+    if !args.len().is_multiple_of(2) {
+        return Err(EvalError::other(&format!("{}: expected an even number of keys and values", name)));
+    }
+    let mut map = PersistentTreeMap::new();
+    for pair in args.chunks(2) {
+        map = map.assoc(pair[0].clone(), pair[1].clone(), &mut |a, b| compare_with(context, &comparator, a, b))?;
+    }
+    Ok(Rc::new(Node::SortedMap(map, comparator)))
+}
+
+fn build_sorted_set(context: &mut EvalContext, comparator: Option<NodePtr>, args: &[NodePtr]) -> EvalResult {
+    let mut set = PersistentTreeSet::new();
+    for value in args {
+        set = set.conj(value.clone(), &mut |a, b| compare_with(context, &comparator, a, b))?;
+    }
+    Ok(Rc::new(Node::SortedSet(set, comparator)))
+}
+
+fn sorted_map(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = args.list_elements()?;
+    build_sorted_map(context, "sorted-map", None, &args)
+}
+
+fn sorted_map_by(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("sorted-map-by", args, 1, None)?;
+    build_sorted_map(context, "sorted-map-by", Some(args[0].clone()), &args[1..])
+}
+
+fn sorted_set(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = args.list_elements()?;
+    build_sorted_set(context, None, &args)
+}
+
+fn sorted_set_by(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("sorted-set-by", args, 1, None)?;
+    build_sorted_set(context, Some(args[0].clone()), &args[1..])
+}
+
+/// Returns the name of a test passed to `subseq`/`rsubseq`, to tell which bound it is.
+fn test_name(test: &NodePtr) -> Option<Rc<str>> {
+    match test.as_ref() {
+        Node::Builtin(symbol, _) => Some(symbol.name()),
+        _ => None
+    }
+}
+
+/// Elements of a sorted collection starting at `key`, or all of them if there is no `key`.
+/// Maps produce `[key value]` vectors.
+fn sorted_elements<'a>(
+    context: &mut EvalContext,
+    coll: &'a Node,
+    start: Option<(&NodePtr, bool)>,
+    ascending: bool
+) -> Result<Box<dyn Iterator<Item = (NodePtr, NodePtr)> + 'a>, EvalError> {
+    match coll {
+        Node::SortedMap(map, comparator) => {
+            let entries = match start {
+                Some((key, inclusive)) => {
+                    map.iter_from(key, inclusive, ascending, &mut |a, b| compare_with(context, comparator, a, b))?
+                },
+                None if ascending => map.iter(),
+                None => map.iter_rev()
+            };
+            Ok(Box::new(entries.map(|(key, value)| {
+                (key.clone(), map_entry(key, value))
+            })))
+        },
+        Node::SortedSet(set, comparator) => {
+            let values: Box<dyn Iterator<Item = &NodePtr>> = match start {
+                Some((key, inclusive)) => {
+                    Box::new(set.iter_from(key, inclusive, ascending, &mut |a, b| compare_with(context, comparator, a, b))?)
+                },
+                None if ascending => Box::new(set.iter()),
+                None => Box::new(set.iter_rev())
+            };
+            Ok(Box::new(values.map(|value| (value.clone(), value.clone()))))
+        },
+        node => Err(EvalError::type_error(&format!("expected a sorted collection but got {}", node)))
+    }
+}
+
+/// Shared implementation of `subseq` and `rsubseq`.
+///
+/// Like in Clojure, an element is within a bound when `(test (compare element key) 0)` is
+/// true. Iteration seeks directly to the bound at its start, when there is one, and goes
+/// on while the elements are within the bound at its end.
+fn sorted_range(context: &mut EvalContext, name: &str, args: &NodePtr, ascending: bool) -> EvalResult {
+    let args = arguments(name, args, 3, Some(5))?;
+    if args.len() == 4 {
+        return Err(EvalError::arity(name, "3 or 5", 4));
+    }
+    let comparator = match args[0].as_ref() {
+        Node::SortedMap(_, comparator) | Node::SortedSet(_, comparator) => comparator.clone(),
+        node => return Err(EvalError::type_error(&format!("{}: expected a sorted collection but got {}", name, node)))
+    };
+
+    let (start_tests, end_tests) = if ascending { ([">", ">="], ["<", "<="]) } else { (["<", "<="], [">", ">="]) };
+    let (start, end) = if args.len() == 5 {
+        if ascending {
+            ((&args[1], &args[2]), Some((&args[3], &args[4])))
+        } else {
+            ((&args[3], &args[4]), Some((&args[1], &args[2])))
+        }
+    } else {
+        ((&args[1], &args[2]), None)
+    };
+    let (start, end) = match test_name(start.0) {
+        Some(test) if start_tests.contains(&test.as_ref()) => (Some((start.1, test.ends_with('='))), end),
+        Some(test) if end_tests.contains(&test.as_ref()) && end.is_none() => (None, Some(start)),
+        _ => return Err(EvalError::type_error(&format!("{}: expected one of <, <=, > or >= as test but got {}", name, start.0)))
+    };
+
+    let zero = Rc::new(Node::Integer(0));
+    let mut result = Vec::new();
+    for (key, element) in sorted_elements(context, &args[0], start, ascending)? {
+        if let Some((test, bound)) = end {
+            let ordering = compare_with(context, &comparator, &key, bound)? as i64;
+            let within = crate::eval::apply(context, test, &list_from(vec![Rc::new(Node::Integer(ordering)), zero.clone()]))?;
+            if let Node::Bool(false) | Node::Nil = within.as_ref() {
+                break;
+            }
+        }
+        result.push(element);
+    }
+    Ok(list_from(result))
+}
+
+fn subseq(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    sorted_range(context, "subseq", args, true)
+}
+
+fn rsubseq(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    sorted_range(context, "rsubseq", args, false)
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("vector"), vector);
+    builtins.insert(Symbol::intern("vec"), vec);
+    builtins.insert(Symbol::intern("conj"), conj);
+    builtins.insert(Symbol::intern("nth"), nth);
+    builtins.insert(Symbol::intern("get"), get);
+    builtins.insert(Symbol::intern("assoc"), assoc);
+    builtins.insert(Symbol::intern("pop"), pop);
+    builtins.insert(Symbol::intern("peek"), peek);
+    builtins.insert(Symbol::intern("subvec"), subvec);
+    builtins.insert(Symbol::intern("count"), count);
+    builtins.insert(Symbol::intern("hash-map"), hash_map);
+    builtins.insert(Symbol::intern("hash-set"), hash_set);
+    builtins.insert(Symbol::intern("set"), set);
+    builtins.insert(Symbol::intern("dissoc"), dissoc);
+    builtins.insert(Symbol::intern("disj"), disj);
+    builtins.insert(Symbol::intern("contains?"), contains);
+    builtins.insert(Symbol::intern("keys"), keys);
+    builtins.insert(Symbol::intern("vals"), vals);
+    builtins.insert(Symbol::intern("sorted-map"), sorted_map);
+    builtins.insert(Symbol::intern("sorted-map-by"), sorted_map_by);
+    builtins.insert(Symbol::intern("sorted-set"), sorted_set);
+    builtins.insert(Symbol::intern("sorted-set-by"), sorted_set_by);
+    builtins.insert(Symbol::intern("subseq"), subseq);
+    builtins.insert(Symbol::intern("empty"), empty);
+    builtins.insert(Symbol::intern("into"), into);
+    builtins.insert(Symbol::intern("queue"), queue);
+    builtins.insert(Symbol::intern("priority-queue"), priority_queue);
+    builtins.insert(Symbol::intern("priority-queue-by"), priority_queue_by);
+    builtins.insert(Symbol::intern("rsubseq"), rsubseq);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn vectors() {
+        assert_eq!(eval("(def v (vec (range 1100))) [(count v) (nth v 1057) (v 0) (peek v)]"), "[1100 1057 0 1099]");
+        assert_eq!(eval("(conj [1 2] 3 4)"), "[1 2 3 4]");
+        assert_eq!(eval("(assoc [1 2 3] 1 :x)"), "[1 :x 3]");
+        assert_eq!(eval("(assoc [1 2 3] 3 4)"), "[1 2 3 4]");
+        assert_eq!(eval("(pop [1 2 3])"), "[1 2]");
+        assert_eq!(eval("(subvec [0 1 2 3 4] 1 3)"), "[1 2]");
+        assert_eq!(eval("(subvec [0 1 2 3 4] 3)"), "[3 4]");
+        assert_eq!(eval("(conj (subvec [0 1 2 3 4] 1 3) :x)"), "[1 2 :x]");
+        assert_eq!(eval_err("(assoc [1 2 3] 4 4)"), "assoc: index 4 out of bounds");
+        assert_eq!(eval_err("(pop [])"), "pop: can't pop an empty vector");
+        assert_eq!(eval_err("(subvec [0 1 2] 2 1)"), "subvec: range 2..1 out of bounds");
+        assert_eq!(eval_err("(nth [1 2] 5)"), "nth: index 5 out of bounds");
+    }
+
+    #[test]
+    fn maps_and_sets() {
+        assert_eq!(eval("(assoc {:a 1} :b 2)"), "{:a 1, :b 2}");
+        assert_eq!(eval("(dissoc {:a 1 :b 2} :a)"), "{:b 2}");
+        assert_eq!(eval("(conj #{1 2} 3)"), "#{1 2 3}");
+        assert_eq!(eval("(disj #{1 2 3} 2)"), "#{1 3}");
+        assert_eq!(eval("[(= {:a 1 :b 2} {:b 2 :a 1}) (= #{1 2 3} #{3 2 1})]"), "[true true]");
+        assert_eq!(eval("(contains? #{nil false} nil)"), "true");
+        let big = "(def big (loop [m {} i 0] (if (< i 1000) (recur (assoc m i (* i i)) (inc i)) m)))";
+        assert_eq!(eval(&format!("{} [(count big) (get big 999) (get big 1000)]", big)), "[1000 998001 nil]");
+        assert_eq!(eval(&format!("{} [(loop [m big i 0] (if (< i 999) (recur (dissoc m i) (inc i)) m)) (count big)]", big)), "[{999 998001} 1000]");
+    }
+
+    #[test]
+    fn sorted_collections() {
+        assert_eq!(eval("(sorted-map :c 3 :a 1 :b 2)"), "{:a 1, :b 2, :c 3}");
+        assert_eq!(eval("(sorted-set 3 1 2)"), "#{1 2 3}");
+        assert_eq!(eval("(sorted-map-by > 1 :a 2 :b 3 :c)"), "{3 :c, 2 :b, 1 :a}");
+        assert_eq!(eval("(sorted-set-by (fn [a b] (compare b a)) 1 3 2)"), "#{3 2 1}");
+        assert_eq!(eval("[(dissoc (sorted-map 1 2 3 4) 1) (disj (sorted-set 1 2 3) 2)]"), "[{3 4} #{1 3}]");
+    }
+
+    #[test]
+    fn sorted_collections_are_looked_up_with_their_comparator() {
+        assert_eq!(eval("[(= (sorted-map-by > 1 :a 2 :b) {1 :a 2 :b}) (= #{1 2} (sorted-set-by > 2 1)) (= (sorted-map-by > 1 :a) {1 :b})]"), "[true true false]");
+        // Keys are equal when the comparator says so, whichever side of = the sorted map is on.
+        let by_count = "(defn by-count [a b] (compare (count a) (count b)))";
+        assert_eq!(eval(&format!("{} [(= {{\"ab\" 1}} (sorted-map-by by-count \"cd\" 1)) (= (sorted-set-by by-count \"cd\") #{{\"ab\"}})]", by_count)), "[true true]");
+        let big = "(def big (into (sorted-map-by <) (for [i (range 3000)] [i i])))";
+        assert_eq!(eval(&format!("{} [(= big (into {{}} big)) (= (into {{}} big) big) (= big (into (sorted-map-by >) big))]", big)), "[true true true]");
+    }
+
+    #[test]
+    fn subseq_takes_the_comparison_builtins() {
+        assert_eq!(eval("(subseq (sorted-set 1 2 3 4 5) > 2)"), "(3 4 5)");
+        assert_eq!(eval("(subseq (sorted-set 1 2 3 4 5) >= 2 < 4)"), "(2 3)");
+        assert_eq!(eval("(subseq (sorted-map 1 :a 2 :b 3 :c) >= 2)"), "([2 :b] [3 :c])");
+        assert_eq!(eval("(subseq (sorted-set 1 2 3) > 5)"), "nil");
+        assert_eq!(eval("(rsubseq (sorted-set 1 2 3 4 5) < 4)"), "(3 2 1)");
+        assert_eq!(eval("(rsubseq (sorted-set 1 2 3 4 5) > 1 <= 4)"), "(4 3 2)");
+        assert_eq!(eval_err("(subseq (sorted-set 1 2) = 1)"), "subseq: expected one of <, <=, > or >= as test but got #<fn =>");
+    }
+
+    #[test]
+    fn sort_uses_compare() {
+        assert_eq!(eval("(sort [[1 2] [1] [0 5]])"), "([1] [0 5] [1 2])");
+        assert_eq!(eval("(sort [:b :a :c/d])"), "(:a :b :c/d)");
+        assert_eq!(eval("(sort > [3 1 2])"), "(3 2 1)");
+        assert_eq!(eval("(compare 1 2.5)"), "-1");
+        assert_eq!(eval_err("(sort [3 \"a\" 1])"), "compare: cannot compare \"a\" to 3");
+    }
+
+    #[test]
+    fn queues() {
+        assert_eq!(eval("(conj (queue) 1 2 3)"), "#queue [1 2 3]");
+        assert_eq!(eval("(def q (conj (queue) 1 2 3)) [(peek q) (pop q) (count q) (seq q) (empty q) (into q [4 5])]"), "[1 #queue [2 3] 3 (1 2 3) #queue [] #queue [1 2 3 4 5]]");
+        assert_eq!(eval("[(pop (queue)) (peek (queue)) (map inc (queue 1 2))]"), "[#queue [] nil (2 3)]");
+        assert_eq!(eval("(def p (priority-queue 5 1 3)) [p (peek p) (pop p) (count p) (seq p) (into p [0 9])]"), "[#priority-queue [1 3 5] 1 #priority-queue [3 5] 3 (1 3 5) #priority-queue [0 1 3 5 9]]");
+        assert_eq!(eval("(let [p (priority-queue-by > 5 1 3)] [p (peek p)])"), "[#priority-queue [5 3 1] 5]");
+    }
+
+    #[test]
+    fn vector_versions_are_independent() {
+        assert_eq!(eval("(let [a (vec (range 40)) b (assoc a 35 :x) c (pop b)] [(nth a 35) (nth b 35) (count c) (count a)])"), "[35 :x 39 40]");
+    }
+}
@@ -0,0 +1,230 @@
+use crate::nodes::{NodePtr, Node, list_from, compare as compare_nodes, equals_with};
+use crate::intern::{Symbol, SymbolMap};
+use std::cmp::Ordering;
+use crate::eval::{EvalResult, EvalError, apply, seq::realize_deep};
+use crate::context::EvalContext;
+use std::rc::Rc;
+
+use super::{Builtin, arguments, elements};
+
+/// Orders two values with `comparator`, or with the default ordering if there is none.
+///
+/// Like in Clojure, a comparator can either return a number, whose sign gives the ordering,
+/// or be a predicate that is true when its first argument goes before the second one.
+pub fn compare_with(context: &mut EvalContext, comparator: &Option<NodePtr>, a: &NodePtr, b: &NodePtr) -> Result<Ordering, EvalError> {
+    let comparator = match comparator {
+        Some(comparator) => comparator,
+        None => return compare_default(a, b)
+    };
+    let result = apply(context, comparator, &list_from(vec![a.clone(), b.clone()]))?;
+    if let Some(number) = result.as_number() {
+        return Ok(number.signum().unwrap_or(Ordering::Equal));
+    }
+    match result.as_ref() {
+        Node::Bool(true) => Ok(Ordering::Less),
+        Node::Bool(false) | Node::Nil => {
+            let reverse = apply(context, comparator, &list_from(vec![b.clone(), a.clone()]))?;
+            if let Node::Bool(false) | Node::Nil = reverse.as_ref() {
+                Ok(Ordering::Equal)
+            } else {
+                Ok(Ordering::Greater)
+            }
+        },
+        node => Err(EvalError::type_error(&format!("compare: comparator returned {} instead of a number or a boolean", node)))
+    }
+}
+
+pub fn compare_default(a: &NodePtr, b: &NodePtr) -> Result<Ordering, EvalError> {
+    compare_nodes(a, b).ok_or_else(|| EvalError::type_error(&format!("compare: cannot compare {} to {}", a, b)))
+}
+
+fn ordering_to_node(ordering: Ordering) -> NodePtr {
+    Rc::new(Node::Integer(match ordering {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1
+    }))
+}
+
+/// Collects the arguments of `=` and `not=`, with any lazy seq in them realized.
+fn realized_arguments(context: &mut EvalContext, name: &str, args: &NodePtr) -> Result<Vec<NodePtr>, EvalError> {
+    let args = arguments(name, args, 1, None)?;
+    for arg in &args {
+        realize_deep(context, arg)?;
+    }
+    Ok(args)
+}
+
+/// Checks if `a` and `b` are equal, calling the comparators of the sorted maps and sets in
+/// them to look up their keys like `get` does.
+pub fn equal(context: &mut EvalContext, a: &Node, b: &Node) -> Result<bool, EvalError> {
+    let mut lookup = |coll: &Node, key: &NodePtr| match coll {
+        Node::SortedMap(map, comparator) => {
+            let mut compare = |a: &NodePtr, b: &NodePtr| compare_with(context, comparator, a, b);
+            Ok(map.get(key, &mut compare)?.map(|(_, value)| value.clone()))
+        },
+        Node::SortedSet(set, comparator) => {
+            let mut compare = |a: &NodePtr, b: &NodePtr| compare_with(context, comparator, a, b);
+            Ok(set.get(key, &mut compare)?.cloned())
+        },
+        _ => Ok(None)
+    };
+    equals_with(a, b, Some(&mut lookup))
+}
+
+fn all_equal(context: &mut EvalContext, args: &[NodePtr]) -> Result<bool, EvalError> {
+    for pair in args.windows(2) {
+        if !equal(context, &pair[0], &pair[1])? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn equals(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = realized_arguments(context, "=", args)?;
+    Ok(Rc::new(Node::Bool(all_equal(context, &args)?)))
+}
+
+fn not_equals(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = realized_arguments(context, "not=", args)?;
+    Ok(Rc::new(Node::Bool(!all_equal(context, &args)?)))
+}
+
+/// Checks if both arguments are the same object. Keywords are interned, so equal keywords
+/// are always identical, and so are `nil` and booleans.
+fn identical(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("identical?", args, 2, Some(2))?;
+    let identical = match (args[0].as_ref(), args[1].as_ref()) {
+        (Node::Keyword(a), Node::Keyword(b)) => a == b,
+        (Node::Nil, Node::Nil) => true,
+        (Node::Bool(a), Node::Bool(b)) => a == b,
+        _ => Rc::ptr_eq(&args[0], &args[1])
+    };
+    Ok(Rc::new(Node::Bool(identical)))
+}
+
+fn not(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("not", args, 1, Some(1))?;
+    Ok(Rc::new(Node::Bool(!args[0].is_truthy())))
+}
+
+fn is_nil(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("nil?", args, 1, Some(1))?;
+    Ok(Rc::new(Node::Bool(matches!(args[0].as_ref(), Node::Nil))))
+}
+
+fn is_some(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("some?", args, 1, Some(1))?;
+    Ok(Rc::new(Node::Bool(!matches!(args[0].as_ref(), Node::Nil))))
+}
+
+fn compare(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("compare", args, 2, Some(2))?;
+    Ok(ordering_to_node(compare_default(&args[0], &args[1])?))
+}
+
+/// Checks that every consecutive pair of numbers is ordered according to `test`.
+fn numbers_ordered(name: &str, args: &NodePtr, test: fn(Ordering) -> bool) -> EvalResult {
+    let args = arguments(name, args, 1, None)?;
+    if let Some(node) = args.iter().find(|node| node.as_number().is_none()) {
+        return Err(EvalError::type_error(&format!("{}: expected numbers but got {}", name, node)));
+    }
+    let ordered = args.windows(2).all(|pair| compare_nodes(&pair[0], &pair[1]).is_some_and(test));
+    Ok(Rc::new(Node::Bool(ordered)))
+}
+
+fn less(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    numbers_ordered("<", args, |ordering| ordering == Ordering::Less)
+}
+
+fn greater(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    numbers_ordered(">", args, |ordering| ordering == Ordering::Greater)
+}
+
+fn less_equal(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    numbers_ordered("<=", args, |ordering| ordering != Ordering::Greater)
+}
+
+fn greater_equal(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    numbers_ordered(">=", args, |ordering| ordering != Ordering::Less)
+}
+
+/// Sorts `nodes` in place. The standard sort can't be interrupted, so after the first error
+/// every comparison is treated as equal and the error is returned at the end.
+fn sort_nodes<F>(nodes: &mut [(NodePtr, NodePtr)], mut compare: F) -> Result<(), EvalError>
+    where F: FnMut(&NodePtr, &NodePtr) -> Result<Ordering, EvalError>
+{
+    let mut error = None;
+    nodes.sort_by(|(a, _), (b, _)| {
+        if error.is_some() {
+            return Ordering::Equal;
+        }
+        compare(a, b).unwrap_or_else(|err| {
+            error = Some(err);
+            Ordering::Equal
+        })
+    });
+    error.map_or(Ok(()), Err)
+}
+
+fn sort(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("sort", args, 1, Some(2))?;
+    let (comparator, coll) = match args.len() {
+        1 => (None, &args[0]),
+        _ => (Some(args[0].clone()), &args[1])
+    };
+    let mut nodes = elements(context, "sort", coll)?
+        .into_iter()
+        .map(|node| (node.clone(), node))
+        .collect::<Vec<(NodePtr, NodePtr)>>();
+    sort_nodes(&mut nodes, |a, b| compare_with(context, &comparator, a, b))?;
+    Ok(list_from(nodes.into_iter().map(|(_, node)| node).collect::<Vec<NodePtr>>()))
+}
+
+fn sort_by(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("sort-by", args, 2, Some(3))?;
+    let (key_fn, comparator, coll) = match args.len() {
+        2 => (&args[0], None, &args[1]),
+        _ => (&args[0], Some(args[1].clone()), &args[2])
+    };
+    let mut nodes = Vec::new();
+    for node in elements(context, "sort-by", coll)? {
+        let key = apply(context, key_fn, &list_from(vec![node.clone()]))?;
+        nodes.push((key, node));
+    }
+    sort_nodes(&mut nodes, |a, b| compare_with(context, &comparator, a, b))?;
+    Ok(list_from(nodes.into_iter().map(|(_, node)| node).collect::<Vec<NodePtr>>()))
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("="), equals);
+    builtins.insert(Symbol::intern("not="), not_equals);
+    builtins.insert(Symbol::intern("identical?"), identical);
+    builtins.insert(Symbol::intern("not"), not);
+    builtins.insert(Symbol::intern("nil?"), is_nil);
+    builtins.insert(Symbol::intern("some?"), is_some);
+    builtins.insert(Symbol::intern("compare"), compare);
+    builtins.insert(Symbol::intern("<"), less);
+    builtins.insert(Symbol::intern(">"), greater);
+    builtins.insert(Symbol::intern("<="), less_equal);
+    builtins.insert(Symbol::intern(">="), greater_equal);
+    builtins.insert(Symbol::intern("sort"), sort);
+    builtins.insert(Symbol::intern("sort-by"), sort_by);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::eval;
+
+    #[test]
+    fn keywords_are_identical() {
+        assert_eq!(eval("[(identical? :a :a) (identical? :a/b :a/b) (identical? :a :b) (identical? nil nil)]"), "[true true false true]");
+        assert_eq!(eval("[(let [v [1]] (identical? v v)) (identical? [1] [1])]"), "[true false]");
+    }
+
+    #[test]
+    fn built_names_equal_read_ones() {
+        assert_eq!(eval("(let [m {:a 1 'b 2}] [(:a m) (get m 'b) (= 'b (symbol \"b\")) (= :x (keyword \"x\"))])"), "[1 2 true true]");
+    }
+}
@@ -0,0 +1,104 @@
+use crate::nodes::{NodePtr, Node};
+use crate::intern::{Symbol, SymbolMap};
+use crate::eval::{EvalResult, EvalError, Exception};
+use crate::context::EvalContext;
+use std::rc::Rc;
+
+use super::{Builtin, arguments};
+
+/// `(ex-info message data cause?)` creates an exception carrying a map of data.
+fn ex_info(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("ex-info", args, 2, Some(3))?;
+    let message = match args[0].as_ref() {
+        Node::String(message) => message,
+        node => return Err(EvalError::type_error(&format!("ex-info: expected a string message but got {}", node)))
+    };
+    if !matches!(args[1].as_ref(), Node::Map(_) | Node::SortedMap(_, _)) {
+        return Err(EvalError::type_error(&format!("ex-info: expected a map of data but got {}", args[1])));
+    }
+    let cause = match args.get(2).map(|cause| cause.as_ref()) {
+        Some(Node::Exception(_)) => args.get(2).cloned(),
+        Some(Node::Nil) | None => None,
+        Some(node) => return Err(EvalError::type_error(&format!("ex-info: expected an exception as cause but got {}", node)))
+    };
+    let exception = Exception::new(Symbol::intern("ExceptionInfo"), message, args[1].clone(), cause);
+    Ok(Rc::new(Node::Exception(Rc::new(exception))))
+}
+
+/// Reads the exception argument of `name`, which returns nil for any other value.
+fn exception_arg<'a>(name: &str, args: &'a NodePtr) -> Result<Option<&'a Exception>, EvalError> {
+    arguments(name, args, 1, Some(1))?;
+    match args.as_ref() {
+        Node::List(arg, _) => match arg.as_ref() {
+            Node::Exception(exception) => Ok(Some(exception)),
+            _ => Ok(None)
+        },
+        _ => unreachable!()
+    }
+}
+
+fn ex_data(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let exception = exception_arg("ex-data", args)?;
+    Ok(exception.map_or_else(|| Rc::new(Node::Nil), |exception| exception.data().clone()))
+}
+
+fn ex_message(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let exception = exception_arg("ex-message", args)?;
+    Ok(Rc::new(exception.map_or(Node::Nil, |exception| Node::String(exception.message().to_string()))))
+}
+
+fn ex_cause(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let exception = exception_arg("ex-cause", args)?;
+    Ok(exception.and_then(Exception::cause).cloned().unwrap_or_else(|| Rc::new(Node::Nil)))
+}
+
+/// `(ex-trace e)` returns the stack trace of where `e` was thrown, as a vector of maps like
+/// `{:fn f, :file "repl", :line 1, :column 3}` from the innermost call. It's nil until the
+/// exception is caught or reported.
+fn ex_trace(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let exception = exception_arg("ex-trace", args)?;
+    Ok(exception.and_then(Exception::trace).cloned().unwrap_or_else(|| Rc::new(Node::Nil)))
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("ex-info"), ex_info);
+    builtins.insert(Symbol::intern("ex-data"), ex_data);
+    builtins.insert(Symbol::intern("ex-message"), ex_message);
+    builtins.insert(Symbol::intern("ex-cause"), ex_cause);
+    builtins.insert(Symbol::intern("ex-trace"), ex_trace);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn ex_info() {
+        assert_eq!(eval("(try (throw (ex-info \"boom\" {:a 1})) (catch ExceptionInfo e [(ex-message e) (ex-data e)]))"), "[\"boom\" {:a 1}]");
+        assert_eq!(eval("(let [e (ex-info \"a\" {} (ex-info \"b\" {}))] [(ex-message (ex-cause e)) (ex-cause (ex-cause e))])"), "[\"b\" nil]");
+        assert_eq!(eval_err("(throw 1)"), "throw: expected an exception but got 1");
+    }
+
+    #[test]
+    fn catch_by_kind() {
+        assert_eq!(eval("[(try (nth [] 1) (catch OutOfBoundsError e :oob)) (try (inc) (catch ArityError e :arity)) (try (1 2) (catch NotCallableError e :nc))]"), "[:oob :arity :nc]");
+        assert_eq!(eval("[(try (inc \"a\") (catch Exception e :caught)) (try (inc) (catch TypeError e :type) (catch EvalError e :eval))]"), "[:caught :eval]");
+        assert_eq!(eval("(try (foo) (catch UnboundSymbolError e (ex-message e)))"), "\"Unable to resolve symbol: foo\"");
+        assert_eq!(eval_err("(try (throw (ex-info \"x\" {})) (catch TypeError e 1))"), "ExceptionInfo: x {}");
+        assert_eq!(eval_err("(try 1 (catch Arithmetic e 1))"), "catch: unknown exception kind Arithmetic");
+    }
+
+    #[test]
+    fn finally_runs_on_every_exit() {
+        let nested = "(def log (transient []))
+            (try (try (throw (ex-info \"x\" {})) (finally (conj! log :inner))) (catch Exception e (conj! log :outer)))
+            (persistent! log)";
+        assert_eq!(eval(nested), "[:inner :outer]");
+        assert_eq!(eval("(def log (transient [])) (loop [i 0] (if (< i 3) (try (recur (inc i)) (finally (conj! log i))) i)) (count log)"), "3");
+        let rethrown = "(def log (transient []))
+            (try (try (throw (ex-info \"a\" {})) (catch Exception e (throw (ex-info \"b\" {}))) (finally (conj! log :f)))
+                (catch Exception e [(ex-message e) (persistent! log)]))";
+        assert_eq!(eval(rethrown), "[\"b\" [:f]]");
+        assert_eq!(eval("(try 1 (finally 2))"), "1");
+    }
+}
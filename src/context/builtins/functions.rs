@@ -0,0 +1,24 @@
+use crate::nodes::{NodePtr, Node, list_from};
+use crate::intern::{Symbol, SymbolMap};
+use crate::eval::{EvalResult, apply};
+use crate::context::EvalContext;
+use std::rc::Rc;
+
+use super::{Builtin, arguments};
+
+/// `(trampoline f args...)` calls `f` with `args`, then keeps calling the result without
+/// arguments while it's a function. Mutually recursive functions can return a function
+/// making the next call instead of making it themselves, so they don't use more stack.
+fn trampoline(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let mut args = arguments("trampoline", args, 1, None)?;
+    let function = args.remove(0);
+    let mut result = apply(context, &function, &list_from(args))?;
+    while let Node::Function(_) | Node::Builtin(_, _) = result.as_ref() {
+        result = apply(context, &result, &Rc::new(Node::Nil))?;
+    }
+    Ok(result)
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("trampoline"), trampoline);
+}
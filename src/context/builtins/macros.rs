@@ -0,0 +1,36 @@
+use crate::nodes::{NodePtr, Node};
+use crate::intern::{Symbol, SymbolMap};
+use crate::eval::{EvalResult, EvalError, macros};
+use crate::context::EvalContext;
+use std::rc::Rc;
+
+use super::{Builtin, arguments};
+
+fn macroexpand_1(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("macroexpand-1", args, 1, Some(1))?;
+    Ok(macros::macroexpand_1(context, &args[0], &[])?.unwrap_or_else(|| args[0].clone()))
+}
+
+fn macroexpand(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("macroexpand", args, 1, Some(1))?;
+    macros::macroexpand(context, &args[0])
+}
+
+/// Returns a new symbol with a unique name, starting with the given prefix or `G__`.
+fn gensym(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("gensym", args, 0, Some(1))?;
+    let prefix = match args.first().map(|arg| arg.as_ref()) {
+        None => "G__".to_string(),
+        Some(Node::String(prefix)) => prefix.clone(),
+        Some(Node::Symbol(prefix)) => prefix.name().to_string(),
+        Some(node) => return Err(EvalError::type_error(&format!("gensym: expected a string prefix but got {}", node)))
+    };
+    let id = context.root().next_id();
+    Ok(Rc::new(Node::Symbol(Symbol::intern(&format!("{}{}", prefix, id)))))
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("macroexpand-1"), macroexpand_1);
+    builtins.insert(Symbol::intern("macroexpand"), macroexpand);
+    builtins.insert(Symbol::intern("gensym"), gensym);
+}
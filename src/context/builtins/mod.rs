@@ -0,0 +1,54 @@
+mod collections;
+mod compare;
+mod exceptions;
+mod functions;
+mod macros;
+mod names;
+mod numbers;
+mod seqs;
+mod transients;
+mod vars;
+
+use crate::nodes::{NodePtr, Node, map_entry};
+use crate::intern::SymbolMap;
+use crate::eval::{EvalResult, EvalError, seq, arguments};
+use crate::context::EvalContext;
+
+pub type Builtin = fn(&mut EvalContext, &NodePtr) -> EvalResult;
+
+/// Reads an argument that is used as an index or a count.
+fn index_arg(name: &str, node: &NodePtr) -> Result<usize, EvalError> {
+    match node.as_ref() {
+        Node::Integer(int) if *int >= 0 => Ok(*int as usize),
+        Node::Integer(int) => Err(EvalError::out_of_bounds(&format!("{}: index {} is negative", name, int))),
+        node => Err(EvalError::type_error(&format!("{}: expected an integer index but got {}", name, node)))
+    }
+}
+
+/// Collects the elements of a collection in iteration order. Maps produce `[key value]` vectors.
+fn elements(context: &mut EvalContext, name: &str, coll: &NodePtr) -> Result<Vec<NodePtr>, EvalError> {
+    match coll.as_ref() {
+        Node::Nil | Node::List(_, _) | Node::Seq(_) | Node::LazySeq(_) => seq::collect(context, coll),
+        Node::Vector(vec) => Ok(vec.iter().cloned().collect()),
+        Node::Set(set) => Ok(set.iter().cloned().collect()),
+        Node::SortedSet(set, _) => Ok(set.iter().cloned().collect()),
+        Node::Map(map) => Ok(map.iter().map(|(key, value)| map_entry(key, value)).collect()),
+        Node::SortedMap(map, _) => Ok(map.iter().map(|(key, value)| map_entry(key, value)).collect()),
+        Node::Queue(queue) => Ok(queue.iter().cloned().collect()),
+        Node::PriorityQueue(queue, _) => Ok(queue.iter().cloned().collect()),
+        node => Err(EvalError::type_error(&format!("{}: {} is not a collection", name, node)))
+    }
+}
+
+pub fn populate_builtins(builtins: &mut SymbolMap<Builtin>) {
+    collections::populate(builtins);
+    compare::populate(builtins);
+    exceptions::populate(builtins);
+    functions::populate(builtins);
+    macros::populate(builtins);
+    names::populate(builtins);
+    numbers::populate(builtins);
+    seqs::populate(builtins);
+    transients::populate(builtins);
+    vars::populate(builtins);
+}
@@ -0,0 +1,109 @@
+use crate::nodes::{NodePtr, Node};
+use crate::intern::{Symbol, SymbolMap};
+use crate::eval::{EvalResult, EvalError};
+use crate::context::EvalContext;
+use std::rc::Rc;
+
+use super::{Builtin, arguments};
+
+/// Reads the name of a keyword, symbol or string argument.
+fn name_arg(name: &str, node: &NodePtr) -> Result<Symbol, EvalError> {
+    match node.as_ref() {
+        Node::Keyword(symbol) | Node::Symbol(symbol) => Ok(*symbol),
+        Node::String(string) if !string.is_empty() => Ok(Symbol::intern(string)),
+        node => Err(EvalError::type_error(&format!("{}: expected a keyword, symbol or string but got {}", name, node)))
+    }
+}
+
+/// Builds the name for `keyword` and `symbol`, either from a single name that may already be
+/// qualified, or from a namespace (which can be nil) and a name.
+fn make_name(name: &str, args: &NodePtr) -> Result<Symbol, EvalError> {
+    let args = arguments(name, args, 1, Some(2))?;
+    match args.as_slice() {
+        [single] => name_arg(name, single),
+        [namespace, local] => match (namespace.as_ref(), local.as_ref()) {
+            (Node::Nil, _) => name_arg(name, local),
+            (Node::String(namespace), Node::String(local)) => Ok(Symbol::qualified(namespace, local)),
+            _ => Err(EvalError::type_error(&format!("{}: expected a namespace and a name as strings", name)))
+        },
+        _ => unreachable!()
+    }
+}
+
+fn keyword(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    Ok(Rc::new(Node::Keyword(make_name("keyword", args)?)))
+}
+
+fn symbol(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    Ok(Rc::new(Node::Symbol(make_name("symbol", args)?)))
+}
+
+/// Name of a keyword or symbol without its namespace. Strings are returned as they are.
+fn name(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("name", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::String(_) => Ok(args[0].clone()),
+        Node::Keyword(symbol) | Node::Symbol(symbol) => Ok(Rc::new(Node::String(symbol.split().1.name().to_string()))),
+        node => Err(EvalError::type_error(&format!("name: expected a keyword, symbol or string but got {}", node)))
+    }
+}
+
+fn namespace(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("namespace", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Keyword(symbol) | Node::Symbol(symbol) => match symbol.split().0 {
+            Some(namespace) => Ok(Rc::new(Node::String(namespace.name().to_string()))),
+            None => Ok(Rc::new(Node::Nil))
+        },
+        node => Err(EvalError::type_error(&format!("namespace: expected a keyword or symbol but got {}", node)))
+    }
+}
+
+fn is_keyword(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("keyword?", args, 1, Some(1))?;
+    Ok(Rc::new(Node::Bool(matches!(args[0].as_ref(), Node::Keyword(_)))))
+}
+
+fn is_symbol(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("symbol?", args, 1, Some(1))?;
+    Ok(Rc::new(Node::Bool(matches!(args[0].as_ref(), Node::Symbol(_)))))
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("keyword"), keyword);
+    builtins.insert(Symbol::intern("symbol"), symbol);
+    builtins.insert(Symbol::intern("name"), name);
+    builtins.insert(Symbol::intern("namespace"), namespace);
+    builtins.insert(Symbol::intern("keyword?"), is_keyword);
+    builtins.insert(Symbol::intern("symbol?"), is_symbol);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn keyword_names() {
+        assert_eq!(eval("[(keyword \"a\") (keyword \"user\" \"id\") (name :user/id) (namespace :user/id) (namespace :a) (name 'x/y) (symbol \"a\" \"b\")]"), "[:a :user/id \"id\" \"user\" nil \"y\" a/b]");
+        assert_eq!(eval("[(keyword? :a) (keyword? 'a) (symbol? 'a) (name \"str\")]"), "[true false true \"str\"]");
+        assert_eq!(eval_err("(keyword nil)"), "keyword: expected a keyword, symbol or string but got nil");
+    }
+
+    #[test]
+    fn auto_resolved_keywords() {
+        assert_eq!(eval("[::id (namespace ::id)]"), "[:user/id \"user\"]");
+    }
+
+    #[test]
+    fn namespaced_maps() {
+        assert_eq!(eval("#:user{:id 1 :name \"x\"}"), "#:user{:id 1, :name \"x\"}");
+        assert_eq!(eval("#:user{:id 1 :other/x 2 :_/y 3}"), "{:user/id 1, :other/x 2, :y 3}");
+        assert_eq!(eval("[(:user/id #:user{:id 1}) (= {::id 1} #:user{:id 1})]"), "[1 true]");
+    }
+
+    #[test]
+    fn arity_errors_agree_in_number() {
+        assert_eq!(eval_err("(name :a 1)"), "name: expected 1 argument but got 2");
+        assert_eq!(eval_err("(keyword)"), "keyword: expected 1 to 2 arguments but got 0");
+    }
+}
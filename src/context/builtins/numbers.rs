@@ -0,0 +1,303 @@
+use crate::nodes::{NodePtr, Node};
+use crate::intern::{Symbol, SymbolMap};
+use std::cmp::Ordering;
+use crate::eval::{EvalResult, EvalError};
+use crate::context::EvalContext;
+use crate::numbers::{self, Number, ArithmeticResult, ArithmeticError, BigInt, BigDecimal};
+use std::rc::Rc;
+
+use super::{Builtin, arguments};
+
+type Operation = fn(&Number, &Number) -> ArithmeticResult;
+
+fn number_arg(name: &str, node: &NodePtr) -> Result<Number, EvalError> {
+    node.as_number().ok_or_else(|| EvalError::type_error(&format!("{}: expected a number but got {}", name, node)))
+}
+
+fn number_args(name: &str, args: &NodePtr, min: usize, max: Option<usize>) -> Result<Vec<Number>, EvalError> {
+    arguments(name, args, min, max)?.iter().map(|arg| number_arg(name, arg)).collect()
+}
+
+fn number_node(name: &str, result: ArithmeticResult) -> EvalResult {
+    match result {
+        Ok(number) => Ok(Rc::new(Node::from(number))),
+        Err(err) => Err(EvalError::other(&format!("{}: {}", name, err)))
+    }
+}
+
+/// Combines all the arguments from left to right, starting from `identity`.
+fn fold(name: &str, args: &NodePtr, identity: i64, operation: Operation) -> EvalResult {
+    let numbers = number_args(name, args, 0, None)?;
+    number_node(name, numbers.iter().try_fold(Number::Integer(identity), |result, number| operation(&result, number)))
+}
+
+/// Combines the arguments from left to right starting from the first one. With a single
+/// argument, `single` is applied to it instead.
+fn fold_first(name: &str, args: &NodePtr, single: fn(&Number) -> ArithmeticResult, operation: Operation) -> EvalResult {
+    let numbers = number_args(name, args, 1, None)?;
+    let result = match numbers.split_first() {
+        Some((first, [])) => single(first),
+        Some((first, rest)) => rest.iter().try_fold(first.clone(), |result, number| operation(&result, number)),
+        None => unreachable!()
+    };
+    number_node(name, result)
+}
+
+fn add(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    fold("+", args, 0, |a, b| numbers::add(a, b, false))
+}
+
+/// `(add numbers...)` is the older name of `+`.
+fn add_named(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    fold("add", args, 0, |a, b| numbers::add(a, b, false))
+}
+
+fn add_promoting(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    fold("+'", args, 0, |a, b| numbers::add(a, b, true))
+}
+
+fn multiply(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    fold("*", args, 1, |a, b| numbers::mul(a, b, false))
+}
+
+fn multiply_promoting(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    fold("*'", args, 1, |a, b| numbers::mul(a, b, true))
+}
+
+fn subtract(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    fold_first("-", args, |a| numbers::negate(a, false), |a, b| numbers::sub(a, b, false))
+}
+
+fn subtract_promoting(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    fold_first("-'", args, |a| numbers::negate(a, true), |a, b| numbers::sub(a, b, true))
+}
+
+fn divide(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    fold_first("/", args, |a| numbers::div(&Number::Integer(1), a), numbers::div)
+}
+
+fn inc(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("inc", args, 1, Some(1))?;
+    number_node("inc", numbers::add(&args[0], &Number::Integer(1), false))
+}
+
+fn inc_promoting(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("inc'", args, 1, Some(1))?;
+    number_node("inc'", numbers::add(&args[0], &Number::Integer(1), true))
+}
+
+fn dec(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("dec", args, 1, Some(1))?;
+    number_node("dec", numbers::sub(&args[0], &Number::Integer(1), false))
+}
+
+fn dec_promoting(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("dec'", args, 1, Some(1))?;
+    number_node("dec'", numbers::sub(&args[0], &Number::Integer(1), true))
+}
+
+fn quot(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("quot", args, 2, Some(2))?;
+    number_node("quot", numbers::quot(&args[0], &args[1]))
+}
+
+fn rem(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("rem", args, 2, Some(2))?;
+    number_node("rem", numbers::rem(&args[0], &args[1]))
+}
+
+fn modulo(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("mod", args, 2, Some(2))?;
+    number_node("mod", numbers::modulo(&args[0], &args[1]))
+}
+
+/// Numeric equality across types, unlike `=`, so `(== 1 1.0)` is true.
+fn numbers_equal(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("==", args, 1, None)?;
+    let equal = args.windows(2).all(|pair| numbers::compare(&pair[0], &pair[1]) == Some(Ordering::Equal));
+    Ok(Rc::new(Node::Bool(equal)))
+}
+
+fn sign_test(name: &str, args: &NodePtr, expected: Ordering) -> EvalResult {
+    let args = number_args(name, args, 1, Some(1))?;
+    Ok(Rc::new(Node::Bool(args[0].signum() == Some(expected))))
+}
+
+fn is_zero(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    sign_test("zero?", args, Ordering::Equal)
+}
+
+fn is_pos(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    sign_test("pos?", args, Ordering::Greater)
+}
+
+fn is_neg(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    sign_test("neg?", args, Ordering::Less)
+}
+
+fn type_test(name: &str, args: &NodePtr, test: fn(&Node) -> bool) -> EvalResult {
+    let args = arguments(name, args, 1, Some(1))?;
+    Ok(Rc::new(Node::Bool(test(&args[0]))))
+}
+
+fn is_number(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    type_test("number?", args, |node| node.as_number().is_some())
+}
+
+fn is_integer(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    type_test("integer?", args, |node| matches!(node, Node::Integer(_) | Node::BigInt(_)))
+}
+
+fn is_ratio(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    type_test("ratio?", args, |node| matches!(node, Node::Ratio(_)))
+}
+
+fn is_decimal(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    type_test("decimal?", args, |node| matches!(node, Node::Decimal(_)))
+}
+
+fn is_float(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    type_test("float?", args, |node| matches!(node, Node::Float(_)))
+}
+
+fn numerator(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("numerator", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Ratio(ratio) => Ok(Rc::new(Node::BigInt(ratio.numer().clone()))),
+        node => Err(EvalError::type_error(&format!("numerator: expected a ratio but got {}", node)))
+    }
+}
+
+fn denominator(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("denominator", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Ratio(ratio) => Ok(Rc::new(Node::BigInt(ratio.denom().clone()))),
+        node => Err(EvalError::type_error(&format!("denominator: expected a ratio but got {}", node)))
+    }
+}
+
+/// Truncates a number to a big integer.
+fn truncate(name: &str, number: &Number) -> Result<BigInt, EvalError> {
+    match number {
+        Number::Integer(int) => Ok(BigInt::from_i64(*int)),
+        Number::BigInt(int) => Ok(int.clone()),
+        Number::Float(float) if !float.is_finite() => {
+            Err(EvalError::type_error(&format!("{}: cannot convert {} to an integer", name, number)))
+        },
+        Number::Float(float) => Ok(BigInt::parse(&format!("{:.0}", float.trunc())).unwrap()),
+        _ => match numbers::quot(number, &Number::Integer(1)) {
+            Ok(Number::BigInt(int)) => Ok(int),
+            Ok(Number::Decimal(decimal)) => Ok(BigInt::parse(&decimal.to_string()).unwrap()),
+            _ => unreachable!()
+        }
+    }
+}
+
+fn bigint(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("bigint", args, 1, Some(1))?;
+    Ok(Rc::new(Node::BigInt(truncate("bigint", &args[0])?)))
+}
+
+fn long(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("long", args, 1, Some(1))?;
+    match truncate("long", &args[0])?.to_i64() {
+        Some(int) => Ok(Rc::new(Node::Integer(int))),
+        None => Err(EvalError::out_of_bounds(&format!("long: {} is out of range", args[0])))
+    }
+}
+
+fn bigdec(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("bigdec", args, 1, Some(1))?;
+    let decimal = match &args[0] {
+        Number::Float(float) => BigDecimal::parse(&format!("{:?}", float)).ok_or_else(|| {
+            EvalError::type_error(&format!("bigdec: cannot convert {} to a decimal", args[0]))
+        })?,
+        // Adding an exact zero converts any other number with the usual contagion rules.
+        number => match numbers::add(number, &Number::Decimal(BigDecimal::from_integer(BigInt::zero())), false) {
+            Ok(Number::Decimal(decimal)) => decimal,
+            Err(err @ ArithmeticError::NonTerminatingDecimal) => return Err(EvalError::other(&format!("bigdec: {}", err))),
+            _ => unreachable!()
+        }
+    };
+    Ok(Rc::new(Node::Decimal(decimal)))
+}
+
+fn double(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = number_args("double", args, 1, Some(1))?;
+    Ok(Rc::new(Node::Float(args[0].to_f64())))
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("+"), add);
+    builtins.insert(Symbol::intern("+'"), add_promoting);
+    builtins.insert(Symbol::intern("add"), add_named);
+    builtins.insert(Symbol::intern("*"), multiply);
+    builtins.insert(Symbol::intern("*'"), multiply_promoting);
+    builtins.insert(Symbol::intern("-"), subtract);
+    builtins.insert(Symbol::intern("-'"), subtract_promoting);
+    builtins.insert(Symbol::intern("/"), divide);
+    builtins.insert(Symbol::intern("inc"), inc);
+    builtins.insert(Symbol::intern("inc'"), inc_promoting);
+    builtins.insert(Symbol::intern("dec"), dec);
+    builtins.insert(Symbol::intern("dec'"), dec_promoting);
+    builtins.insert(Symbol::intern("quot"), quot);
+    builtins.insert(Symbol::intern("rem"), rem);
+    builtins.insert(Symbol::intern("mod"), modulo);
+    builtins.insert(Symbol::intern("=="), numbers_equal);
+    builtins.insert(Symbol::intern("zero?"), is_zero);
+    builtins.insert(Symbol::intern("pos?"), is_pos);
+    builtins.insert(Symbol::intern("neg?"), is_neg);
+    builtins.insert(Symbol::intern("number?"), is_number);
+    builtins.insert(Symbol::intern("integer?"), is_integer);
+    builtins.insert(Symbol::intern("ratio?"), is_ratio);
+    builtins.insert(Symbol::intern("decimal?"), is_decimal);
+    builtins.insert(Symbol::intern("float?"), is_float);
+    builtins.insert(Symbol::intern("numerator"), numerator);
+    builtins.insert(Symbol::intern("denominator"), denominator);
+    builtins.insert(Symbol::intern("bigint"), bigint);
+    builtins.insert(Symbol::intern("long"), long);
+    builtins.insert(Symbol::intern("bigdec"), bigdec);
+    builtins.insert(Symbol::intern("double"), double);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn literals_and_arithmetic() {
+        assert_eq!(eval("[1N (+ 1N 2) 1/3 (+ 1/3 1/6) 1.5M (+ 1.5M 1) (* 1/2 2) (/ 1 3) (/ 4 2)]"), "[1N 3N 1/3 1/2 1.5M 2.5M 1N 1/3 2]");
+        assert_eq!(eval("(* 99999999999999999999N 99999999999999999999N)"), "9999999999999999999800000000000000000001N");
+        assert_eq!(eval("[(+ 1 1.5) (+ 1/2 0.5) (+ 1/2 1.5M) (+ 1N 1.5M) (* 1.10M 2)]"), "[2.5 1.0 2.0M 2.5M 2.20M]");
+        assert_eq!(eval("[(= 1 1N) (= 1/2 0.5) (/ 1.0 0)]"), "[true false ##Inf]");
+    }
+
+    #[test]
+    fn overflow_throws() {
+        assert_eq!(eval_err("(+ 9223372036854775807 1)"), "+: integer overflow");
+        assert_eq!(eval_err("(- -9223372036854775807 2)"), "-: integer overflow");
+        assert_eq!(eval_err("(* 9223372036854775807 2)"), "*: integer overflow");
+        assert_eq!(eval_err("(inc 9223372036854775807)"), "inc: integer overflow");
+        assert_eq!(eval_err("(/ 1 0)"), "/: divide by zero");
+        assert_eq!(eval_err("(/ 1M 3)"), "/: non-terminating decimal expansion, no exact decimal result");
+    }
+
+    #[test]
+    fn primed_operators_promote() {
+        assert_eq!(eval("[(+' 9223372036854775807 1) (-' -9223372036854775807 2) (*' 9223372036854775807 2) (inc' 9223372036854775807)]"),
+            "[9223372036854775808N -9223372036854775809N 18446744073709551614N 9223372036854775808N]");
+    }
+
+    #[test]
+    fn add_is_a_checked_alias_of_plus() {
+        assert_eq!(eval("(add 1 2)"), "3");
+        assert_eq!(eval_err("(add 9223372036854775807 1)"), "add: integer overflow");
+    }
+
+    #[test]
+    fn equal_floats_are_the_same_key() {
+        assert_eq!(eval("[(= 0.0 -0.0) (get {0.0 :zero} -0.0) (count (conj #{0.0} -0.0))]"), "[true :zero 1]");
+        // NaN is never == to itself, but it is = to itself, so that it can be a key.
+        assert_eq!(eval("(def nan (/ 0.0 0.0)) [(== nan nan) (= nan nan) (get {nan :nan} nan)]"), "[false true :nan]");
+    }
+}
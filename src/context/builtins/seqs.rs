@@ -0,0 +1,390 @@
+use crate::nodes::{NodePtr, Node, IntoListIter, list_from};
+use crate::intern::{Symbol, SymbolMap};
+use crate::eval::{EvalResult, EvalError, apply};
+use crate::eval::seq::{self, SeqIter, lazy, cons};
+use crate::context::EvalContext;
+use std::rc::Rc;
+
+use super::{Builtin, arguments, index_arg};
+
+fn seq(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("seq", args, 1, Some(1))?;
+    seq::seq(context, &args[0])
+}
+
+fn first(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("first", args, 1, Some(1))?;
+    seq::first(context, &args[0])
+}
+
+fn rest(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("rest", args, 1, Some(1))?;
+    seq::rest(context, &args[0])
+}
+
+fn next(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("next", args, 1, Some(1))?;
+    seq::next(context, &args[0])
+}
+
+fn cons_builtin(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("cons", args, 2, Some(2))?;
+    // A lazy seq is already a seq, and stays unrealized. Other collections are turned into
+    // their seq, so the rest of the list is always a seq.
+    let rest = match args[1].as_ref() {
+        Node::LazySeq(_) => args[1].clone(),
+        _ => seq::seq(context, &args[1])?
+    };
+    Ok(cons(args[0].clone(), rest))
+}
+
+fn list(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    Ok(list_from(args.list_elements()?))
+}
+
+fn is_seq(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("seq?", args, 1, Some(1))?;
+    let is_seq = matches!(args[0].as_ref(), Node::List(_, _) | Node::Seq(_) | Node::LazySeq(_));
+    Ok(Rc::new(Node::Bool(is_seq)))
+}
+
+fn is_empty(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("empty?", args, 1, Some(1))?;
+    let seq = seq::seq(context, &args[0])?;
+    Ok(Rc::new(Node::Bool(matches!(seq.as_ref(), Node::Nil))))
+}
+
+fn is_realized(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("realized?", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::LazySeq(lazy) => Ok(Rc::new(Node::Bool(lazy.realized().is_some()))),
+        node => Err(EvalError::type_error(&format!("realized?: expected a lazy seq but got {}", node)))
+    }
+}
+
+fn integer_arg(name: &str, node: &NodePtr) -> Result<i64, EvalError> {
+    match node.as_ref() {
+        Node::Integer(int) => Ok(*int),
+        node => Err(EvalError::type_error(&format!("{}: expected an integer but got {}", name, node)))
+    }
+}
+
+/// Integers from `start` by `step`, up to `end` excluded if there is one.
+fn range_from(start: i64, end: Option<i64>, step: i64) -> NodePtr {
+    lazy(move |_| {
+        let done = match end {
+            Some(end) if step > 0 => start >= end,
+            Some(end) if step < 0 => start <= end,
+            Some(end) => start == end,
+            None => false
+        };
+        if done {
+            return Ok(Rc::new(Node::Nil));
+        }
+        let rest = match start.checked_add(step) {
+            Some(next) => range_from(next, end, step),
+            None => Rc::new(Node::Nil)
+        };
+        Ok(cons(Rc::new(Node::Integer(start)), rest))
+    })
+}
+
+fn range(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("range", args, 0, Some(3))?;
+    let args = args.iter().map(|arg| integer_arg("range", arg)).collect::<Result<Vec<i64>, EvalError>>()?;
+    Ok(match args.as_slice() {
+        [] => range_from(0, None, 1),
+        [end] => range_from(0, Some(*end), 1),
+        [start, end] => range_from(*start, Some(*end), 1),
+        [start, end, step, ..] => range_from(*start, Some(*end), *step)
+    })
+}
+
+fn iterate_from(function: NodePtr, value: NodePtr) -> NodePtr {
+    let rest = {
+        let value = value.clone();
+        lazy(move |context| {
+            let next = apply(context, &function, &list_from(vec![value.clone()]))?;
+            Ok(iterate_from(function.clone(), next))
+        })
+    };
+    cons(value, rest)
+}
+
+fn iterate(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("iterate", args, 2, Some(2))?;
+    Ok(iterate_from(args[0].clone(), args[1].clone()))
+}
+
+fn repeat_forever(value: NodePtr) -> NodePtr {
+    lazy(move |_| Ok(cons(value.clone(), repeat_forever(value.clone()))))
+}
+
+fn repeat(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("repeat", args, 1, Some(2))?;
+    match args.len() {
+        1 => Ok(repeat_forever(args[0].clone())),
+        _ => Ok(take_from(index_arg("repeat", &args[0])?, repeat_forever(args[1].clone())))
+    }
+}
+
+/// Elements of `current`, starting over from `coll` every time it runs out.
+fn cycle_from(coll: NodePtr, current: NodePtr) -> NodePtr {
+    lazy(move |context| {
+        let mut items = seq::seq(context, &current)?;
+        if let Node::Nil = items.as_ref() {
+            items = seq::seq(context, &coll)?;
+            if let Node::Nil = items.as_ref() {
+                return Ok(items);
+            }
+        }
+        let rest = seq::rest(context, &items)?;
+        Ok(cons(seq::first(context, &items)?, cycle_from(coll.clone(), rest)))
+    })
+}
+
+fn cycle(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("cycle", args, 1, Some(1))?;
+    Ok(cycle_from(args[0].clone(), args[0].clone()))
+}
+
+fn take_from(count: usize, coll: NodePtr) -> NodePtr {
+    lazy(move |context| {
+        if count == 0 {
+            return Ok(Rc::new(Node::Nil));
+        }
+        let items = seq::seq(context, &coll)?;
+        if let Node::Nil = items.as_ref() {
+            return Ok(items);
+        }
+        let rest = seq::rest(context, &items)?;
+        Ok(cons(seq::first(context, &items)?, take_from(count - 1, rest)))
+    })
+}
+
+fn take(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("take", args, 2, Some(2))?;
+    Ok(take_from(index_arg("take", &args[0])?, args[1].clone()))
+}
+
+fn drop(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("drop", args, 2, Some(2))?;
+    let count = index_arg("drop", &args[0])?;
+    let coll = args[1].clone();
+    Ok(lazy(move |context| {
+        let mut items = seq::seq(context, &coll)?;
+        for _ in 0..count {
+            if let Node::Nil = items.as_ref() {
+                break;
+            }
+            items = seq::next(context, &items)?;
+        }
+        Ok(items)
+    }))
+}
+
+fn call_predicate(context: &mut EvalContext, predicate: &NodePtr, value: &NodePtr) -> Result<bool, EvalError> {
+    Ok(apply(context, predicate, &list_from(vec![value.clone()]))?.is_truthy())
+}
+
+fn take_while_from(predicate: NodePtr, coll: NodePtr) -> NodePtr {
+    lazy(move |context| {
+        let items = seq::seq(context, &coll)?;
+        if let Node::Nil = items.as_ref() {
+            return Ok(items);
+        }
+        let first = seq::first(context, &items)?;
+        if !call_predicate(context, &predicate, &first)? {
+            return Ok(Rc::new(Node::Nil));
+        }
+        let rest = seq::rest(context, &items)?;
+        Ok(cons(first, take_while_from(predicate.clone(), rest)))
+    })
+}
+
+fn take_while(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("take-while", args, 2, Some(2))?;
+    Ok(take_while_from(args[0].clone(), args[1].clone()))
+}
+
+fn drop_while(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("drop-while", args, 2, Some(2))?;
+    let (predicate, coll) = (args[0].clone(), args[1].clone());
+    Ok(lazy(move |context| {
+        let mut items = seq::seq(context, &coll)?;
+        while !matches!(items.as_ref(), Node::Nil) {
+            let first = seq::first(context, &items)?;
+            if !call_predicate(context, &predicate, &first)? {
+                break;
+            }
+            items = seq::next(context, &items)?;
+        }
+        Ok(items)
+    }))
+}
+
+/// Applies `function` to the first elements of every collection, then to the second ones,
+/// and so on until one of the collections runs out.
+fn map_from(function: NodePtr, colls: Vec<NodePtr>) -> NodePtr {
+    lazy(move |context| {
+        let mut firsts = Vec::with_capacity(colls.len());
+        let mut rests = Vec::with_capacity(colls.len());
+        for coll in &colls {
+            let items = seq::seq(context, coll)?;
+            if let Node::Nil = items.as_ref() {
+                return Ok(items);
+            }
+            firsts.push(seq::first(context, &items)?);
+            rests.push(seq::rest(context, &items)?);
+        }
+        let value = apply(context, &function, &list_from(firsts))?;
+        Ok(cons(value, map_from(function.clone(), rests)))
+    })
+}
+
+fn map(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("map", args, 2, None)?;
+    Ok(map_from(args[0].clone(), args[1..].to_vec()))
+}
+
+/// Elements of `coll` for which `predicate` is truthy, or falsy if `keep` isn't set.
+fn filter_from(predicate: NodePtr, coll: NodePtr, keep: bool) -> NodePtr {
+    lazy(move |context| {
+        let mut items = seq::seq(context, &coll)?;
+        while !matches!(items.as_ref(), Node::Nil) {
+            let first = seq::first(context, &items)?;
+            if call_predicate(context, &predicate, &first)? == keep {
+                let rest = seq::rest(context, &items)?;
+                return Ok(cons(first, filter_from(predicate.clone(), rest, keep)));
+            }
+            items = seq::next(context, &items)?;
+        }
+        Ok(items)
+    })
+}
+
+fn filter(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("filter", args, 2, Some(2))?;
+    Ok(filter_from(args[0].clone(), args[1].clone(), true))
+}
+
+fn remove(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("remove", args, 2, Some(2))?;
+    Ok(filter_from(args[0].clone(), args[1].clone(), false))
+}
+
+/// Elements of every coll in turn. The last one is returned as it is once the others are
+/// used up, so that in right-nested concats like `(concat a (concat b c))` the elements of the
+/// inner ones don't go through every level.
+fn concat_from(colls: Vec<NodePtr>) -> NodePtr {
+    lazy(move |context| {
+        for (index, coll) in colls.iter().enumerate() {
+            if index + 1 == colls.len() {
+                return Ok(coll.clone());
+            }
+            let items = seq::seq(context, coll)?;
+            if let Node::Nil = items.as_ref() {
+                continue;
+            }
+            let mut rests = vec![seq::rest(context, &items)?];
+            rests.extend_from_slice(&colls[index + 1..]);
+            return Ok(cons(seq::first(context, &items)?, concat_from(rests)));
+        }
+        Ok(Rc::new(Node::Nil))
+    })
+}
+
+fn concat(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    Ok(concat_from(args.list_elements()?))
+}
+
+fn doall(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("doall", args, 1, Some(1))?;
+    let mut iter = SeqIter::new(&args[0]);
+    while iter.next(context)?.is_some() {}
+    Ok(args[0].clone())
+}
+
+fn dorun(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    doall(context, args)?;
+    Ok(Rc::new(Node::Nil))
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("seq"), seq);
+    builtins.insert(Symbol::intern("first"), first);
+    builtins.insert(Symbol::intern("rest"), rest);
+    builtins.insert(Symbol::intern("next"), next);
+    builtins.insert(Symbol::intern("cons"), cons_builtin);
+    builtins.insert(Symbol::intern("list"), list);
+    builtins.insert(Symbol::intern("seq?"), is_seq);
+    builtins.insert(Symbol::intern("empty?"), is_empty);
+    builtins.insert(Symbol::intern("realized?"), is_realized);
+    builtins.insert(Symbol::intern("range"), range);
+    builtins.insert(Symbol::intern("iterate"), iterate);
+    builtins.insert(Symbol::intern("repeat"), repeat);
+    builtins.insert(Symbol::intern("cycle"), cycle);
+    builtins.insert(Symbol::intern("take"), take);
+    builtins.insert(Symbol::intern("drop"), drop);
+    builtins.insert(Symbol::intern("take-while"), take_while);
+    builtins.insert(Symbol::intern("drop-while"), drop_while);
+    builtins.insert(Symbol::intern("map"), map);
+    builtins.insert(Symbol::intern("filter"), filter);
+    builtins.insert(Symbol::intern("remove"), remove);
+    builtins.insert(Symbol::intern("concat"), concat);
+    builtins.insert(Symbol::intern("doall"), doall);
+    builtins.insert(Symbol::intern("dorun"), dorun);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn seq_protocol() {
+        assert_eq!(eval("[(seq [1 2]) (seq {:a 1}) (seq #{1}) (seq \"ab\") (seq nil) (seq []) (seq '())]"), "[(1 2) ([:a 1]) (1) (\\a \\b) nil nil nil]");
+        assert_eq!(eval("[(first [1 2]) (rest [1 2]) (next [1]) (rest [1]) (first \"ab\") (rest nil) (first nil)]"), "[1 (2) nil () \\a () nil]");
+        assert_eq!(eval_err("(first 5)"), "seq: cannot create a seq from 5");
+        assert_eq!(eval("[(seq \"héllo\") (rest (sorted-map :b 2 :a 1)) (next #{1})]"), "[(\\h \\é \\l \\l \\o) ([:b 2]) nil]");
+        let big = "(def big (into {} (for [i (range 2000)] [i i])))";
+        assert_eq!(eval(&format!("{} [(count (seq big)) (= (set (keys big)) (set (range 2000))) (= (first big) (first (seq big)))]", big)), "[2000 true true]");
+    }
+
+    #[test]
+    fn cons_keeps_a_seq_as_its_rest() {
+        assert_eq!(eval("[(cons 0 [1 2]) (count (cons 0 [1 2])) (rest (cons 1 [2 3])) (= (cons 0 [1 2]) '(0 1 2))]"), "[(0 1 2) 3 (2 3) true]");
+        assert_eq!(eval("[(cons 1 (lazy-seq [2])) (rest (cons 1 nil)) (next (cons 1 nil))]"), "[(1 2) () nil]");
+        assert_eq!(eval_err("(cons 1 2)"), "seq: cannot create a seq from 2");
+    }
+
+    #[test]
+    fn macros_can_build_forms_with_seqs() {
+        assert_eq!(eval("(defmacro m [] (cons 'list [1 2])) (m)"), "(1 2)");
+        assert_eq!(eval("(defmacro m [& xs] (concat (list 'vector) (map (fn [x] (list 'inc x)) xs))) (m 1 2)"), "[2 3]");
+    }
+
+    #[test]
+    fn infinite_seqs() {
+        assert_eq!(eval("(take 5 (range))"), "(0 1 2 3 4)");
+        assert_eq!(eval("(take 5 (iterate inc 10))"), "(10 11 12 13 14)");
+        assert_eq!(eval("(take 3 (repeat :x))"), "(:x :x :x)");
+        assert_eq!(eval("(take 5 (cycle [1 2]))"), "(1 2 1 2 1)");
+        assert_eq!(eval("(defn nums [n] (lazy-seq (cons n (nums (inc n))))) (take 3 (nums 0))"), "(0 1 2)");
+        assert_eq!(eval("[(nth (iterate inc 0) 100000) (first (drop 100000 (cycle [1 2 3])))]"), "[100000 2]");
+    }
+
+    #[test]
+    fn lazy_seqs_are_realized_once() {
+        assert_eq!(eval("(def calls (transient [])) (def s (lazy-seq (conj! calls 1) [1 2])) [(first s) (first s) (count calls)]"), "[1 1 1]");
+    }
+
+    #[test]
+    fn nested_concats_are_linear() {
+        assert_eq!(eval("(count (doall (loop [i 0 acc ()] (if (< i 20000) (recur (inc i) (concat [i] acc)) acc))))"), "20000");
+    }
+
+    #[test]
+    fn lazy_seqs_returning_lazy_seqs_are_realized_in_a_loop() {
+        assert_eq!(eval("(defn f [n] (lazy-seq (if (< n 100000) (f (inc n)) [n]))) (f 0)"), "(100000)");
+    }
+}
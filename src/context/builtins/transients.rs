@@ -0,0 +1,191 @@
+use crate::nodes::{NodePtr, Node, TransientCollection};
+use crate::intern::{Symbol, SymbolMap};
+use crate::eval::{EvalResult, EvalError};
+use crate::context::EvalContext;
+use crate::collections::Transient;
+use std::rc::Rc;
+
+use super::{Builtin, arguments, index_arg};
+
+fn transient(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("transient", args, 1, Some(1))?;
+    let collection = match args[0].as_ref() {
+        Node::Vector(vec) => TransientCollection::Vector(vec.clone()),
+        Node::Set(set) => TransientCollection::Set(set.clone()),
+        Node::Map(map) => TransientCollection::Map(map.clone()),
+        node => return Err(EvalError::type_error(&format!("transient: cannot create a transient from {}", node)))
+    };
+    Ok(Rc::new(Node::Transient(Transient::new(collection))))
+}
+
+fn persistent(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("persistent!", args, 1, Some(1))?;
+    match args[0].as_ref() {
+        Node::Transient(transient) => match transient.persistent() {
+            Some(TransientCollection::Vector(vec)) => Ok(Rc::new(Node::Vector(vec))),
+            Some(TransientCollection::Set(set)) => Ok(Rc::new(Node::Set(set))),
+            Some(TransientCollection::Map(map)) => Ok(Rc::new(Node::Map(map))),
+            None => Err(used_after_persistent("persistent!"))
+        },
+        node => Err(EvalError::type_error(&format!("persistent!: expected a transient but got {}", node)))
+    }
+}
+
+fn used_after_persistent(name: &str) -> EvalError {
+    EvalError::other(&format!("{}: transient used after persistent! call", name))
+}
+
+/// Returns the current contents of `node` if it's a transient, for builtins reading
+/// collections like `get` or `count`, or else `node` itself. The contents share their
+/// structure with the transient until either of them changes.
+pub(super) fn read_transient(name: &str, node: &NodePtr) -> EvalResult {
+    match node.as_ref() {
+        Node::Transient(transient) => {
+            let collection = transient.read(|collection| match collection {
+                TransientCollection::Vector(vec) => Node::Vector(vec.clone()),
+                TransientCollection::Set(set) => Node::Set(set.clone()),
+                TransientCollection::Map(map) => Node::Map(map.clone())
+            });
+            collection.map(Rc::new).ok_or_else(|| used_after_persistent(name))
+        },
+        _ => Ok(node.clone())
+    }
+}
+
+/// Applies `edit` to the collection behind a transient, and returns the transient itself.
+fn edit_transient<F>(name: &str, args: &[NodePtr], edit: F) -> EvalResult
+    where F: FnOnce(&mut TransientCollection, &[NodePtr]) -> Result<(), EvalError>
+{
+    match args[0].as_ref() {
+        Node::Transient(transient) => {
+            transient.edit(|collection| edit(collection, &args[1..]))
+                .ok_or_else(|| used_after_persistent(name))??;
+            Ok(args[0].clone())
+        },
+        node => Err(EvalError::type_error(&format!("{}: expected a transient but got {}", name, node)))
+    }
+}
+
+fn conj(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("conj!", args, 1, None)?;
+    edit_transient("conj!", &args, |collection, values| {
+        for value in values {
+            match collection {
+                TransientCollection::Vector(vec) => vec.push_mut(value.clone()),
+                TransientCollection::Set(set) => {
+                    set.insert_mut(value.clone());
+                },
+                TransientCollection::Map(map) => match value.as_ref() {
+                    Node::Vector(entry) if entry.len() == 2 => {
+                        map.insert_mut(entry.get(0).unwrap().clone(), entry.get(1).unwrap().clone());
+                    },
+                    node => return Err(EvalError::type_error(&format!("conj!: expected a [key value] vector but got {}", node)))
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+fn assoc(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("assoc!", args, 3, None)?;
+    if args.len() % 2 == 0 {
+        return Err(EvalError::other("assoc!: expected an even number of keys and values"));
+    }
+    edit_transient("assoc!", &args, |collection, pairs| {
+        for pair in pairs.chunks(2) {
+            match collection {
+                TransientCollection::Vector(vec) => {
+                    let index = index_arg("assoc!", &pair[0])?;
+                    if !vec.set_mut(index, pair[1].clone()) {
+                        return Err(EvalError::out_of_bounds(&format!("assoc!: index {} out of bounds", index)));
+                    }
+                },
+                TransientCollection::Map(map) => {
+                    map.insert_mut(pair[0].clone(), pair[1].clone());
+                },
+                TransientCollection::Set(_) => return Err(EvalError::type_error("assoc!: not supported on transient sets"))
+            }
+        }
+        Ok(())
+    })
+}
+
+fn dissoc(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("dissoc!", args, 1, None)?;
+    edit_transient("dissoc!", &args, |collection, keys| match collection {
+        TransientCollection::Map(map) => {
+            for key in keys {
+                map.remove_mut(key);
+            }
+            Ok(())
+        },
+        _ => Err(EvalError::type_error("dissoc!: only supported on transient maps"))
+    })
+}
+
+fn disj(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("disj!", args, 1, None)?;
+    edit_transient("disj!", &args, |collection, values| match collection {
+        TransientCollection::Set(set) => {
+            for value in values {
+                set.remove_mut(value);
+            }
+            Ok(())
+        },
+        _ => Err(EvalError::type_error("disj!: only supported on transient sets"))
+    })
+}
+
+fn pop(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("pop!", args, 1, Some(1))?;
+    edit_transient("pop!", &args, |collection, _| match collection {
+        TransientCollection::Vector(vec) => {
+            if vec.pop_mut() {
+                Ok(())
+            } else {
+                Err(EvalError::out_of_bounds("pop!: can't pop an empty vector"))
+            }
+        },
+        _ => Err(EvalError::type_error("pop!: only supported on transient vectors"))
+    })
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("transient"), transient);
+    builtins.insert(Symbol::intern("persistent!"), persistent);
+    builtins.insert(Symbol::intern("conj!"), conj);
+    builtins.insert(Symbol::intern("assoc!"), assoc);
+    builtins.insert(Symbol::intern("dissoc!"), dissoc);
+    builtins.insert(Symbol::intern("disj!"), disj);
+    builtins.insert(Symbol::intern("pop!"), pop);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn batch_construction() {
+        assert_eq!(eval("(persistent! (loop [t (transient []) i 0] (if (< i 100) (recur (conj! t i) (inc i)) t)))"), eval("(vec (range 100))"));
+        assert_eq!(eval("(persistent! (assoc! (transient {:a 1}) :b 2))"), "{:a 1, :b 2}");
+        assert_eq!(eval("(persistent! (dissoc! (transient {:a 1 :b 2}) :a))"), "{:b 2}");
+        assert_eq!(eval("(persistent! (disj! (transient #{1 2}) 1))"), "#{2}");
+        assert_eq!(eval("(persistent! (pop! (transient [1 2 3])))"), "[1 2]");
+        assert_eq!(eval("(let [v [1 2] t (transient v)] (conj! t 3) v)"), "[1 2]");
+    }
+
+    #[test]
+    fn reads() {
+        assert_eq!(eval("(let [t (transient [1 2])] [(count t) (nth t 1) (get t 0)])"), "[2 2 1]");
+        assert_eq!(eval("(let [t (transient {:a 1})] [(get t :a) (contains? t :a) (count t)])"), "[1 true 1]");
+    }
+
+    #[test]
+    fn use_after_persistent_fails() {
+        assert_eq!(eval_err("(let [t (transient [1])] (persistent! t) (conj! t 2))"), "conj!: transient used after persistent! call");
+        assert_eq!(eval_err("(let [t (transient [1])] (persistent! t) (persistent! t))"), "persistent!: transient used after persistent! call");
+        assert_eq!(eval_err("(let [t (transient [1])] (persistent! t) (count t))"), "count: transient used after persistent! call");
+        assert_eq!(eval_err("(transient '(1))"), "transient: cannot create a transient from (1)");
+    }
+}
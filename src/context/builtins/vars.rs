@@ -0,0 +1,79 @@
+use crate::nodes::{NodePtr, Node, list_from};
+use crate::intern::{Symbol, SymbolMap};
+use crate::eval::{EvalResult, EvalError, ErrorKind, apply};
+use crate::context::{EvalContext, Var};
+use std::rc::Rc;
+
+use super::{Builtin, arguments};
+
+fn var_arg(name: &str, node: &NodePtr) -> Result<Rc<Var>, EvalError> {
+    match node.as_ref() {
+        Node::Var(var) => Ok(var.clone()),
+        node => Err(EvalError::type_error(&format!("{}: expected a var but got {}", name, node)))
+    }
+}
+
+fn is_var(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("var?", args, 1, Some(1))?;
+    Ok(Rc::new(Node::Bool(matches!(args[0].as_ref(), Node::Var(_)))))
+}
+
+fn var_get(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("var-get", args, 1, Some(1))?;
+    let var = var_arg("var-get", &args[0])?;
+    var.get().ok_or_else(|| EvalError::new(ErrorKind::UnboundVar(var.name())))
+}
+
+fn is_bound(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("bound?", args, 1, None)?;
+    let vars = args.iter().map(|arg| var_arg("bound?", arg)).collect::<Result<Vec<Rc<Var>>, EvalError>>()?;
+    Ok(Rc::new(Node::Bool(vars.iter().all(|var| var.is_bound()))))
+}
+
+/// `(alter-var-root var f args...)` sets the value of `var` to `(f value args...)` and
+/// returns it.
+fn alter_var_root(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let mut args = arguments("alter-var-root", args, 2, None)?;
+    let var = var_arg("alter-var-root", &args[0])?;
+    let value = var.get().unwrap_or_else(|| Rc::new(Node::Nil));
+    let function = args[1].clone();
+    args.splice(0..2, [value]);
+    let value = apply(context, &function, &list_from(args))?;
+    var.set(value.clone());
+    Ok(value)
+}
+
+pub fn populate(builtins: &mut SymbolMap<Builtin>) {
+    builtins.insert(Symbol::intern("var?"), is_var);
+    builtins.insert(Symbol::intern("var-get"), var_get);
+    builtins.insert(Symbol::intern("bound?"), is_bound);
+    builtins.insert(Symbol::intern("alter-var-root"), alter_var_root);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn redefinitions_are_seen_by_callers() {
+        assert_eq!(eval("(declare h) (defn k [] (h)) (defn h [] 1) (def a (k)) (defn h [] 2) [a (k)]"), "[1 2]");
+        assert_eq!(eval_err("(declare u) u"), "Var user/u is unbound");
+    }
+
+    #[test]
+    fn defonce_keeps_the_first_value() {
+        assert_eq!(eval("(defonce o 1) (defonce o 2) o"), "1");
+    }
+
+    #[test]
+    fn var_indirection() {
+        assert_eq!(eval("(defn h [] 1) [#'h (var h) (#'h)]"), "[#'user/h #'user/h 1]");
+        assert_eq!(eval("(def o 1) [(alter-var-root #'o inc) o]"), "[2 2]");
+    }
+
+    #[test]
+    fn qualified_names() {
+        assert_eq!(eval("(def x 1) (defn f [] 2) [user/x (user/f) (lispure.core/when true 3)]"), "[1 2 3]");
+        assert_eq!(eval_err("(def lispure.core/x 1)"), "def: can't define lispure.core/x outside of the current namespace");
+    }
+}
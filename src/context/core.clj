@@ -0,0 +1,261 @@
+(defmacro when
+  "Evaluates the body if test is true, else returns nil."
+  [test & body]
+  `(if ~test (do ~@body)))
+
+(defmacro when-not
+  "Evaluates the body if test is false or nil, else returns nil."
+  [test & body]
+  `(if ~test nil (do ~@body)))
+
+(defmacro if-not
+  "Evaluates then if test is false or nil, else the other branch."
+  ([test then] `(if ~test nil ~then))
+  ([test then else] `(if ~test ~else ~then)))
+
+(defmacro and
+  "Evaluates the forms in order until one is false or nil, and returns the last value."
+  ([] true)
+  ([x] x)
+  ([x & more] `(let [and# ~x] (if and# (and ~@more) and#))))
+
+(defmacro or
+  "Evaluates the forms in order until one is true, and returns the last value."
+  ([] nil)
+  ([x] x)
+  ([x & more] `(let [or# ~x] (if or# or# (or ~@more)))))
+
+(defmacro cond
+  "Takes pairs of a test and an expression, and evaluates the one of the first true test."
+  [& clauses]
+  (when clauses
+    (if (next clauses)
+      `(if ~(first clauses) ~(nth clauses 1) (cond ~@(drop 2 clauses)))
+      (throw (ex-info "cond: expected an even number of forms" {})))))
+
+(defmacro condp
+  "Evaluates the expression of the first clause whose test gives true with (pred test expr)."
+  [pred expr & clauses]
+  (let [p (gensym "pred__")
+        value (gensym "expr__")
+        emit (fn emit [clauses]
+               (let [n (count clauses)
+                     [test then f] clauses]
+                 (cond
+                   (= 0 n) `(throw (ex-info "condp: no matching clause" {:value ~value}))
+                   (= 1 n) test
+                   (= :>> then) (if (< n 3)
+                                  (throw (ex-info "condp: expected a function after :>>" {}))
+                                  `(let [result# (~p ~test ~value)]
+                                     (if result# (~f result#) ~(emit (drop 3 clauses)))))
+                   :else `(if (~p ~test ~value) ~then ~(emit (drop 2 clauses))))))]
+    `(let [~p ~pred ~value ~expr] ~(emit clauses))))
+
+(defmacro case
+  "Evaluates the expression of the clause whose constant, or list of constants, has the value of expr."
+  [expr & clauses]
+  (let [value (gensym "case__")]
+    (loop [clauses clauses
+           table {}]
+      (cond
+        (nil? clauses)
+        `(let [~value ~expr]
+           (case* ~value ~table (throw (ex-info "case: no matching clause" {:value ~value}))))
+
+        (nil? (next clauses))
+        `(let [~value ~expr] (case* ~value ~table ~(first clauses)))
+
+        :else
+        (let [[test then & more] clauses]
+          (recur more
+                 (loop [constants (if (seq? test) test (list test))
+                        table table]
+                   (if constants
+                     (if (contains? table (first constants))
+                       (throw (ex-info "case: duplicate constant" {:constant (first constants)}))
+                       (recur (next constants) (assoc table (first constants) then)))
+                     table))))))))
+
+(defmacro if-let
+  "Binds the form to the value of test and evaluates then if it's true, else the other branch."
+  ([bindings then] `(if-let ~bindings ~then nil))
+  ([bindings then else]
+   (when-not (= 2 (count bindings))
+     (throw (ex-info "if-let: expected a binding vector with one form and one test" {})))
+   `(let [temp# ~(nth bindings 1)]
+      (if temp# (let [~(first bindings) temp#] ~then) ~else))))
+
+(defmacro when-let
+  "Binds the form to the value of test and evaluates the body if the value is true."
+  [bindings & body]
+  (when-not (= 2 (count bindings))
+    (throw (ex-info "when-let: expected a binding vector with one form and one test" {})))
+  `(let [temp# ~(nth bindings 1)]
+     (when temp# (let [~(first bindings) temp#] ~@body))))
+
+(defmacro if-some
+  "Like if-let, but evaluates then for every value except nil, including false."
+  ([bindings then] `(if-some ~bindings ~then nil))
+  ([bindings then else]
+   (when-not (= 2 (count bindings))
+     (throw (ex-info "if-some: expected a binding vector with one form and one test" {})))
+   `(let [temp# ~(nth bindings 1)]
+      (if (nil? temp#) ~else (let [~(first bindings) temp#] ~then)))))
+
+(defmacro when-some
+  "Like when-let, but evaluates the body for every value except nil, including false."
+  [bindings & body]
+  (when-not (= 2 (count bindings))
+    (throw (ex-info "when-some: expected a binding vector with one form and one test" {})))
+  `(let [temp# ~(nth bindings 1)]
+     (if (nil? temp#) nil (let [~(first bindings) temp#] ~@body))))
+
+(defmacro ->
+  "Threads x through the forms, as the first argument of each one."
+  [x & forms]
+  (loop [x x
+         forms forms]
+    (if forms
+      (let [form (first forms)]
+        (recur (if (seq? form) `(~(first form) ~x ~@(next form)) (list form x))
+               (next forms)))
+      x)))
+
+(defmacro ->>
+  "Like ->, but inserts x as the last argument of the forms."
+  [x & forms]
+  (loop [x x
+         forms forms]
+    (if forms
+      (let [form (first forms)]
+        (recur (if (seq? form) `(~@form ~x) (list form x))
+               (next forms)))
+      x)))
+
+(defmacro as->
+  "Binds name to expr, then to the result of each form in turn, and returns the last one."
+  [expr name & forms]
+  (loop [bindings [name expr]
+         forms forms]
+    (if forms
+      (recur (conj bindings name (first forms)) (next forms))
+      `(let ~bindings ~name))))
+
+(defmacro some->
+  "Like ->, but stops threading and returns nil as soon as a form returns nil."
+  [expr & forms]
+  (let [value (gensym "some__")]
+    (loop [bindings [value expr]
+           forms forms]
+      (if forms
+        (recur (conj bindings value `(if (nil? ~value) nil (-> ~value ~(first forms))))
+               (next forms))
+        `(let ~bindings ~value)))))
+
+(defmacro cond->
+  "Takes pairs of a test and a form, and threads expr through the forms whose test is true."
+  [expr & clauses]
+  (when (= 1 (rem (count clauses) 2))
+    (throw (ex-info "cond->: expected an even number of forms" {})))
+  (let [value (gensym "cond__")]
+    (loop [bindings [value expr]
+           clauses clauses]
+      (if clauses
+        (recur (conj bindings value `(if ~(first clauses) (-> ~value ~(nth clauses 1)) ~value))
+               (next (next clauses)))
+        `(let ~bindings ~value)))))
+
+(defmacro doto
+  "Evaluates x, then calls the forms with it as their first argument, and returns it."
+  [x & forms]
+  (let [value (gensym "doto__")]
+    `(let [~value ~x]
+       ~@(map (fn [form] (if (seq? form) `(~(first form) ~value ~@(next form)) (list form value))) forms)
+       ~value)))
+
+(defmacro while
+  "Evaluates the body again and again for as long as test is true, and returns nil."
+  [test & body]
+  `(loop [] (when ~test ~@body (recur))))
+
+(defmacro dotimes
+  "Evaluates the body with name bound to each integer from 0 to n, excluded, and returns nil."
+  [bindings & body]
+  (when-not (= 2 (count bindings))
+    (throw (ex-info "dotimes: expected a binding vector with one name and one count" {})))
+  `(let [n# ~(nth bindings 1)]
+     (loop [i# 0]
+       (when (< i# n#)
+         (let [~(first bindings) i#] ~@body)
+         (recur (inc i#))))))
+
+(defmacro doseq
+  "Evaluates the body for each element of the seqs, bound like in for, and returns nil."
+  [bindings & body]
+  (when (= 1 (rem (count bindings) 2))
+    (throw (ex-info "doseq: expected an even number of forms in the binding vector" {})))
+  (let [emit (fn emit [bindings]
+               (if bindings
+                 (let [[form value & more] bindings
+                       inner (emit more)]
+                   (cond
+                     (= :let form) `(let ~value ~inner)
+                     (= :when form) `(if ~value ~inner true)
+                     (= :while form) `(if ~value ~inner false)
+                     (keyword? form) (throw (ex-info "doseq: unknown modifier" {:modifier form}))
+                     :else `(loop [s# (seq ~value)]
+                              (if s#
+                                (let [~form (first s#)]
+                                  (if ~inner (recur (next s#)) true))
+                                true))))
+                 `(do ~@body true)))]
+    `(do ~(emit (seq bindings)) nil)))
+
+(defmacro for
+  "Returns a lazy seq of the values of the body for each element of the seqs, the last one varying fastest."
+  [bindings body]
+  (when (= 1 (rem (count bindings) 2))
+    (throw (ex-info "for: expected an even number of forms in the binding vector" {})))
+  (let [levels (loop [pairs (seq bindings)
+                      levels []]
+                 (if pairs
+                   (let [[form value & more] pairs]
+                     (cond
+                       (not (keyword? form))
+                       (recur more (conj levels [form value []]))
+
+                       (not (contains? #{:let :when :while} form))
+                       (throw (ex-info "for: unknown modifier" {:modifier form}))
+
+                       (empty? levels)
+                       (throw (ex-info "for: expected a binding before the modifiers" {:modifier form}))
+
+                       :else
+                       (let [[bound coll modifiers] (peek levels)]
+                         (recur more (conj (pop levels) [bound coll (conj modifiers [form value])])))))
+                   levels))
+        emit (fn emit [levels after]
+               (let [[[form coll modifiers] & more] levels
+                     step (gensym "step__")
+                     s (gensym "s__")
+                     skip `(recur (next ~s))
+                     produce (if more
+                               (emit more `(~step (next ~s)))
+                               `(cons ~body (~step (next ~s))))
+                     modify (fn modify [modifiers]
+                              (if modifiers
+                                (let [[[kind value] & more] modifiers
+                                      inner (modify more)]
+                                  (cond
+                                    (= :let kind) `(let ~value ~inner)
+                                    (= :when kind) `(if ~value ~inner ~skip)
+                                    :else `(if ~value ~inner ~after)))
+                                produce))]
+                 `((fn ~step [~s]
+                     (lazy-seq
+                       (loop [~s (seq ~s)]
+                         (if ~s
+                           (let [~form (first ~s)] ~(modify (seq modifiers)))
+                           ~after))))
+                   ~coll)))]
+    (emit (seq levels) nil)))
@@ -0,0 +1,135 @@
+use crate::nodes::{NodePtr, Node};
+use crate::intern::{Symbol, SymbolMap};
+use crate::eval::{Function, Lambda};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// What a name is bound to in a frame.
+enum Binding {
+    Value(NodePtr),
+    /// Function that closes over the frame holding it, like a named `fn` referring to itself.
+    /// Only its code is stored, and a closure over the frame is created when it's looked up,
+    /// so the frame never holds a reference to itself and can be freed.
+    Recursive(Rc<Lambda>)
+}
+
+/// Lexical environment holding local bindings. Frames are shared by reference between the
+/// code running in them and the closures created there, so a binding added to a frame later
+/// is visible to every closure over it.
+pub struct Frame {
+    bindings: RefCell<SymbolMap<Binding>>,
+    parent: Option<Rc<Frame>>
+}
+
+impl Frame {
+
+    pub fn new(parent: Option<Rc<Frame>>) -> Self {
+        Self {
+            bindings: RefCell::new(SymbolMap::default()),
+            parent
+        }
+    }
+
+    pub fn set(&self, name: Symbol, value: NodePtr) {
+        self.bindings.borrow_mut().insert(name, Binding::Value(value));
+    }
+
+    /// Binds `name` to a closure over this frame running `lambda`.
+    pub fn set_recursive(&self, name: Symbol, lambda: Rc<Lambda>) {
+        self.bindings.borrow_mut().insert(name, Binding::Recursive(lambda));
+    }
+
+    /// Finds the value bound to `name` in `frame` or the frames enclosing it.
+    pub fn lookup(frame: &Rc<Frame>, name: Symbol) -> Option<NodePtr> {
+        let mut frame = frame;
+        loop {
+            match frame.bindings.borrow().get(&name) {
+                Some(Binding::Value(value)) => return Some(value.clone()),
+                Some(Binding::Recursive(lambda)) => {
+                    return Some(Rc::new(Node::Function(Function::new(lambda.clone(), frame.clone()))));
+                },
+                None => ()
+            }
+            frame = frame.parent.as_ref()?;
+        }
+    }
+
+    /// Lists the names bound in `frame` and the frames enclosing it, with their values.
+    /// Shadowed names are only listed once.
+    pub fn locals(frame: &Rc<Frame>) -> Vec<(Symbol, NodePtr)> {
+        let mut names = Vec::new();
+        let mut current = Some(frame);
+        while let Some(frame) = current {
+            for name in frame.bindings.borrow().keys() {
+                if !names.contains(name) {
+                    names.push(*name);
+                }
+            }
+            current = frame.parent.as_ref();
+        }
+        names.into_iter().filter_map(|name| Some((name, Frame::lookup(frame, name)?))).collect()
+    }
+
+}
+
+impl std::fmt::Debug for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Frame [")?;
+        let bindings = self.bindings.borrow();
+        let mut names = bindings.keys().peekable();
+        while let Some(name) = names.next() {
+            write!(f, "{}", name)?;
+            if names.peek().is_some() {
+                write!(f, ", ")?;
+            }
+        }
+        write!(f, "]")?;
+        if let Some(parent) = &self.parent {
+            write!(f, " -> {:?}", parent)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Frame {
+    // Long chains of frames are freed iteratively rather than recursively.
+    fn drop(&mut self) {
+        let mut parent = self.parent.take();
+        while let Some(frame) = parent {
+            parent = match Rc::try_unwrap(frame) {
+                Ok(mut frame) => frame.parent.take(),
+                Err(_) => None
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inner_bindings_shadow_outer_ones() {
+        let (x, y) = (Symbol::intern("x"), Symbol::intern("y"));
+        let outer = Rc::new(Frame::new(None));
+        outer.set(x, Rc::new(Node::Integer(1)));
+        let inner = Rc::new(Frame::new(Some(outer.clone())));
+        inner.set(x, Rc::new(Node::Integer(2)));
+        assert_eq!(Frame::lookup(&inner, x), Some(Rc::new(Node::Integer(2))));
+        assert_eq!(Frame::lookup(&outer, x), Some(Rc::new(Node::Integer(1))));
+        assert_eq!(Frame::lookup(&inner, y), None);
+        // Bindings added to a shared frame later are seen through it.
+        outer.set(y, Rc::new(Node::Integer(3)));
+        assert_eq!(Frame::lookup(&inner, y), Some(Rc::new(Node::Integer(3))));
+        assert_eq!(Frame::locals(&inner).len(), 2);
+    }
+
+    #[test]
+    fn long_chains_are_dropped_without_recursion() {
+        let mut frame = Rc::new(Frame::new(None));
+        for _ in 0..1_000_000 {
+            frame = Rc::new(Frame::new(Some(frame)));
+        }
+        drop(frame);
+    }
+}
@@ -1,21 +1,47 @@
 mod builtins;
+mod frame;
+mod var;
 
 use crate::nodes::NodePtr;
-use std::collections::HashMap;
+use crate::intern::{Symbol, SymbolMap};
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
-use self::builtins::Builtin;
+pub use self::frame::Frame;
+pub use self::var::Var;
+pub use self::builtins::Builtin;
 use crate::context::builtins::populate_builtins;
+use crate::parser::{tokenize, parse_file};
+use crate::eval::eval_file;
 
-#[derive(Debug)]
+/// Namespace of the core macros, whose vars can be used from every namespace.
+pub const CORE_NAMESPACE: &str = "lispure.core";
+
+/// Source of the core macros, like `when`, `cond`, `->` and `for`.
+const CORE_SOURCE: &str = include_str!("core.clj");
+
+/// Where code is evaluated: the frame holding its locals and the root context shared by
+/// the whole program. Cloning a context shares its frame.
+#[derive(Debug, Clone)]
 pub struct EvalContext {
-    variables: HashMap<String, NodePtr>,
-    parent: Option<Rc<EvalContext>>,
+    frame: Rc<Frame>,
     root: Rc<RootContext>
 }
 
 pub struct RootContext {
-    builtins: HashMap<String, Builtin>,
+    builtins: SymbolMap<Builtin>,
+    /// Vars defined with `def`, by namespace and then by name without the namespace, so that
+    /// finding the var of a name doesn't build its qualified name.
+    vars: RefCell<SymbolMap<SymbolMap<Rc<Var>>>>,
+    /// Interned `CORE_NAMESPACE`.
+    core: Symbol,
+    /// Namespace that code is read in, used to resolve keywords like `::id`.
+    namespace: Cell<Symbol>,
+    /// Counter making the names returned by `gensym` unique.
+    next_id: Cell<u64>,
+    /// Most continuations an evaluation can have on its stack, or `None` to only be limited
+    /// by memory.
+    max_depth: Cell<Option<usize>>
 }
 
 impl std::fmt::Debug for RootContext {
@@ -33,36 +59,62 @@ impl std::fmt::Debug for RootContext {
 }
 
 impl EvalContext {
+    /// Context for evaluating a program, with the core macros loaded.
     pub fn new_main() -> Self {
-        Self {
-            variables: HashMap::new(),
-            parent: None,
+        let mut context = Self {
+            frame: Rc::new(Frame::new(None)),
             root: Rc::new(RootContext::new())
+        };
+        context.load_core();
+        context
+    }
+
+    /// Evaluates the core source in its own namespace. It ships with the interpreter, so
+    /// failing to load it is a bug.
+    fn load_core(&mut self) {
+        let namespace = self.root.namespace();
+        let core = Symbol::intern(CORE_NAMESPACE);
+        self.root.set_namespace(core);
+        let tokens = tokenize(CORE_SOURCE, "lispure/core.clj").unwrap_or_else(|err| panic!("{}", err));
+        let forms = parse_file(&mut tokens.iter().peekable(), core).unwrap_or_else(|err| panic!("{}", err));
+        if let Err(err) = eval_file(self, &forms) {
+            panic!("{}", err);
         }
+        self.root.set_namespace(namespace);
+    }
+
+    /// Context for evaluating in a new frame nested in this one.
+    pub fn new_child(&self) -> Self {
+        self.enter(&self.frame)
     }
 
-    pub fn new_child(parent: &Rc<Self>) -> Self {
+    /// Context for evaluating in a new frame nested in `frame`, like the body of a closure.
+    pub fn enter(&self, frame: &Rc<Frame>) -> Self {
         Self {
-            variables: HashMap::new(),
-            parent: Some(parent.clone()),
-            root: parent.root.clone()
+            frame: Rc::new(Frame::new(Some(frame.clone()))),
+            root: self.root.clone()
         }
     }
 
-    pub fn get_var(&self, name: &str) -> Option<&NodePtr> {
-        self.variables.get(name)
+    /// Context for evaluating in `frame` itself.
+    pub fn in_frame(&self, frame: &Rc<Frame>) -> Self {
+        Self {
+            frame: frame.clone(),
+            root: self.root.clone()
+        }
     }
 
-    pub fn set_var(&mut self, name: &str, value: NodePtr) {
-        self.variables.insert(name.to_string(), value);
+    pub fn frame(&self) -> &Rc<Frame> {
+        &self.frame
     }
 
-    pub fn parent(&self) -> &Option<Rc<EvalContext>> {
-        &self.parent
+    pub fn set_var(&self, name: Symbol, value: NodePtr) {
+        self.frame.set(name, value);
     }
 
-    pub fn parent_mut(&mut self) -> &mut Option<Rc<EvalContext>> {
-        &mut self.parent
+    /// Finds the value bound to `name` in this frame or the enclosing ones, then in the vars.
+    pub fn lookup(&self, name: Symbol) -> Option<NodePtr> {
+        Frame::lookup(&self.frame, name).or_else(|| self.root.find_var(name)?.get())
     }
 
     pub fn root(&self) -> &RootContext {
@@ -73,19 +125,171 @@ impl EvalContext {
 impl RootContext {
 
     fn new() -> Self {
-        let mut builtins = HashMap::new();
+        let mut builtins = SymbolMap::default();
         populate_builtins(&mut builtins);
         Self {
-            builtins
+            builtins,
+            vars: RefCell::new(SymbolMap::default()),
+            core: Symbol::intern(CORE_NAMESPACE),
+            namespace: Cell::new(Symbol::intern("user")),
+            next_id: Cell::new(1),
+            max_depth: Cell::new(None)
         }
     }
 
-    pub fn get_builtin(&self, name: &str) -> Option<&Builtin> {
-        self.builtins.get(name)
+    pub fn get_builtin(&self, name: Symbol) -> Option<&Builtin> {
+        self.builtins.get(&name)
+    }
+
+    /// Qualifies a name without a namespace with the current one.
+    pub fn qualify(&self, name: Symbol) -> Symbol {
+        match name.split() {
+            (Some(_), _) => name,
+            (None, _) => Symbol::qualified(&self.namespace().name(), &name.name())
+        }
+    }
+
+    /// Finds the var for `name`, which is looked up in the current namespace if it isn't
+    /// qualified, then in the core namespace.
+    pub fn find_var(&self, name: Symbol) -> Option<Rc<Var>> {
+        let vars = self.vars.borrow();
+        let find = |namespace, name| vars.get(&namespace)?.get(&name).cloned();
+        match name.split() {
+            (Some(namespace), name) => find(namespace, name),
+            (None, name) => find(self.namespace(), name).or_else(|| find(self.core, name))
+        }
+    }
+
+    /// Finds the var for `name` in the current namespace, creating an unbound one if needed.
+    pub fn intern_var(&self, name: Symbol) -> Rc<Var> {
+        let (namespace, unqualified) = match name.split() {
+            (Some(namespace), unqualified) => (namespace, unqualified),
+            (None, _) => (self.namespace(), name)
+        };
+        self.vars.borrow_mut().entry(namespace).or_default().entry(unqualified)
+            .or_insert_with(|| Rc::new(Var::new(self.qualify(name))))
+            .clone()
+    }
+
+    pub fn namespace(&self) -> Symbol {
+        self.namespace.get()
+    }
+
+    pub fn set_namespace(&self, namespace: Symbol) {
+        self.namespace.set(namespace);
+    }
+
+    /// Returns a new number every time, for generating unique names.
+    pub fn next_id(&self) -> u64 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
     }
 
-    pub fn insert_builtin(&mut self, name: String, builtin: Builtin) {
-        self.builtins.insert(name, builtin);
+    pub fn max_depth(&self) -> Option<usize> {
+        self.max_depth.get()
     }
 
+    /// Limits the depth of evaluations, which fail with a `StackOverflow` error past
+    /// `max_depth` continuations, about twice the number of nested calls.
+    pub fn set_max_depth(&self, max_depth: Option<usize>) {
+        self.max_depth.set(max_depth);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{error, eval, eval_err};
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(eval("[(when true 1 2) (when false 1) (when-not false :a) (if-not false :yes :no)]"), "[2 nil :a :yes]");
+        assert_eq!(eval("[(and) (and 1 2 3) (and 1 nil 3) (or) (or nil false 3) (or nil false)]"), "[true 3 nil nil 3 false]");
+        assert_eq!(eval("(and false (throw (ex-info \"no\" {})))"), "false");
+        assert_eq!(eval("[(cond false 1 nil 2 :else 3) (cond false 1)]"), "[3 nil]");
+        assert_eq!(eval("[(condp = 3 1 :one 2 :two 3 :three :none) (condp = 5 1 :one :none)]"), "[:three :none]");
+        assert_eq!(eval("(condp contains? :b #{:a} :x #{:b :c} :>> (fn [r] [r :found]) :z)"), "[true :found]");
+        assert_eq!(eval_err("(condp = 5 1 :one)"), "ExceptionInfo: condp: no matching clause {:value 5}");
+    }
+
+    #[test]
+    fn case() {
+        let f = "(defn f [x] (case x 1 :one (2 3) :two-or-three \"s\" :str sym :symbol [1 2] :vec nil :nil :default))";
+        assert_eq!(
+            eval(&format!("{} [(f 1) (f 2) (f 3) (f \"s\") (f 'sym) (f [1 2]) (f nil) (f 99)]", f)),
+            "[:one :two-or-three :two-or-three :str :symbol :vec :nil :default]");
+        assert_eq!(eval_err("(case 5 1 :one)"), "ExceptionInfo: case: no matching clause {:value 5}");
+        assert_eq!(eval_err("(case 1 1 :a 1 :b)"), "macroexpand: unable to expand lispure.core/case");
+    }
+
+    #[test]
+    fn bindings() {
+        assert_eq!(eval("[(if-let [x 3] (+ x 1) :no) (if-let [x nil] (+ x 1) :no) (if-let [[a b] [1 2]] (+ a b))]"), "[4 :no 3]");
+        assert_eq!(eval("(when-let [x 5] x (* x 2))"), "10");
+        assert_eq!(eval("[(if-some [x false] [:some x] :none) (if-some [x nil] [:some x] :none) (when-some [x false] [x])]"), "[[:some false] :none [false]]");
+    }
+
+    #[test]
+    fn threading() {
+        assert_eq!(eval("[(-> 5 inc (- 3) (vector 1)) (->> 5 inc (- 3) (vector 1)) (as-> 5 x (inc x) (vector x x))]"), "[[3 1] [1 -3] [6 6]]");
+        assert_eq!(eval("[(some-> 5 inc (get 2)) (some-> {:a {:b 1}} (get :a) (get :b) inc)]"), "[nil 2]");
+        assert_eq!(eval("[(cond-> 1 true inc false (* 10) true (* 2)) (doto [1] (conj 2) count)]"), "[4 [1]]");
+    }
+
+    #[test]
+    fn tail_positions_are_kept() {
+        assert_eq!(eval("(defn count-down [n] (cond (= n 0) :done :else (count-down (dec n)))) (count-down 10000)"), ":done");
+        assert_eq!(eval("(loop [n 10000] (case n 0 :done (recur (dec n))))"), ":done");
+        assert_eq!(eval("(loop [n 10000] (when (> n 0) (recur (dec n))))"), "nil");
+    }
+
+    #[test]
+    fn prelude_macros_are_ordinary() {
+        assert_eq!(eval("(macroexpand '(when a b))"), "(if a (do b))");
+        assert_eq!(eval("(defmacro unless [t & body] `(when (not ~t) ~@body)) [(unless false 1) (macroexpand-1 '(unless false 1))]"), "[1 (lispure.core/when (not false) 1)]");
+        assert_eq!(eval("(def when 3) when"), "3");
+    }
+
+    #[test]
+    fn expansion_errors_point_at_the_call() {
+        let err = error("(def x 1)\n(cond 1)");
+        let span = err.span().unwrap();
+        assert_eq!((span.start.line, span.start.column), (2, 1));
+        let cause = err.cause().unwrap();
+        assert_eq!(cause.kind().to_string(), "ExceptionInfo: cond: expected an even number of forms {}");
+        assert_eq!(cause.span().unwrap().start.source.name().as_ref(), "lispure/core.clj");
+    }
+
+    #[test]
+    fn for_comprehensions() {
+        assert_eq!(eval("(for [x [1 2 3]] (* x 10))"), "(10 20 30)");
+        assert_eq!(eval("(for [x [1 2 3] y [:a :b]] [x y])"), "([1 :a] [1 :b] [2 :a] [2 :b] [3 :a] [3 :b])");
+        assert_eq!(eval("(for [x (range 10) :when (= 0 (rem x 3))] x)"), "(0 3 6 9)");
+        assert_eq!(eval("(for [x (range) :while (< x 5)] x)"), "(0 1 2 3 4)");
+        assert_eq!(eval("(take 5 (for [x (range) :let [y (* x x)] :when (= 1 (rem y 2))] y))"), "(1 9 25 49 81)");
+        assert_eq!(eval("(for [x [1 2 3] y [1 2 3] :while (< y x)] [x y])"), "([2 1] [3 1] [3 2])");
+        assert_eq!(eval("[(for [[k v] {:a 1} :let [w (inc v)]] [k w]) (for [x [] y (range)] [x y])]"), "[([:a 2]) ()]");
+        assert_eq!(eval("(take 3 (for [x (range) y (range 2)] [x y]))"), "([0 0] [0 1] [1 0])");
+        assert_eq!(eval("[(count (for [x (range 10000)] x)) (count (for [x (range 10000) :when (= x 9999)] x))]"), "[10000 1]");
+        assert_eq!(eval("[(count (for [x (range 10000) y [1 2]] x)) (count (for [x (range 10000) y [] z [1]] x))]"), "[20000 0]");
+        assert_eq!(eval("(for [x [1 2 3] :while (< x 3) y [1 2] :when (= y 1)] [x y])"), "([1 1] [2 1])");
+        assert_eq!(eval_err("(for [:when true x [1]] x)"), "macroexpand: unable to expand lispure.core/for");
+        assert_eq!(
+            error("(for [x [1] :foo 2] x)").cause().map(|cause| cause.kind().to_string()),
+            Some("ExceptionInfo: for: unknown modifier {:modifier :foo}".to_string()));
+    }
+
+    #[test]
+    fn side_effecting_loops() {
+        assert_eq!(eval("(let [log (transient [])] [(doseq [x [1 2 3]] (conj! log x)) (persistent! log)])"), "[nil [1 2 3]]");
+        assert_eq!(
+            eval("(let [log (transient [])] (doseq [x [1 2] y [3 4] :let [z (+ x y)] :when (not= z 5)] (conj! log [x y z])) (persistent! log))"),
+            "[[1 3 4] [2 4 6]]");
+        assert_eq!(eval("(let [log (transient [])] (doseq [x [1 2 3] :while (< x 3)] (conj! log x)) (persistent! log))"), "[1 2]");
+        assert_eq!(eval("(let [log (transient [])] [(dotimes [i 3] (conj! log i)) (persistent! log)])"), "[nil [0 1 2]]");
+        assert_eq!(eval("(let [log (transient [])] (while (< (count log) 3) (conj! log 1)) (persistent! log))"), "[1 1 1]");
+        assert_eq!(eval("[(dotimes [i 10000] i) (doseq [x (range 10000) :when (= x 5) y [x]] y) (while false 1)]"), "[nil nil nil]");
+        assert_eq!(eval_err("(doseq [x [1 2 3] :while (< x 3)] (when (= x 2) (throw (ex-info \"at\" {:x x}))))"), "ExceptionInfo: at {:x 2}");
+    }
 }
@@ -0,0 +1,56 @@
+use crate::nodes::NodePtr;
+use crate::intern::Symbol;
+use std::cell::{Cell, RefCell};
+
+/// Global definition created by `def`. Code refers to vars by name and reads their value
+/// when it runs, so redefining a var is seen by every function using it.
+pub struct Var {
+    name: Symbol,
+    value: RefCell<Option<NodePtr>>,
+    /// Set by `defmacro`. Calls to a macro are expanded before being evaluated.
+    is_macro: Cell<bool>
+}
+
+impl Var {
+
+    /// Creates an unbound var with a qualified name like `user/x`.
+    pub fn new(name: Symbol) -> Self {
+        Self {
+            name,
+            value: RefCell::new(None),
+            is_macro: Cell::new(false)
+        }
+    }
+
+    pub fn name(&self) -> Symbol {
+        self.name
+    }
+
+    /// Value of the var, or None if it was declared but never defined.
+    pub fn get(&self) -> Option<NodePtr> {
+        self.value.borrow().clone()
+    }
+
+    pub fn set(&self, value: NodePtr) {
+        *self.value.borrow_mut() = Some(value);
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.value.borrow().is_some()
+    }
+
+    pub fn is_macro(&self) -> bool {
+        self.is_macro.get()
+    }
+
+    pub fn set_macro(&self, is_macro: bool) {
+        self.is_macro.set(is_macro);
+    }
+
+}
+
+impl std::fmt::Debug for Var {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Var({})", self.name)
+    }
+}
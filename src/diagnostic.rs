@@ -0,0 +1,318 @@
+use crate::intern::Symbol;
+use crate::parser::{Span, TokenPos, ParseError, TokenizeError};
+use crate::eval::{EvalError, TraceFrame};
+use std::collections::HashMap;
+
+/// Texts of the sources that positions refer to, by name.
+#[derive(Default)]
+pub struct Sources {
+    texts: HashMap<Symbol, String>
+}
+
+impl Sources {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, name: &str, text: &str) {
+        self.texts.insert(Symbol::intern(name), text.to_string());
+    }
+
+    /// Adds `text` at the end of the source `name`. Returns the line the text starts at.
+    pub fn append(&mut self, name: &str, text: &str) -> usize {
+        let source = self.texts.entry(Symbol::intern(name)).or_default();
+        if !source.is_empty() && !source.ends_with('\n') {
+            source.push('\n');
+        }
+        let line = source.lines().count() + 1;
+        source.push_str(text);
+        line
+    }
+
+    fn line(&self, pos: &TokenPos) -> Option<&str> {
+        self.texts.get(&pos.source)?.lines().nth(pos.line.checked_sub(1)?)
+    }
+
+}
+
+/// Part of a source pointed at by a diagnostic.
+struct Label {
+    span: Span,
+    message: Option<String>,
+    primary: bool
+}
+
+/// Description of an error for people, showing the lines of the sources it's about.
+///
+/// ```text
+/// Parse Error: Unexpected token ')' while parsing Vector
+///   --> repl:1:7
+///   |
+/// 1 | [1 2 3)
+///   |       ^ doesn't close the collection
+///   | - Vector opened here
+/// ```
+pub struct Diagnostic {
+    /// Kind of error, like `Parse Error`.
+    title: String,
+    message: String,
+    labels: Vec<Label>,
+    /// Lines shown after the snippets, like the frames of a stack trace.
+    notes: Vec<String>,
+    cause: Option<Box<Diagnostic>>
+}
+
+impl Diagnostic {
+
+    pub fn new(title: &str, message: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            message: message.to_string(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            cause: None
+        }
+    }
+
+    /// Points at where the error is, with an optional message.
+    pub fn with_primary(mut self, span: Span, message: Option<&str>) -> Self {
+        self.labels.push(Label { span, message: message.map(str::to_string), primary: true });
+        self
+    }
+
+    /// Points at another place related to the error.
+    pub fn with_secondary(mut self, span: Span, message: &str) -> Self {
+        self.labels.push(Label { span, message: Some(message.to_string()), primary: false });
+        self
+    }
+
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    pub fn with_cause(mut self, cause: Diagnostic) -> Self {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    /// Renders the diagnostic with the lines of `sources` it points at, using ANSI colors if
+    /// `color` is set.
+    pub fn render(&self, sources: &Sources, color: bool) -> String {
+        let style = Style { color };
+        let mut out = String::new();
+        self.render_into(&mut out, sources, &style);
+        out
+    }
+
+    fn render_into(&self, out: &mut String, sources: &Sources, style: &Style) {
+        out.push_str(&format!("{}: {}\n", style.paint(&self.title, Style::ERROR), self.message));
+        let mut labels = self.labels.iter().collect::<Vec<&Label>>();
+        // The primary label comes first, then the others in the order of the sources.
+        labels.sort_by_key(|label| (!label.primary, label.span.start.source.name(), label.span.start.line));
+        let width = labels.iter().map(|label| label.span.start.line.to_string().len()).max().unwrap_or(0);
+        let gutter = " ".repeat(width);
+        let mut index = 0;
+        while index < labels.len() {
+            let start = labels[index].span.start;
+            // Labels on the same line share the line.
+            let count = labels[index..].iter()
+                .take_while(|label| label.span.start.source == start.source && label.span.start.line == start.line)
+                .count();
+            let arrow = if index == 0 { "-->" } else { ":::" };
+            out.push_str(&format!("{} {} {}\n", gutter, style.paint(arrow, Style::GUTTER), start));
+            if let Some(line) = sources.line(&start) {
+                let bar = style.paint("|", Style::GUTTER);
+                out.push_str(&format!("{} {}\n", gutter, bar));
+                out.push_str(&format!("{} {} {}\n", style.paint(&format!("{:>width$}", start.line, width = width), Style::GUTTER), bar, line));
+                for label in &labels[index..index + count] {
+                    out.push_str(&format!("{} {} {}\n", gutter, bar, underline(line, label, style)));
+                }
+            }
+            index += count;
+        }
+        for note in &self.notes {
+            out.push_str(&format!("{} {} {}\n", gutter, style.paint("=", Style::GUTTER), note));
+        }
+        if let Some(cause) = &self.cause {
+            cause.render_into(out, sources, style);
+        }
+    }
+
+}
+
+/// Underlines the span of `label` in `line`, which is the line it starts on, followed by the
+/// message of the label. Spans going past the line are underlined up to its end.
+fn underline(line: &str, label: &Label, style: &Style) -> String {
+    let Span { start, end } = label.span;
+    let length = line.chars().count();
+    let from = start.column.saturating_sub(1).min(length);
+    let to = match end.line == start.line {
+        true => end.column.saturating_sub(1).min(length),
+        false => length
+    };
+    // Tabs are kept so the marks line up with the text above them.
+    let indent = line.chars().take(from).map(|c| if c == '\t' { '\t' } else { ' ' }).collect::<String>();
+    let (mark, color) = match label.primary {
+        true => ("^", Style::ERROR),
+        false => ("-", Style::SECONDARY)
+    };
+    let marks = mark.repeat(to.saturating_sub(from).max(1));
+    match &label.message {
+        Some(message) => format!("{}{}", indent, style.paint(&format!("{} {}", marks, message), color)),
+        None => format!("{}{}", indent, style.paint(&marks, color))
+    }
+}
+
+struct Style {
+    color: bool
+}
+
+impl Style {
+
+    const ERROR: &'static str = "\x1b[1;31m";
+    const SECONDARY: &'static str = "\x1b[1;34m";
+    const GUTTER: &'static str = "\x1b[34m";
+
+    fn paint(&self, text: &str, color: &str) -> String {
+        match self.color {
+            true => format!("{}{}\x1b[0m", color, text),
+            false => text.to_string()
+        }
+    }
+
+}
+
+/// Span of the single character at `pos`.
+fn char_span(pos: TokenPos) -> Span {
+    Span { start: pos, end: pos.advance(1) }
+}
+
+impl From<&TokenizeError> for Diagnostic {
+    fn from(err: &TokenizeError) -> Self {
+        Diagnostic::new("Tokenize Error", err.message()).with_primary(char_span(err.pos()), None)
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Self {
+        let mut diagnostic = Diagnostic::new("Parse Error", err.message());
+        if let Some(pos) = err.pos() {
+            diagnostic = diagnostic.with_primary(char_span(pos), err.label());
+        }
+        for (pos, label) in err.secondary() {
+            diagnostic = diagnostic.with_secondary(char_span(*pos), label);
+        }
+        diagnostic
+    }
+}
+
+/// Eval errors point at the form they were raised in, and at the macro calls it was expanded
+/// from. The stack trace follows as notes, then the causes.
+impl From<&EvalError> for Diagnostic {
+    fn from(err: &EvalError) -> Self {
+        diagnostic_for(err, "Eval Error")
+    }
+}
+
+fn diagnostic_for(err: &EvalError, title: &str) -> Diagnostic {
+    let mut diagnostic = Diagnostic::new(title, &err.kind().to_string());
+    if let Some(span) = err.span() {
+        diagnostic = diagnostic.with_primary(span, None);
+    }
+    for frame in err.trace() {
+        match frame {
+            TraceFrame::Expansion { name, span: Some(span) } if Some(*span) != err.span() => {
+                diagnostic = diagnostic.with_secondary(*span, &format!("in expansion of {}", name));
+            },
+            _ => ()
+        }
+    }
+    for line in err.trace_lines() {
+        diagnostic = diagnostic.with_note(&line);
+    }
+    match err.cause() {
+        Some(cause) => diagnostic.with_cause(diagnostic_for(cause, "Caused by")),
+        None => diagnostic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::testing::error;
+    use crate::parser::{parse_file, tokenize};
+
+    /// Renders the first error of tokenizing, parsing or evaluating `source`, without colors.
+    fn render(source: &str) -> String {
+        let mut sources = Sources::new();
+        sources.append("test", source);
+        let diagnostic = match tokenize(source, "test") {
+            Err(err) => Diagnostic::from(&err),
+            Ok(tokens) => match parse_file(&mut tokens.iter().peekable(), Symbol::intern("user")) {
+                Err(err) => Diagnostic::from(&err),
+                Ok(_) => Diagnostic::from(&error(source))
+            }
+        };
+        diagnostic.render(&sources, false)
+    }
+
+    #[test]
+    fn parse_errors_show_where_collections_open() {
+        assert_eq!(render("[1 2 3)"), "\
+Parse Error: Unexpected token ')' while parsing Vector
+  --> test:1:7
+  |
+1 | [1 2 3)
+  |       ^ doesn't close the collection
+  | - Vector opened here
+");
+        assert_eq!(render("\"abc"), "\
+Tokenize Error: Unexpected End of File while parsing String
+  --> test:1:1
+  |
+1 | \"abc
+  | ^
+");
+    }
+
+    #[test]
+    fn hash_literals_are_reported_at_their_hash() {
+        assert!(render("(foo #{1").contains("--> test:1:6\n"));
+        assert!(render("#:user{:id 1").contains("--> test:1:1\n"));
+        assert!(render("#queue [1").contains("--> test:1:1\n"));
+    }
+
+    #[test]
+    fn eval_errors_underline_the_form_and_list_the_trace() {
+        assert_eq!(render("(defn add [a b] (+ a b))\n(defn g [x]\n  (add x :k))\n(g 1)"), "\
+Eval Error: +: expected a number but got :k
+  --> test:1:17
+  |
+1 | (defn add [a b] (+ a b))
+  |                 ^^^^^^^
+  = at add (test:3:3)
+  = at g (test:4:1)
+");
+    }
+
+    #[test]
+    fn expansions_and_causes_are_shown() {
+        let rendered = render("(defmacro m [x] `(let [~x] 1))\n(defn k [] (m y))\n(k)");
+        assert!(rendered.contains("--> test:2:12\n"));
+        assert!(rendered.contains("= in expansion of user/m (test:2:12)\n"));
+        let rendered = render("(defmacro m [x] (throw (ex-info \"bad macro\" {})))\n(m 1)");
+        assert!(rendered.starts_with("Eval Error: macroexpand: unable to expand user/m\n  --> test:2:1\n"));
+        assert!(rendered.contains("Caused by: ExceptionInfo: bad macro {}\n  --> test:1:17\n"));
+    }
+
+    #[test]
+    fn colors_are_optional() {
+        let err = error("(inc :a)");
+        let mut sources = Sources::new();
+        sources.append("test", "(inc :a)");
+        assert!(Diagnostic::from(&err).render(&sources, true).contains("\x1b["));
+        assert!(!Diagnostic::from(&err).render(&sources, false).contains("\x1b["));
+    }
+}
@@ -0,0 +1,475 @@
+use crate::nodes::{NodePtr, Node, IntoListIter, list_from};
+use crate::intern::Symbol;
+use crate::context::{EvalContext, Frame};
+use crate::parser::{copy_span, set_elements};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{EvalResult, EvalError, macros};
+use super::special::{try_clause, split_bindings, split_letfn};
+use super::destructure::bound_names;
+use super::machine::literal_elements;
+
+/// Prepares a form for evaluation, before any of it is evaluated:
+/// - macro calls are expanded;
+/// - `recur` is checked to be in tail position of a `loop` or function, with the right number
+///   of values;
+/// - calls of a function to its local name, like `self` in `(fn self [...] ...)`, in tail
+///   position become `recur`, so they run in constant stack space;
+/// - `fn`, `loop` and `letfn` become `fn*`, `loop*` and `letfn*`, which don't analyze their
+///   body again.
+///
+/// The bodies of `defn` and `defmacro` are analyzed when they are evaluated.
+pub fn analyze(context: &mut EvalContext, form: &NodePtr) -> EvalResult {
+    Analyzer::new(context).form(form, None)
+}
+
+/// Analyzes the arities of a function defined by a form like `defn`. Its calls to itself go
+/// through its var, which can be redefined, so they don't become `recur`. `leading` are
+/// parameters added before the declared ones, like the `&form` and `&env` of macros, which
+/// a `recur` has to pass as well.
+pub fn analyze_arities(context: &mut EvalContext, arities: &NodePtr, leading: &[Symbol]) -> EvalResult {
+    Analyzer::new(context).arities(arities, None, leading)
+}
+
+/// What a `recur` in tail position jumps back to.
+#[derive(Clone, Copy)]
+struct Target {
+    /// Number of values `recur` has to pass.
+    count: usize,
+    /// Name of the function, if its calls with `count` arguments can become `recur`.
+    name: Option<Symbol>,
+    /// Number of locals bound outside of the function, so the locals shadowing its name can be
+    /// found.
+    locals: usize
+}
+
+struct Analyzer<'a> {
+    context: &'a mut EvalContext,
+    /// Names bound by the forms enclosing the one being analyzed.
+    locals: Vec<Symbol>
+}
+
+impl<'a> Analyzer<'a> {
+
+    fn new(context: &'a mut EvalContext) -> Self {
+        Self {
+            context,
+            locals: Vec::new()
+        }
+    }
+
+    fn is_local(&self, symbol: Symbol) -> bool {
+        self.locals.contains(&symbol) || Frame::lookup(self.context.frame(), symbol).is_some()
+    }
+
+    /// Analyzes `form`. `tail` is the target of a `recur` if the form is in tail position.
+    fn form(&mut self, form: &NodePtr, tail: Option<Target>) -> EvalResult {
+        self.analyze_form(form, tail).map_err(|err| err.at(form))
+    }
+
+    fn analyze_form(&mut self, form: &NodePtr, tail: Option<Target>) -> EvalResult {
+        let (head, args) = match form.as_ref() {
+            Node::List(head, args) => (head, args),
+            Node::Vector(_) | Node::Map(_) | Node::Set(_) => return self.collection(form),
+            _ => return Ok(form.clone())
+        };
+        let symbol = match head.as_ref() {
+            Node::Symbol(symbol) => *symbol,
+            _ => return self.elements(form, 0, None)
+        };
+        if self.is_local(symbol) {
+            return self.call(form, symbol, args, tail);
+        }
+        match symbol {
+            Symbol::QUOTE | Symbol::SYNTAX_QUOTE | Symbol::VAR | Symbol::DECLARE | Symbol::DEFN | Symbol::DEFMACRO
+                | Symbol::FN_STAR | Symbol::LOOP_STAR | Symbol::LETFN_STAR => Ok(form.clone()),
+            Symbol::DEF | Symbol::DEFONCE => self.elements(form, 2, None),
+            Symbol::IF => {
+                let mut elements = form.list_elements()?;
+                for (index, element) in elements.iter_mut().enumerate().skip(1) {
+                    // The branches are in tail position, but not the test.
+                    *element = self.form(element, if index == 1 { None } else { tail })?;
+                }
+                Ok(rebuild_from(form, elements))
+            },
+            Symbol::DO => Ok(rebuild(form, vec![head.clone()], self.body(args, tail)?)),
+            Symbol::LET => self.bindings(form, tail, None),
+            Symbol::LOOP => self.bindings(form, tail, Some(Symbol::LOOP_STAR)),
+            Symbol::FN => self.function(form),
+            Symbol::LETFN => self.letfn(form, tail),
+            Symbol::TRY => self.try_form(form, tail),
+            Symbol::CASE_STAR => {
+                let mut elements = form.list_elements()?;
+                if elements.len() == 4 {
+                    // The value isn't in tail position, but the branches and the default are.
+                    elements[1] = self.form(&elements[1], None)?;
+                    elements[2] = self.case_table(&elements[2], tail)?;
+                    elements[3] = self.form(&elements[3], tail)?;
+                }
+                Ok(rebuild_from(form, elements))
+            },
+            // The body of a lazy seq is evaluated later, so it can't `recur` out of it.
+            Symbol::LAZY_SEQ => Ok(rebuild(form, vec![head.clone()], self.body(args, None)?)),
+            Symbol::RECUR => {
+                let target = tail.ok_or_else(|| EvalError::syntax("recur: can only be used in tail position"))?;
+                let count = args.list_iter().count();
+                if count != target.count {
+                    return Err(EvalError::arity("recur", &target.count.to_string(), count));
+                }
+                self.elements(form, 1, None)
+            },
+            _ => match macros::macroexpand_1(self.context, form, &self.locals)? {
+                Some(expansion) => self.form(&expansion, tail),
+                None => self.call(form, symbol, args, tail)
+            }
+        }
+    }
+
+    /// Analyzes a call to a function named by `symbol`, which becomes a `recur` if it calls
+    /// the function of `tail` by its name.
+    fn call(&mut self, form: &NodePtr, symbol: Symbol, args: &NodePtr, tail: Option<Target>) -> EvalResult {
+        let form = self.elements(form, 1, None)?;
+        let recur = tail.filter(|target| {
+            target.name == Some(symbol)
+                && !self.locals[target.locals..].contains(&symbol)
+                && args.list_iter().count() == target.count
+        });
+        match (recur, form.as_ref()) {
+            (Some(_), Node::List(_, args)) => {
+                let recur = Rc::new(Node::List(Rc::new(Node::Symbol(Symbol::RECUR)), args.clone()));
+                copy_span(&form, &recur);
+                Ok(recur)
+            },
+            _ => Ok(form)
+        }
+    }
+
+    /// Analyzes the elements of `form` from index `skip`, all in the same position.
+    fn elements(&mut self, form: &NodePtr, skip: usize, tail: Option<Target>) -> EvalResult {
+        let mut elements = form.list_elements()?;
+        for element in elements.iter_mut().skip(skip) {
+            *element = self.form(element, tail)?;
+        }
+        Ok(rebuild_from(form, elements))
+    }
+
+    /// Analyzes the elements of a vector, map or set literal, and the keys of maps.
+    fn collection(&mut self, form: &NodePtr) -> EvalResult {
+        let analyzed = match form.as_ref() {
+            Node::Vector(vector) => {
+                let elements = vector.iter().map(|element| self.form(element, None)).collect::<Result<Vec<NodePtr>, EvalError>>()?;
+                match vector.iter().zip(&elements).all(|(original, element)| Rc::ptr_eq(original, element)) {
+                    true => return Ok(form.clone()),
+                    false => Node::Vector(elements.into_iter().collect())
+                }
+            },
+            Node::Map(_) | Node::Set(_) => {
+                // Maps and sets are analyzed in source order, and remember it for evaluation.
+                let originals = literal_elements(form);
+                let elements = originals.iter().map(|element| self.form(element, None)).collect::<Result<Vec<NodePtr>, EvalError>>()?;
+                if originals.iter().zip(&elements).all(|(original, element)| Rc::ptr_eq(original, element)) {
+                    return Ok(form.clone());
+                }
+                let analyzed = match form.as_ref() {
+                    Node::Map(_) => Node::Map(elements.chunks(2).map(|entry| (entry[0].clone(), entry[1].clone())).collect()),
+                    _ => Node::Set(elements.iter().cloned().collect())
+                };
+                let analyzed = Rc::new(analyzed);
+                copy_span(form, &analyzed);
+                set_elements(&analyzed, elements);
+                return Ok(analyzed);
+            },
+            _ => return Ok(form.clone())
+        };
+        let analyzed = Rc::new(analyzed);
+        copy_span(form, &analyzed);
+        Ok(analyzed)
+    }
+
+    /// Analyzes the branches of the map of a `case*`, leaving its keys as they are. Keys sharing
+    /// a branch still share it once analyzed.
+    fn case_table(&mut self, table: &NodePtr, tail: Option<Target>) -> EvalResult {
+        let map = match table.as_ref() {
+            Node::Map(map) => map,
+            _ => return Ok(table.clone())
+        };
+        let mut analyzed = HashMap::<*const Node, NodePtr>::new();
+        let mut changed = false;
+        let mut entries = Vec::with_capacity(map.len());
+        for (key, branch) in map.iter() {
+            let result = match analyzed.get(&Rc::as_ptr(branch)) {
+                Some(result) => result.clone(),
+                None => {
+                    let result = self.form(branch, tail)?;
+                    analyzed.insert(Rc::as_ptr(branch), result.clone());
+                    result
+                }
+            };
+            changed |= !Rc::ptr_eq(branch, &result);
+            entries.push((key.clone(), result));
+        }
+        match changed {
+            true => {
+                let rebuilt = Rc::new(Node::Map(entries.into_iter().collect()));
+                copy_span(table, &rebuilt);
+                Ok(rebuilt)
+            },
+            false => Ok(table.clone())
+        }
+    }
+
+    /// Analyzes the forms of a body, with the last one in tail position.
+    fn body(&mut self, body: &NodePtr, tail: Option<Target>) -> EvalResult {
+        let mut forms = body.list_elements()?;
+        let last = forms.len().saturating_sub(1);
+        for (index, form) in forms.iter_mut().enumerate() {
+            *form = self.form(form, if index == last { tail } else { None })?;
+        }
+        Ok(rebuild_from(body, forms))
+    }
+
+    /// Analyzes a form like `(let [bindings] body...)`. A `loop` is renamed to `loop*`, and
+    /// its body gets its own `recur` target.
+    fn bindings(&mut self, form: &NodePtr, tail: Option<Target>, rename: Option<Symbol>) -> EvalResult {
+        let (head, vector, bindings, body) = match form.as_ref() {
+            Node::List(head, args) => match args.as_ref() {
+                Node::List(vector, body) => match vector.as_ref() {
+                    Node::Vector(vec) => (head, vector, vec.iter().cloned().collect::<Vec<NodePtr>>(), body),
+                    _ => return Ok(form.clone())
+                },
+                _ => return Ok(form.clone())
+            },
+            _ => return Ok(form.clone())
+        };
+        let locals = self.locals.len();
+        let mut analyzed = Vec::with_capacity(bindings.len());
+        for pair in bindings.chunks(2) {
+            analyzed.push(pair[0].clone());
+            if let Some(value) = pair.get(1) {
+                analyzed.push(self.form(value, None)?);
+            }
+            bound_names(&pair[0], &mut self.locals);
+        }
+        let tail = match rename {
+            Some(_) => Some(Target { count: bindings.len() / 2, name: None, locals: self.locals.len() }),
+            None => tail
+        };
+        let body = self.body(body, tail);
+        self.locals.truncate(locals);
+        let head = rename.map_or_else(|| head.clone(), |name| Rc::new(Node::Symbol(name)));
+        let bindings = match analyzed.iter().zip(&bindings).all(|(analyzed, binding)| Rc::ptr_eq(analyzed, binding)) {
+            true => vector.clone(),
+            false => Rc::new(Node::Vector(analyzed.into_iter().collect()))
+        };
+        Ok(rebuild(form, vec![head, bindings], body?))
+    }
+
+    /// Analyzes `(try body... (catch kind name handler...)... (finally cleanup...)?)`. The
+    /// last form of the body and of the handlers are in tail position, since `finally` is
+    /// evaluated before a `recur` leaves the `try`.
+    fn try_form(&mut self, form: &NodePtr, tail: Option<Target>) -> EvalResult {
+        let mut elements = form.list_elements()?;
+        let clauses = elements.iter().position(|element| try_clause(element).is_some()).unwrap_or(elements.len());
+        for (index, slot) in elements.iter_mut().enumerate().skip(1) {
+            let element = slot.clone();
+            *slot = match try_clause(&element) {
+                Some(("catch", args)) => {
+                    let mut args = args.list_elements()?;
+                    if args.len() < 2 {
+                        continue;
+                    }
+                    let locals = self.locals.len();
+                    bound_names(&args[1], &mut self.locals);
+                    let handler = self.body(&list_from(args.split_off(2)), tail);
+                    self.locals.truncate(locals);
+                    let head = vec![first(&element)].into_iter().chain(args).collect();
+                    rebuild(&element, head, handler?)
+                },
+                Some((_, cleanup)) => {
+                    let cleanup = self.body(cleanup, None)?;
+                    rebuild(&element, vec![first(&element)], cleanup)
+                },
+                None => self.form(&element, if index + 1 == clauses { tail } else { None })?
+            };
+        }
+        Ok(rebuild_from(form, elements))
+    }
+
+    /// Analyzes `(fn name? arities...)` into `(fn* name? arities...)`.
+    fn function(&mut self, form: &NodePtr) -> EvalResult {
+        let args = match form.as_ref() {
+            Node::List(_, args) => args,
+            _ => unreachable!()
+        };
+        let mut head = vec![Rc::new(Node::Symbol(Symbol::FN_STAR))];
+        let locals = self.locals.len();
+        let (name, arities) = match args.as_ref() {
+            Node::List(name, arities) => match name.as_ref() {
+                Node::Symbol(symbol) => {
+                    head.push(name.clone());
+                    self.locals.push(*symbol);
+                    (Some(*symbol), arities)
+                },
+                _ => (None, args)
+            },
+            _ => (None, args)
+        };
+        let arities = self.arities(arities, name, &[]);
+        self.locals.truncate(locals);
+        Ok(rebuild(form, head, arities?))
+    }
+
+    /// Analyzes `(letfn [(name [params...] body...)...] body...)` into `letfn*`. The names of
+    /// all the functions are bound in their bodies and in the body of the `letfn`.
+    fn letfn(&mut self, form: &NodePtr, tail: Option<Target>) -> EvalResult {
+        let args = match form.as_ref() {
+            Node::List(_, args) => args,
+            _ => unreachable!()
+        };
+        let (specs, body) = split_bindings("letfn", args)?;
+        let names = specs.iter().map(|spec| Ok(split_letfn(spec)?.0)).collect::<Result<Vec<Symbol>, EvalError>>()?;
+        let locals = self.locals.len();
+        self.locals.extend(&names);
+        let analyzed = specs.iter().zip(&names)
+            .map(|(spec, name)| {
+                let arities = self.arities(&split_letfn(spec)?.1, Some(*name), &[])?;
+                Ok(rebuild(spec, vec![first(spec)], arities))
+            })
+            .collect::<Result<Vec<NodePtr>, EvalError>>();
+        let body = self.body(&body, tail);
+        self.locals.truncate(locals);
+        let bindings = Rc::new(Node::Vector(analyzed?.into_iter().collect()));
+        copy_span(&first(args), &bindings);
+        Ok(rebuild(form, vec![Rc::new(Node::Symbol(Symbol::LETFN_STAR)), bindings], body?))
+    }
+
+    /// Analyzes `[params...] body...`, or a list of `([params...] body...)` for each arity.
+    fn arities(&mut self, arities: &NodePtr, name: Option<Symbol>, leading: &[Symbol]) -> EvalResult {
+        match arities.as_ref() {
+            Node::List(first, _) if matches!(first.as_ref(), Node::List(_, _)) => {
+                let analyzed = arities.list_iter()
+                    .map(|arity| self.arity(&arity?, name, leading))
+                    .collect::<Result<Vec<NodePtr>, EvalError>>()?;
+                Ok(rebuild_from(arities, analyzed))
+            },
+            _ => self.arity(arities, name, leading)
+        }
+    }
+
+    fn arity(&mut self, arity: &NodePtr, name: Option<Symbol>, leading: &[Symbol]) -> EvalResult {
+        let (params, body) = match arity.as_ref() {
+            Node::List(params, body) => match params.as_ref() {
+                Node::Vector(vec) => (vec, body),
+                _ => return Ok(arity.clone())
+            },
+            _ => return Ok(arity.clone())
+        };
+        let variadic = params.iter().any(|param| matches!(param.as_ref(), Node::Symbol(Symbol::AMPERSAND)));
+        // Calls with a rest argument can't become `recur`, which takes the rest as a seq.
+        let target = Target {
+            count: leading.len() + if variadic { params.len() - 1 } else { params.len() },
+            name: name.filter(|_| !variadic),
+            locals: self.locals.len()
+        };
+        self.locals.extend(leading);
+        for param in params.iter() {
+            bound_names(param, &mut self.locals);
+        }
+        let body = self.body(body, Some(target));
+        self.locals.truncate(target.locals);
+        Ok(rebuild(arity, vec![first(arity)], body?))
+    }
+
+}
+
+/// First element of a list that the analyzer already matched as a `Node::List`.
+fn first(list: &NodePtr) -> NodePtr {
+    match list.as_ref() {
+        Node::List(first, _) => first.clone(),
+        _ => unreachable!()
+    }
+}
+
+/// Builds the list `head... rest...`, or returns `form` if it's the same list. The lists
+/// given to the rebuilding functions were already walked by the analyzer.
+fn rebuild(form: &NodePtr, head: Vec<NodePtr>, rest: NodePtr) -> NodePtr {
+    rebuild_from(form, head.into_iter().chain(rest.list_iter().map_while(Result::ok)).collect())
+}
+
+/// Builds a list of `elements` with the span of `form`, or returns `form` if it has the same
+/// elements.
+fn rebuild_from(form: &NodePtr, elements: Vec<NodePtr>) -> NodePtr {
+    let same = form.list_iter().map_while(Result::ok).zip(&elements).all(|(original, element)| Rc::ptr_eq(&original, element));
+    match same {
+        true => form.clone(),
+        false => {
+            let rebuilt = list_from(elements);
+            copy_span(form, &rebuilt);
+            rebuilt
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn loop_and_recur() {
+        assert_eq!(eval("(loop [i 0 acc 0] (if (< i 10000) (recur (inc i) (+ acc i)) acc))"), "49995000");
+        assert_eq!(eval("[(loop [i 0] (cond (< i 3) (recur (inc i)) :else i)) (loop [i 0] (let [j (inc i)] (if (< j 5) (recur j) j)))]"), "[3 5]");
+        assert_eq!(eval("(loop [i 0] (try (if (< i 2) (recur (inc i)) i)))"), "2");
+    }
+
+    #[test]
+    fn recur_must_be_in_tail_position() {
+        assert_eq!(eval_err("(loop [i 0] (if (< i 3) (+ 1 (recur (inc i))) i))"), "recur: can only be used in tail position");
+        assert_eq!(eval_err("(recur 1)"), "recur: can only be used in tail position");
+        assert_eq!(eval_err("(fn [x] (recur))"), "recur: expected 1 argument but got 0");
+        assert_eq!(eval_err("(loop [x 1] (recur 1 2))"), "recur: expected 1 argument but got 2");
+    }
+
+    #[test]
+    fn local_self_calls_become_recur() {
+        assert_eq!(eval("((fn self [n acc] (if (= n 0) acc (self (dec n) (+ acc 1)))) 10000 0)"), "10000");
+        // A local shadowing the name is called instead.
+        assert_eq!(eval("((fn self [n] (if (= n 0) :done (let [self (fn [m] m)] (self n)))) 5)"), "5");
+    }
+
+    #[test]
+    fn trampoline() {
+        let source = "(defn ev? [n] (if (= n 0) true (fn [] (od? (dec n)))))
+            (defn od? [n] (if (= n 0) false (fn [] (ev? (dec n)))))
+            [(trampoline ev? 10001) (trampoline + 1 2)]";
+        assert_eq!(eval(source), "[false 3]");
+    }
+
+    #[test]
+    fn collection_literals_are_evaluated() {
+        assert_eq!(eval("(def x 5) [[(+ 1 2) x] {:a x (keyword \"b\") [x x]} #{x (+ x 1)} {[x] #{x}}]"), "[[3 5] {:a 5, :b [5 5]} #{5 6} {[5] #{5}}]");
+        assert_eq!(eval("(def x 5) ['[a x] `[~x y]]"), "[[a x] [5 user/y]]");
+        assert_eq!(eval("(let [m {:k (fn [] 1)}] ((:k m)))"), "1");
+        assert_eq!(eval_err("[(undefined-sym)]"), "Unable to resolve symbol: undefined-sym");
+    }
+
+    #[test]
+    fn collection_literals_are_evaluated_in_order() {
+        let log = "(def log (transient [])) (defn t [x] (conj! log x) x)";
+        assert_eq!(eval(&format!("{} [(t 1) (t 2) (t 3)] (persistent! log)", log)), "[1 2 3]");
+        assert_eq!(eval(&format!("{} {{(t :a) (t 1) (t :b) (t 2)}} (persistent! log)", log)), "[:a 1 :b 2]");
+        assert_eq!(eval(&format!("{} #{{(t 1) (t 2) (t 3)}} (persistent! log)", log)), "[1 2 3]");
+    }
+
+    #[test]
+    fn constant_literals_are_shared() {
+        assert_eq!(eval("(defn f [] [1 2 {:a 3}]) (identical? (f) (f))"), "true");
+        assert_eq!(eval("(def x 5) (defn g [] [1 x]) [(identical? (g) (g)) (= (g) (g))]"), "[false true]");
+    }
+
+    #[test]
+    fn duplicate_keys_are_errors() {
+        assert_eq!(eval_err("(def x 5) #{x 5}"), "Duplicate element in set literal: 5");
+        assert_eq!(eval_err("(def x 5) {x 1 5 2}"), "Duplicate key in map literal: 5");
+    }
+}
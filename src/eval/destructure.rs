@@ -0,0 +1,222 @@
+use crate::nodes::{NodePtr, Node};
+use crate::intern::Symbol;
+use crate::context::EvalContext;
+use std::rc::Rc;
+
+use super::{EvalError, eval_expr, call_builtin};
+use super::seq;
+
+/// Binds the names in a binding form to the matching parts of `value`, in the frame of
+/// `context`. Used by `let`, function parameters and the other binding forms.
+///
+/// Binding forms can be:
+/// - a symbol, bound to the whole value;
+/// - a vector like `[a b & rest :as all]`, bound to the elements of a sequential value;
+/// - a map like `{a :a, :keys [b c], :or {c 1}, :as m}`, bound to values looked up in a map.
+///
+/// Vector and map forms can be nested. `form` names the form being evaluated, for errors.
+pub fn bind(context: &mut EvalContext, form: &str, pattern: &NodePtr, value: NodePtr) -> Result<(), EvalError> {
+    match pattern.as_ref() {
+        Node::Symbol(symbol) if symbol.split().0.is_none() => {
+            context.set_var(*symbol, value);
+            Ok(())
+        },
+        Node::Vector(patterns) => bind_sequential(context, form, &patterns.iter().cloned().collect::<Vec<_>>(), value),
+        Node::Map(_) => bind_map(context, form, pattern, value),
+        node => Err(EvalError::syntax(&format!("{}: unsupported binding form: {}", form, node)))
+    }
+}
+
+/// Adds the names bound by a binding form to `names`.
+pub fn bound_names(pattern: &NodePtr, names: &mut Vec<Symbol>) {
+    match pattern.as_ref() {
+        Node::Symbol(symbol) if *symbol != Symbol::AMPERSAND => names.push(*symbol),
+        Node::Vector(patterns) => patterns.iter().for_each(|pattern| bound_names(pattern, names)),
+        Node::Map(map) => for (key, target) in map.iter() {
+            match key.as_ref() {
+                Node::Keyword(keyword) => match keyword.split().1.name().as_ref() {
+                    "keys" | "strs" | "syms" => if let Node::Vector(shorthands) = target.as_ref() {
+                        for shorthand in shorthands.iter() {
+                            if let Node::Symbol(symbol) | Node::Keyword(symbol) = shorthand.as_ref() {
+                                names.push(symbol.split().1);
+                            }
+                        }
+                    },
+                    "as" => bound_names(target, names),
+                    _ => ()
+                },
+                _ => bound_names(key, names)
+            }
+        },
+        _ => ()
+    }
+}
+
+fn bind_sequential(context: &mut EvalContext, form: &str, patterns: &[NodePtr], value: NodePtr) -> Result<(), EvalError> {
+    let mut coll = seq::seq(context, &value)?;
+    let mut patterns = patterns.iter();
+    while let Some(pattern) = patterns.next() {
+        match pattern.as_ref() {
+            Node::Symbol(Symbol::AMPERSAND) => {
+                match patterns.next() {
+                    Some(rest) => bind(context, form, rest, coll.clone())?,
+                    None => return Err(EvalError::syntax(&format!("{}: expected a binding form after &", form)))
+                }
+                return match patterns.next() {
+                    None => Ok(()),
+                    Some(pattern) if is_keyword(pattern, "as") => bind_as(context, form, patterns.next(), value),
+                    Some(pattern) => Err(EvalError::syntax(&format!("{}: unexpected {} after the rest binding", form, pattern)))
+                };
+            },
+            _ if is_keyword(pattern, "as") => return bind_as(context, form, patterns.next(), value),
+            _ => {
+                let element = seq::first(context, &coll)?;
+                coll = seq::next(context, &coll)?;
+                bind(context, form, pattern, element)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Binds the symbol following `:as` to the whole value.
+fn bind_as(context: &mut EvalContext, form: &str, name: Option<&NodePtr>, value: NodePtr) -> Result<(), EvalError> {
+    match name.map(|name| name.as_ref()) {
+        Some(Node::Symbol(name)) => {
+            context.set_var(*name, value);
+            Ok(())
+        },
+        _ => Err(EvalError::syntax(&format!("{}: expected a symbol after :as", form)))
+    }
+}
+
+fn is_keyword(node: &NodePtr, name: &str) -> bool {
+    matches!(node.as_ref(), Node::Keyword(keyword) if keyword.name().as_ref() == name)
+}
+
+fn bind_map(context: &mut EvalContext, form: &str, pattern: &NodePtr, value: NodePtr) -> Result<(), EvalError> {
+    let entries = match pattern.as_ref() {
+        Node::Map(map) => map.iter().map(|(key, value)| (key.clone(), value.clone())).collect::<Vec<_>>(),
+        _ => unreachable!()
+    };
+    // Sequences of keys and values, like keyword arguments after `&`, are read as maps.
+    let map = match value.as_ref() {
+        Node::List(_, _) | Node::Seq(_) | Node::LazySeq(_) => {
+            let elements = seq::collect(context, &value)?;
+            call_builtin(context, "hash-map", elements)?
+        },
+        _ => value.clone()
+    };
+    let defaults = entries.iter().find(|(key, _)| is_keyword(key, "or")).map(|(_, defaults)| defaults.clone());
+    if let Some(defaults) = &defaults {
+        if !matches!(defaults.as_ref(), Node::Map(_)) {
+            return Err(EvalError::syntax(&format!("{}: expected a map after :or but got {}", form, defaults)));
+        }
+    }
+    for (key, target) in &entries {
+        // Apart from the keyword options, entries map a binding form to the key it looks up.
+        match key.as_ref() {
+            Node::Keyword(keyword) => match keyword.split() {
+                (None, name) if name.name().as_ref() == "or" => (),
+                (None, name) if name.name().as_ref() == "as" => bind_as(context, form, Some(target), value.clone())?,
+                (namespace, name) if matches!(name.name().as_ref(), "keys" | "strs" | "syms") => {
+                    let names = match target.as_ref() {
+                        Node::Vector(names) => names.iter().cloned().collect::<Vec<_>>(),
+                        node => return Err(EvalError::syntax(&format!("{}: expected a vector after {} but got {}", form, key, node)))
+                    };
+                    for element in names {
+                        let (local, lookup_key) = shorthand_key(form, &element, namespace, &name.name())?;
+                        let found = lookup(context, &map, lookup_key, local, &defaults)?;
+                        context.set_var(local, found);
+                    }
+                },
+                _ => bind_entry(context, form, &map, key, target.clone(), &defaults)?
+            },
+            _ => bind_entry(context, form, &map, key, target.clone(), &defaults)?
+        }
+    }
+    Ok(())
+}
+
+/// Finds the local name and the key to look up for a name in `:keys`, `:strs` or `:syms`.
+/// `:keys [a ns/b]` looks up `:a` and `:ns/b`, and `:ns/keys [a]` looks up `:ns/a`.
+fn shorthand_key(form: &str, name: &NodePtr, namespace: Option<Symbol>, kind: &str) -> Result<(Symbol, NodePtr), EvalError> {
+    let symbol = match name.as_ref() {
+        Node::Symbol(symbol) | Node::Keyword(symbol) => *symbol,
+        node => return Err(EvalError::syntax(&format!("{}: expected a symbol in :{} but got {}", form, kind, node)))
+    };
+    let (name_namespace, local) = symbol.split();
+    let qualified = match (namespace, name_namespace) {
+        (Some(namespace), None) => Symbol::qualified(&namespace.name(), &local.name()),
+        _ => symbol
+    };
+    let key = match kind {
+        "keys" => Node::Keyword(qualified),
+        "strs" => Node::String(local.name().to_string()),
+        _ => Node::Symbol(qualified)
+    };
+    Ok((local, Rc::new(key)))
+}
+
+fn bind_entry(
+    context: &mut EvalContext,
+    form: &str,
+    map: &NodePtr,
+    target: &NodePtr,
+    key: NodePtr,
+    defaults: &Option<NodePtr>
+) -> Result<(), EvalError> {
+    let default_name = match target.as_ref() {
+        Node::Symbol(symbol) => Some(*symbol),
+        _ => None
+    };
+    let found = match default_name {
+        Some(name) => lookup(context, map, key, name, defaults)?,
+        None => call_builtin(context, "get", vec![map.clone(), key])?
+    };
+    bind(context, form, target, found)
+}
+
+/// Looks up `key` in `map`. If it's missing, the default given to `name` in the `:or` map is
+/// evaluated instead.
+fn lookup(context: &mut EvalContext, map: &NodePtr, key: NodePtr, name: Symbol, defaults: &Option<NodePtr>) -> Result<NodePtr, EvalError> {
+    let missing = Rc::new(Node::Nil);
+    let found = call_builtin(context, "get", vec![map.clone(), key, missing.clone()])?;
+    if !Rc::ptr_eq(&found, &missing) {
+        return Ok(found);
+    }
+    let default = match defaults.as_ref().map(|defaults| defaults.as_ref()) {
+        Some(Node::Map(defaults)) => defaults.get(&Rc::new(Node::Symbol(name))).cloned(),
+        _ => None
+    };
+    match default {
+        Some(default) => eval_expr(context, &default),
+        None => Ok(Rc::new(Node::Nil))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn sequential() {
+        assert_eq!(eval("(let [[a b & rest :as all] [1 2 3 4]] [a b rest all])"), "[1 2 (3 4) [1 2 3 4]]");
+        assert_eq!(eval("(let [[a b] nil] [a b])"), "[nil nil]");
+        assert_eq!(eval_err("(let [[a] 5] a)"), "seq: cannot create a seq from 5");
+    }
+
+    #[test]
+    fn associative() {
+        assert_eq!(eval("(let [{:keys [a b] :or {b 1} :as m} {:a 5}] [a b m])"), "[5 1 {:a 5}]");
+        assert_eq!(eval("[(let [{:strs [a]} {\"a\" 1}] a) (let [{:syms [a]} {'a 1}] a) (let [{:keys [a]} nil] a)]"), "[1 1 nil]");
+        assert_eq!(eval("(let [[a [b {:keys [c]}]] [1 [2 {:c 3}]]] [a b c])"), "[1 2 3]");
+    }
+
+    #[test]
+    fn shared_by_fn_loop_and_for() {
+        assert_eq!(eval("(defn h [{:keys [x]} [y]] [x y]) (h {:x 1} [2])"), "[1 2]");
+        assert_eq!(eval("(loop [[x & xs] [1 2 3] acc 0] (if x (recur xs (+ acc x)) acc))"), "6");
+        assert_eq!(eval("(for [[k v] {:a 1 :b 2}] [v k])"), "([1 :a] [2 :b])");
+    }
+}
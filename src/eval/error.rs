@@ -1,20 +1,461 @@
+use crate::nodes::{NodePtr, Node};
+use crate::intern::Symbol;
+use crate::parser::{Span, span_of, expansion_of};
+use std::rc::Rc;
+
+use super::Exception;
+
+/// What went wrong in an evaluation.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// A function or form called with the wrong number of arguments.
+    Arity {
+        name: String,
+        /// Number of arguments accepted, like `2` or `1 to 3`.
+        expected: String,
+        got: usize
+    },
+    /// A value of the wrong type, like a keyword given to `+`.
+    Type(String),
+    /// A symbol naming neither a local, a var nor a builtin.
+    UnboundSymbol(Symbol),
+    /// A var used before getting a value.
+    UnboundVar(Symbol),
+    /// A value called as a function but which isn't one.
+    NotCallable(NodePtr),
+    /// An index or a count outside of what a collection or a function allows.
+    OutOfBounds(String),
+    /// A special form or a binding form written the wrong way.
+    Syntax(String),
+    /// A macro that failed to expand. The cause tells why.
+    MacroExpansion(Symbol),
+    /// Exception thrown by `throw`, like the ones created by `ex-info`.
+    User(NodePtr),
+    /// The evaluation went deeper than its stack allows, like a function recursing forever.
+    StackOverflow,
+    Io(std::io::Error),
+    Other(String)
+}
+
+impl ErrorKind {
+
+    /// Machine readable name of the kind, which stays the same when messages change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::Arity { .. } => "arity",
+            ErrorKind::Type(_) => "type",
+            ErrorKind::UnboundSymbol(_) => "unbound-symbol",
+            ErrorKind::UnboundVar(_) => "unbound-var",
+            ErrorKind::NotCallable(_) => "not-callable",
+            ErrorKind::OutOfBounds(_) => "out-of-bounds",
+            ErrorKind::Syntax(_) => "syntax",
+            ErrorKind::MacroExpansion(_) => "macro-expansion",
+            ErrorKind::User(_) => "user",
+            ErrorKind::StackOverflow => "stack-overflow",
+            ErrorKind::Io(_) => "io",
+            ErrorKind::Other(_) => "other"
+        }
+    }
+
+    /// Kind of the exception a `catch` gets for errors of this kind.
+    fn exception_kind(&self) -> &'static str {
+        match self {
+            ErrorKind::Arity { .. } => "ArityError",
+            ErrorKind::Type(_) => "TypeError",
+            ErrorKind::UnboundSymbol(_) => "UnboundSymbolError",
+            ErrorKind::UnboundVar(_) => "UnboundVarError",
+            ErrorKind::NotCallable(_) => "NotCallableError",
+            ErrorKind::OutOfBounds(_) => "OutOfBoundsError",
+            ErrorKind::Syntax(_) => "SyntaxError",
+            ErrorKind::MacroExpansion(_) => "MacroExpansionError",
+            ErrorKind::User(_) => "ExceptionInfo",
+            ErrorKind::StackOverflow => "StackOverflowError",
+            ErrorKind::Io(_) => "IoError",
+            ErrorKind::Other(_) => "EvalError"
+        }
+    }
+
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::Arity { name, expected, got } => {
+                let arguments = match expected.as_str() {
+                    "1" | "at least 1" => "argument",
+                    _ => "arguments"
+                };
+                write!(f, "{}: expected {} {} but got {}", name, expected, arguments, got)
+            },
+            ErrorKind::UnboundSymbol(symbol) => write!(f, "Unable to resolve symbol: {}", symbol),
+            ErrorKind::UnboundVar(name) => write!(f, "Var {} is unbound", name),
+            ErrorKind::NotCallable(node) => write!(f, "Node {} is not a function", node),
+            ErrorKind::MacroExpansion(name) => write!(f, "macroexpand: unable to expand {}", name),
+            ErrorKind::User(node) => match node.as_ref() {
+                Node::Exception(exception) if matches!(exception.data().as_ref(), Node::Nil) => {
+                    write!(f, "{}: {}", exception.kind(), exception.message())
+                },
+                Node::Exception(exception) => {
+                    write!(f, "{}: {} {}", exception.kind(), exception.message(), exception.data())
+                },
+                node => write!(f, "{}", node)
+            },
+            ErrorKind::StackOverflow => write!(f, "StackOverflow: the evaluation went too deep"),
+            ErrorKind::Io(err) => write!(f, "{}", err),
+            ErrorKind::Type(message) | ErrorKind::OutOfBounds(message)
+                | ErrorKind::Syntax(message) | ErrorKind::Other(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+/// Most frames kept in a stack trace, the innermost ones. A stack overflow has thousands.
+const MAX_TRACE: usize = 64;
+
+/// Step of a stack trace.
+#[derive(Debug, Clone)]
+pub enum TraceFrame {
+    /// Call of a function, from the call at `span` if it's called by a form.
+    Call { name: Option<Symbol>, span: Option<Span> },
+    /// Form produced by the macro `name`, called at `span`.
+    Expansion { name: Symbol, span: Option<Span> }
+}
+
+impl TraceFrame {
+
+    /// The frame as a map, like `{:fn f, :file "repl", :line 1, :column 3}`. Macro
+    /// expansions have a `:macro` instead of a `:fn`.
+    pub fn to_node(&self) -> NodePtr {
+        let keyword = |name: &str| Rc::new(Node::Keyword(Symbol::intern(name)));
+        let (key, name, span) = match self {
+            TraceFrame::Call { name, span } => ("fn", name.map_or(Node::Nil, Node::Symbol), span),
+            TraceFrame::Expansion { name, span } => ("macro", Node::Symbol(*name), span)
+        };
+        let mut entries = vec![(keyword(key), Rc::new(name))];
+        if let Some(span) = span {
+            entries.push((keyword("file"), Rc::new(Node::String(span.start.source.name().to_string()))));
+            entries.push((keyword("line"), Rc::new(Node::Integer(span.start.line as i64))));
+            entries.push((keyword("column"), Rc::new(Node::Integer(span.start.column as i64))));
+        }
+        Rc::new(Node::Map(entries.into_iter().collect()))
+    }
+
+}
+
+impl std::fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let span = match self {
+            TraceFrame::Call { name: Some(name), span } => {
+                write!(f, "at {}", name)?;
+                span
+            },
+            TraceFrame::Call { name: None, span } => {
+                write!(f, "at fn")?;
+                span
+            },
+            TraceFrame::Expansion { name, span } => {
+                write!(f, "in expansion of {}", name)?;
+                span
+            }
+        };
+        match span {
+            Some(span) => write!(f, " ({})", span),
+            None => Ok(())
+        }
+    }
+}
+
+/// Error raised by an evaluation, with the form it was raised in when it was read from a
+/// source, the calls and macro expansions it was raised in, and the error that caused it.
+///
+/// The error is boxed, so results stay small.
+#[derive(Debug)]
+pub struct EvalError(Box<Details>);
+
 #[derive(Debug)]
-pub struct EvalError {
-    message: String
+struct Details {
+    kind: ErrorKind,
+    span: Option<Span>,
+    /// Frames of the stack trace, from the innermost one.
+    trace: Vec<TraceFrame>,
+    /// Whether outer frames were left out of the trace.
+    truncated: bool,
+    cause: Option<EvalError>
 }
 
 impl EvalError {
-    pub fn new(message: &str) -> Self {
-        Self {
-            message: String::from(message)
+
+    pub fn new(kind: ErrorKind) -> Self {
+        EvalError(Box::new(Details {
+            kind,
+            span: None,
+            trace: Vec::new(),
+            truncated: false,
+            cause: None
+        }))
+    }
+
+    pub fn arity(name: &str, expected: &str, got: usize) -> Self {
+        Self::new(ErrorKind::Arity { name: name.to_string(), expected: expected.to_string(), got })
+    }
+
+    pub fn type_error(message: &str) -> Self {
+        Self::new(ErrorKind::Type(message.to_string()))
+    }
+
+    pub fn out_of_bounds(message: &str) -> Self {
+        Self::new(ErrorKind::OutOfBounds(message.to_string()))
+    }
+
+    pub fn syntax(message: &str) -> Self {
+        Self::new(ErrorKind::Syntax(message.to_string()))
+    }
+
+    pub fn other(message: &str) -> Self {
+        Self::new(ErrorKind::Other(message.to_string()))
+    }
+
+    /// Error for a thrown exception. The causes of the exception become the causes of the
+    /// error.
+    pub fn user(exception: NodePtr) -> Self {
+        let cause = match exception.as_ref() {
+            Node::Exception(exception) => exception.cause().map(|cause| EvalError::user(cause.clone())),
+            _ => None
+        };
+        let mut err = Self::new(ErrorKind::User(exception));
+        err.0.cause = cause;
+        err
+    }
+
+    pub fn with_cause(mut self, cause: EvalError) -> Self {
+        self.0.cause = Some(cause);
+        self
+    }
+
+    /// Gives the error the span of `form`, unless it was raised in a nested form which already
+    /// gave it its own span. If the form comes from macro expansions, they're added to the
+    /// stack trace.
+    pub fn at(mut self, form: &NodePtr) -> Self {
+        if self.0.span.is_none() {
+            self.0.span = span_of(form);
+            let mut form = form.clone();
+            while let Some((name, call)) = expansion_of(&form) {
+                self.push_frame(TraceFrame::Expansion { name, span: span_of(&call) });
+                form = call;
+            }
         }
+        self
+    }
+
+    /// Adds a frame outside of the ones already in the stack trace.
+    pub fn push_frame(&mut self, frame: TraceFrame) {
+        match self.0.trace.len() < MAX_TRACE {
+            true => self.0.trace.push(frame),
+            false => self.0.truncated = true
+        }
+    }
+
+    /// Checks if the stack trace has all the frames it can keep.
+    pub fn is_trace_full(&self) -> bool {
+        self.0.trace.len() >= MAX_TRACE
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.0.kind.code()
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.0.span
+    }
+
+    pub fn cause(&self) -> Option<&EvalError> {
+        self.0.cause.as_ref()
+    }
+
+    pub fn trace(&self) -> &[TraceFrame] {
+        &self.0.trace
+    }
+
+    /// The stack trace as a vector of maps, see `TraceFrame::to_node`.
+    pub fn trace_node(&self) -> NodePtr {
+        Rc::new(Node::Vector(self.0.trace.iter().map(TraceFrame::to_node).collect()))
+    }
+
+    /// Checks if a `catch` of `kind` catches this error.
+    pub fn is_a(&self, kind: Symbol) -> bool {
+        match &self.0.kind {
+            ErrorKind::User(exception) => match exception.as_ref() {
+                Node::Exception(exception) => exception.is_a(kind),
+                _ => false
+            },
+            own => Exception::kind_is_a(own.exception_kind(), &kind.name())
+        }
+    }
+
+    /// The exception a `catch` gets for this error, with its stack trace. Thrown exceptions
+    /// are given as a copy identical to them, and the errors raised by the evaluator get a map with the code of
+    /// their kind and their stack trace as data.
+    pub fn to_exception(&self) -> NodePtr {
+        let trace = self.trace_node();
+        let exception = match &self.0.kind {
+            ErrorKind::User(exception) => match exception.as_ref() {
+                Node::Exception(thrown) => Exception::with_trace(thrown, trace),
+                _ => return exception.clone()
+            },
+            kind => {
+                let message = match kind {
+                    ErrorKind::StackOverflow => "the evaluation went too deep".to_string(),
+                    kind => kind.to_string()
+                };
+                let data = vec![
+                    (Rc::new(Node::Keyword(Symbol::intern("code"))), Rc::new(Node::Keyword(Symbol::intern(self.code())))),
+                    (Rc::new(Node::Keyword(Symbol::intern("trace"))), trace.clone())
+                ];
+                let cause = self.0.cause.as_ref().map(|cause| cause.to_exception());
+                let exception = Exception::new(Symbol::intern(kind.exception_kind()), &message, Rc::new(Node::Map(data.into_iter().collect())), cause);
+                Exception::with_trace(&Rc::new(exception), trace)
+            }
+        };
+        Rc::new(Node::Exception(Rc::new(exception)))
+    }
+
+}
+
+impl From<std::io::Error> for EvalError {
+    fn from(err: std::io::Error) -> Self {
+        EvalError::new(ErrorKind::Io(err))
     }
 }
 
+impl EvalError {
+
+    /// Lines describing the stack trace, with the frames repeated by a recursion written once.
+    pub fn trace_lines(&self) -> Vec<String> {
+        let frames = self.0.trace.iter().map(TraceFrame::to_string).collect::<Vec<String>>();
+        let mut lines = Vec::new();
+        let mut index = 0;
+        while index < frames.len() {
+            let repeated = frames[index..].iter().take_while(|frame| **frame == frames[index]).count();
+            lines.push(frames[index].clone());
+            if repeated > 1 {
+                lines.push(format!("... repeated {} more times", repeated - 1));
+            }
+            index += repeated;
+        }
+        if self.0.truncated {
+            lines.push("...".to_string());
+        }
+        lines
+    }
+
+    fn fmt_trace(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in self.trace_lines() {
+            write!(f, "\n    {}", line)?;
+        }
+        Ok(())
+    }
+
+}
+
 impl std::fmt::Display for EvalError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Eval Error: {}", self.message)
+        match self.0.span {
+            Some(span) => write!(f, "Eval Error ({}) {}", span, self.0.kind)?,
+            None => write!(f, "Eval Error: {}", self.0.kind)?
+        }
+        self.fmt_trace(f)?;
+        let mut cause = self.cause();
+        while let Some(err) = cause {
+            match err.0.span {
+                Some(span) => write!(f, "\nCaused by ({}) {}", span, err.0.kind)?,
+                None => write!(f, "\nCaused by: {}", err.0.kind)?
+            }
+            err.fmt_trace(f)?;
+            cause = err.cause();
+        }
+        Ok(())
     }
 }
 
-impl std::error::Error for EvalError {}
+impl std::error::Error for EvalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match (&self.0.cause, &self.0.kind) {
+            (Some(cause), _) => Some(cause),
+            (None, ErrorKind::Io(err)) => Some(err),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{error, eval};
+    use std::error::Error;
+
+    #[test]
+    fn kinds_have_stable_codes() {
+        let code = |source| error(source).code();
+        assert_eq!(code("(inc)"), "arity");
+        assert_eq!(code("(inc :a)"), "type");
+        assert_eq!(code("nope"), "unbound-symbol");
+        assert_eq!(code("(declare u) u"), "unbound-var");
+        assert_eq!(code("(1 2)"), "not-callable");
+        assert_eq!(code("(nth [] 1)"), "out-of-bounds");
+        assert_eq!(code("(let [x] x)"), "syntax");
+        assert_eq!(code("(throw (ex-info \"x\" {}))"), "user");
+        assert_eq!(eval("(try (inc) (catch ArityError e (:code (ex-data e))))"), ":arity");
+    }
+
+    #[test]
+    fn errors_point_at_their_form() {
+        let err = error("(def x 1)\n(+ x\n   (inc :a))");
+        let span = err.span().unwrap();
+        assert_eq!((span.start.line, span.start.column), (3, 4));
+        assert_eq!(err.to_string(), "Eval Error (test:3:4) inc: expected a number but got :a");
+    }
+
+    #[test]
+    fn causes_are_chained() {
+        let err = error("(defmacro bad [] (inc :a)) (bad)");
+        assert_eq!(err.code(), "macro-expansion");
+        let cause = err.source().unwrap();
+        assert_eq!(cause.to_string().lines().next(), Some("Eval Error (test:1:18) inc: expected a number but got :a"));
+        assert!(cause.source().is_none());
+
+        let err = error("(throw (ex-info \"outer\" {} (ex-info \"inner\" {})))");
+        assert_eq!(err.cause().map(|cause| cause.kind().to_string()), Some("ExceptionInfo: inner {}".to_string()));
+    }
+
+    #[test]
+    fn traces_record_the_calls() {
+        let err = error("(defn add [a b] (+ a b))\n(defn g [x] (add x :k))\n(defn h [x] (let [y (g x)] y))\n(h 1)");
+        assert_eq!(err.trace_lines(), vec!["at add (test:2:13)", "at g (test:3:21)", "at h (test:4:1)"]);
+        assert_eq!(
+            eval("(defn f [] (inc :a)) (try (f) (catch TypeError e (ex-trace e)))"),
+            "[{:fn f, :file \"test\", :line 1, :column 27}]");
+    }
+
+    #[test]
+    fn rethrown_exceptions_get_their_own_traces() {
+        let source = "(def e (ex-info \"boom\" {}))\n(defn f [] (throw e))\n(defn g [] (f))\n\
+            (def caught (try (g) (catch ExceptionInfo x x)))\n(def again (try (f) (catch ExceptionInfo x x)))\n\
+            [(count (ex-trace caught)) (count (ex-trace again)) (ex-trace e) (= e caught again) (get #{e} again)]";
+        assert_eq!(eval(source), "[2 1 nil true #error {:kind ExceptionInfo, :message \"boom\", :data {}}]");
+    }
+
+    #[test]
+    fn repeated_frames_are_collapsed() {
+        let err = error("(defn deep [n] (if (= n 0) (throw (ex-info \"bottom\" {})) (+ 1 (deep (- n 1)))))\n(deep 3)");
+        assert_eq!(err.trace_lines(), vec!["at deep (test:1:63)", "... repeated 2 more times", "at deep (test:2:1)"]);
+        assert_eq!(err.trace().len(), 4);
+    }
+
+    #[test]
+    fn expansions_are_part_of_the_trace() {
+        let err = error("(defmacro bad-let [] `(let [x#] 1))\n(defn k [] (bad-let))\n(k)");
+        assert_eq!(err.trace_lines(), vec!["in expansion of user/bad-let (test:2:12)", "at k (test:3:1)"]);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::nodes::{NodePtr, Node};
+use crate::intern::Symbol;
+use std::rc::Rc;
+
+/// Kinds of exceptions, which `catch` can name, with the kind they're a case of. `Throwable`
+/// catches every exception, `Exception` every one but stack overflows, and `EvalError` the
+/// errors raised by the evaluator.
+const KINDS: [(&str, Option<&str>); 14] = [
+    ("Throwable", None),
+    ("Exception", Some("Throwable")),
+    ("StackOverflowError", Some("Throwable")),
+    ("ExceptionInfo", Some("Exception")),
+    ("EvalError", Some("Exception")),
+    ("ArityError", Some("EvalError")),
+    ("TypeError", Some("EvalError")),
+    ("UnboundSymbolError", Some("EvalError")),
+    ("UnboundVarError", Some("EvalError")),
+    ("NotCallableError", Some("EvalError")),
+    ("OutOfBoundsError", Some("EvalError")),
+    ("SyntaxError", Some("EvalError")),
+    ("MacroExpansionError", Some("EvalError")),
+    ("IoError", Some("EvalError"))
+];
+
+fn parent(kind: &str) -> Option<&'static str> {
+    KINDS.iter().find(|(name, _)| *name == kind).and_then(|(_, parent)| *parent)
+}
+
+/// Value thrown by `throw` and caught by `try`. Errors raised by the evaluator are caught as
+/// exceptions too, with a kind telling what went wrong.
+#[derive(Debug)]
+pub struct Exception {
+    kind: Symbol,
+    message: String,
+    /// Map given to `ex-info`, or nil for other exceptions.
+    data: NodePtr,
+    /// Exception that caused this one.
+    cause: Option<NodePtr>,
+    /// Stack trace of where the exception was thrown, in the copies given to `catch`.
+    trace: Option<NodePtr>,
+    /// Exception this is a copy of, which it's identical to.
+    original: Option<Rc<Exception>>
+}
+
+impl Exception {
+
+    pub fn new(kind: Symbol, message: &str, data: NodePtr, cause: Option<NodePtr>) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+            data,
+            cause,
+            trace: None,
+            original: None
+        }
+    }
+
+    pub fn is_kind(kind: Symbol) -> bool {
+        KINDS.iter().any(|(name, _)| *name == kind.name().as_ref())
+    }
+
+    pub fn kind(&self) -> Symbol {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn data(&self) -> &NodePtr {
+        &self.data
+    }
+
+    pub fn cause(&self) -> Option<&NodePtr> {
+        self.cause.as_ref()
+    }
+
+    pub fn trace(&self) -> Option<&NodePtr> {
+        self.trace.as_ref()
+    }
+
+    /// Copy of `exception` with the stack trace of one of its throws. It stays identical to
+    /// `exception`, which keeps the trace it had, so rethrowing it elsewhere changes no trace.
+    pub fn with_trace(exception: &Rc<Exception>, trace: NodePtr) -> Self {
+        let original = exception.original.clone().unwrap_or_else(|| exception.clone());
+        Self {
+            kind: exception.kind,
+            message: exception.message.clone(),
+            data: exception.data.clone(),
+            cause: exception.cause.clone(),
+            trace: Some(trace),
+            original: Some(original)
+        }
+    }
+
+    /// The exception `=` and hashing go by, shared by the copies given to each `catch`.
+    pub fn identity(&self) -> *const Exception {
+        self.original.as_ref().map_or(self as *const Exception, Rc::as_ptr)
+    }
+
+    /// Checks if a `catch` of `kind` catches this exception.
+    pub fn is_a(&self, kind: Symbol) -> bool {
+        Self::kind_is_a(&self.kind.name(), &kind.name())
+    }
+
+    /// Checks if exceptions of the kind `own` are a case of `kind`.
+    pub fn kind_is_a(own: &str, kind: &str) -> bool {
+        let mut current = Some(own);
+        while let Some(name) = current {
+            if name == kind {
+                return true;
+            }
+            current = parent(name);
+        }
+        false
+    }
+
+}
+
+impl std::fmt::Display for Exception {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#error {{:kind {}, :message \"{}\"", self.kind, self.message)?;
+        if !matches!(self.data.as_ref(), Node::Nil) {
+            write!(f, ", :data {}", self.data)?;
+        }
+        if let Some(cause) = &self.cause {
+            write!(f, ", :cause {}", cause)?;
+        }
+        write!(f, "}}")
+    }
+}
@@ -0,0 +1,251 @@
+use crate::nodes::{NodePtr, Node, list_from};
+use crate::intern::Symbol;
+use crate::context::{EvalContext, Frame};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use super::EvalError;
+use super::destructure::bind;
+
+/// One parameter list of a function with its body, like `([x y & more] body...)`.
+pub struct Arity {
+    params: Vec<NodePtr>,
+    rest: Option<NodePtr>,
+    body: NodePtr
+}
+
+impl Arity {
+
+    /// Reads the binding forms of a parameter vector, checking where `&` is used.
+    pub fn new(params: &[NodePtr], body: NodePtr) -> Result<Self, EvalError> {
+        let ampersand = params.iter().position(|param| matches!(param.as_ref(), Node::Symbol(Symbol::AMPERSAND)));
+        let (params, rest) = match ampersand {
+            Some(index) if index + 2 == params.len() => (&params[..index], Some(params[index + 1].clone())),
+            Some(_) => return Err(EvalError::syntax("fn: expected a single binding form after &")),
+            None => (params, None)
+        };
+        Ok(Self {
+            params: params.to_vec(),
+            rest,
+            body
+        })
+    }
+
+    /// Adds parameters before the declared ones, like the implicit `&form` and `&env` of macros.
+    pub fn with_leading_params(mut self, params: &[NodePtr]) -> Self {
+        self.params.splice(0..0, params.iter().cloned());
+        self
+    }
+
+    fn accepts(&self, count: usize) -> bool {
+        match self.rest {
+            Some(_) => count >= self.params.len(),
+            None => count == self.params.len()
+        }
+    }
+
+}
+
+/// Code of a `fn` form, shared by all the closures created from it.
+pub struct Lambda {
+    name: Option<Symbol>,
+    arities: Vec<Arity>
+}
+
+impl Lambda {
+
+    /// Checks that calls can't match more than one of the arities.
+    pub fn new(name: Option<Symbol>, arities: Vec<Arity>) -> Result<Self, EvalError> {
+        let variadic = arities.iter().filter(|arity| arity.rest.is_some()).collect::<Vec<&Arity>>();
+        if variadic.len() > 1 {
+            return Err(EvalError::syntax("fn: can't have more than one variadic arity"));
+        }
+        for (index, arity) in arities.iter().enumerate() {
+            if arity.rest.is_some() {
+                continue;
+            }
+            if arities[..index].iter().any(|other| other.rest.is_none() && other.params.len() == arity.params.len()) {
+                return Err(EvalError::syntax("fn: can't have two arities with the same number of parameters"));
+            }
+            if variadic.iter().any(|variadic| variadic.params.len() < arity.params.len()) {
+                return Err(EvalError::syntax("fn: can't have a fixed arity with more parameters than the variadic one"));
+            }
+        }
+        Ok(Self {
+            name,
+            arities
+        })
+    }
+
+    /// Describes the accepted argument counts for arity errors, like "1, 2 or at least 4".
+    fn expected(&self) -> String {
+        let mut counts = self.arities.iter().map(|arity| (arity.params.len(), arity.rest.is_some())).collect::<Vec<_>>();
+        counts.sort();
+        let counts = counts.iter().map(|(count, variadic)| match variadic {
+            true => format!("at least {}", count),
+            false => format!("{}", count)
+        }).collect::<Vec<String>>();
+        match counts.split_last() {
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+            None => "no".to_string()
+        }
+    }
+
+}
+
+/// Function created by a `fn` form. It keeps the frame it was created in, so its body can
+/// refer to the locals that are visible there.
+///
+/// Closures don't hold the root context, which is taken from the caller instead, so
+/// functions stored in globals don't keep the root context alive.
+#[derive(Clone)]
+pub struct Function {
+    lambda: Rc<Lambda>,
+    env: Rc<Frame>
+}
+
+impl Function {
+
+    pub fn new(lambda: Rc<Lambda>, env: Rc<Frame>) -> Self {
+        Self {
+            lambda,
+            env
+        }
+    }
+
+    pub fn name(&self) -> Option<Symbol> {
+        self.lambda.name
+    }
+
+    /// Finds the arity matching the number of arguments. Returns its index with the values
+    /// of its parameters, where the rest arguments are gathered in a list.
+    pub fn select(&self, mut args: Vec<NodePtr>) -> Result<(usize, Vec<NodePtr>), EvalError> {
+        let index = match self.lambda.arities.iter().position(|arity| arity.accepts(args.len())) {
+            Some(index) => index,
+            None => {
+                let name = self.lambda.name.map_or_else(|| "fn".to_string(), |name| name.name().to_string());
+                return Err(EvalError::arity(&name, &self.lambda.expected(), args.len()));
+            }
+        };
+        let arity = &self.lambda.arities[index];
+        if arity.rest.is_some() {
+            // Like `next`, the rest arguments are nil rather than an empty list.
+            let rest = args.split_off(arity.params.len());
+            args.push(match rest.is_empty() {
+                true => Rc::new(Node::Nil),
+                false => list_from(rest)
+            });
+        }
+        Ok((index, args))
+    }
+
+    /// Creates the frame the body of an arity is evaluated in, binding its parameters to
+    /// `values`. The values of a `recur` are bound the same way, including the rest parameter.
+    pub fn enter(&self, context: &EvalContext, arity: usize, values: Vec<NodePtr>) -> Result<Rc<Frame>, EvalError> {
+        let arity = &self.lambda.arities[arity];
+        let count = arity.params.len() + arity.rest.iter().len();
+        if values.len() != count {
+            return Err(EvalError::arity("recur", &count.to_string(), values.len()));
+        }
+        let mut frame = context.enter(&self.env);
+        for (param, value) in arity.params.iter().chain(&arity.rest).zip(values) {
+            bind(&mut frame, "fn", param, value)?;
+        }
+        Ok(frame.frame().clone())
+    }
+
+    pub fn body(&self, arity: usize) -> &NodePtr {
+        &self.lambda.arities[arity].body
+    }
+
+}
+
+/// Functions can't be compared by what they do, so a function is only equal to itself: the
+/// same lambda closing over the same frame. Closures bound by name, like the functions of
+/// `letfn`, are rebuilt on each lookup and still equal each other.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.lambda, &other.lambda) && Rc::ptr_eq(&self.env, &other.env)
+    }
+}
+
+impl Eq for Function {}
+
+impl Hash for Function {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.lambda.as_ref(), state);
+        std::ptr::hash(self.env.as_ref(), state);
+    }
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Function({:?}, {} arities)", self.lambda.name, self.lambda.arities.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::testing::{eval, eval_err};
+
+    fn recursive_frame(name: Symbol) -> Rc<Frame> {
+        let arity = Arity::new(&[], Rc::new(Node::Nil)).unwrap();
+        let frame = Rc::new(Frame::new(None));
+        frame.set_recursive(name, Rc::new(Lambda::new(Some(name), vec![arity]).unwrap()));
+        frame
+    }
+
+    #[test]
+    fn recursive_bindings_dont_keep_their_frame_alive() {
+        let name = Symbol::intern("self");
+        let frame = recursive_frame(name);
+        let weak = Rc::downgrade(&frame);
+        let function = Frame::lookup(&frame, name).unwrap();
+        drop(frame);
+        // The closure holds the frame, but the frame doesn't hold the closure.
+        assert!(weak.upgrade().is_some());
+        drop(function);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn closures_rebuilt_by_lookup_are_equal() {
+        let name = Symbol::intern("self");
+        let frame = recursive_frame(name);
+        let (a, b) = (Frame::lookup(&frame, name).unwrap(), Frame::lookup(&frame, name).unwrap());
+        assert!(!Rc::ptr_eq(&a, &b));
+        assert_eq!(a, b);
+        let hash = |node: &NodePtr| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            node.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&a), hash(&b));
+        assert_ne!(a, Frame::lookup(&recursive_frame(name), name).unwrap());
+    }
+
+    #[test]
+    fn closures_see_later_definitions() {
+        assert_eq!(eval("(defn ev? [n] (if (= n 0) true (od? (- n 1)))) (defn od? [n] (if (= n 0) false (ev? (- n 1)))) (ev? 10)"), "true");
+        assert_eq!(eval("(def g (fn [] later)) (def later 5) (def a (g)) (def later 6) [a (g)]"), "[5 6]");
+        assert_eq!(eval("(let [x 1 f (fn [] x) x 2] [(f) x])"), "[1 2]");
+    }
+
+    #[test]
+    fn function_equality() {
+        assert_eq!(eval("(let [f (fn [] 1)] (= f f))"), "true");
+        assert_eq!(eval("[(= (fn [] 1) (fn [] 1)) (let [mk (fn [x] (fn [] x))] (= (mk 1) (mk 1)))]"), "[false false]");
+        assert_eq!(eval("(letfn [(f [] 1)] [(= f f) (contains? #{f} f)])"), "[true true]");
+    }
+
+    #[test]
+    fn multiple_arities() {
+        assert_eq!(eval("(def f (fn ([x] 1) ([x y] 2) ([x y & more] more))) [(f 1) (f 1 2) (f 1 2 3 4)]"), "[1 2 (3 4)]");
+        assert_eq!(eval("((fn fact [n] (if (= n 0) 1 (* n (fact (- n 1))))) 5)"), "120");
+        assert_eq!(eval_err("(defn g ([x] 1) ([x y] 2)) (g 1 2 3)"), "g: expected 1 or 2 arguments but got 3");
+        assert_eq!(eval_err("(fn ([x] 1) ([y] 2))"), "fn: can't have two arities with the same number of parameters");
+        assert_eq!(eval_err("(fn ([& x] 1) ([& y] 2))"), "fn: can't have more than one variadic arity");
+    }
+}
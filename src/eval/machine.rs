@@ -0,0 +1,633 @@
+use crate::nodes::{NodePtr, Node, IntoListIter, list_from};
+use crate::intern::Symbol;
+use crate::context::{EvalContext, Frame};
+use std::rc::Rc;
+use std::task::Poll;
+
+use super::{EvalResult, EvalError, ErrorKind, TraceFrame, Function, Exception, resolve, apply, arguments};
+use super::special::{self, SpecialForm, split_bindings, symbol_arg, try_clause};
+use super::destructure::bind;
+use super::{analyze, macros, stack};
+use crate::parser::{span_of, elements_of};
+use crate::collections::{PersistentHashMap, PersistentHashSet};
+
+/// Evaluation of a form, with the work left to do kept in a stack of continuations on the
+/// heap rather than in the Rust stack, about two for each function call in progress. Calls
+/// can nest as deep as memory allows, or as the `max_depth` of the root context if it has
+/// one, and going deeper fails with a `StackOverflow` error. The evaluation can be paused
+/// and resumed between any two steps.
+pub struct Evaluation {
+    context: EvalContext,
+    /// What to do next, or `None` once the evaluation is done.
+    state: Option<State>,
+    stack: Vec<Continuation>
+}
+
+enum State {
+    Eval(NodePtr, Rc<Frame>),
+    /// Calls a function with evaluated arguments, from code in the frame. Also has the form
+    /// of the call, if there's one.
+    Apply(NodePtr, Vec<NodePtr>, Rc<Frame>, Option<NodePtr>),
+    /// Gives a result to the continuation on top of the stack.
+    Return(Tail)
+}
+
+/// Result of a form evaluated in tail position, which can be a `recur` to the enclosing
+/// `loop` or function instead of a value.
+enum Tail {
+    Value(NodePtr),
+    Recur(Vec<NodePtr>)
+}
+
+impl Tail {
+    fn value(self) -> EvalResult {
+        match self {
+            Tail::Value(value) => Ok(value),
+            Tail::Recur(_) => Err(EvalError::syntax("recur: can only be used in tail position"))
+        }
+    }
+}
+
+/// Binding vector of a `let` or `loop`, with the body evaluated after it.
+struct Bindings {
+    form: &'static str,
+    pairs: Rc<[NodePtr]>,
+    body: NodePtr,
+    /// Frame the `let` or `loop` is evaluated in.
+    outer: Rc<Frame>
+}
+
+/// `(catch kind name body...)` clause of a `try`.
+struct Handler {
+    kind: Symbol,
+    name: Symbol,
+    body: NodePtr
+}
+
+/// What to do with the result of the form being evaluated.
+enum Continuation {
+    /// Evaluates a branch of an `if` depending on the result of its test.
+    If { then: NodePtr, otherwise: Option<NodePtr>, env: Rc<Frame> },
+    /// Evaluates the forms left in a body.
+    Body { rest: NodePtr, env: Rc<Frame> },
+    /// Collects the value of the head or an argument of the call `form`, or of an argument
+    /// of a `recur`, then evaluates the next one.
+    Args { values: Vec<NodePtr>, rest: NodePtr, env: Rc<Frame>, form: NodePtr, recur: bool },
+    /// Binds the name at `index` in a binding vector to the result, in a new frame nested in
+    /// `env`.
+    Bind { bindings: Bindings, index: usize, env: Rc<Frame> },
+    /// Evaluates the body of a `loop` again on `recur`.
+    Loop { bindings: Bindings },
+    /// Evaluates the body of a function again on `recur`. Also marks the call in stack traces,
+    /// with its form if there's one.
+    Function { function: Function, arity: usize, call: Option<NodePtr> },
+    /// Catches the errors raised while evaluating the body of a `try` with its handlers, then
+    /// evaluates its `finally` body however the `try` body or handler is left.
+    Try { handlers: Rc<[Handler]>, finally: Option<NodePtr>, env: Rc<Frame> },
+    /// Goes on with the result or the error that left a `try`, once its `finally` body is
+    /// evaluated.
+    Finally { completion: Result<Tail, EvalError> },
+    /// Throws the result, from the `throw` form.
+    Throw { form: NodePtr },
+    /// Evaluates the branch of a `case*` found under the result in `table`, or `default`.
+    Case { table: NodePtr, default: NodePtr, env: Rc<Frame> },
+    /// Collects the value of an element of a vector, map or set literal, then evaluates the
+    /// next one. The keys and values of maps are evaluated in turn.
+    Collection { literal: NodePtr, elements: Rc<[NodePtr]>, values: Vec<NodePtr>, env: Rc<Frame> }
+}
+
+impl Evaluation {
+
+    /// Evaluation of `form` in the frame of `context`.
+    pub fn new(context: &EvalContext, form: NodePtr) -> Self {
+        Self {
+            context: context.clone(),
+            state: Some(State::Eval(form, context.frame().clone())),
+            stack: Vec::new()
+        }
+    }
+
+    /// Evaluation of a call to `function` with evaluated arguments.
+    pub fn apply(context: &EvalContext, function: NodePtr, args: Vec<NodePtr>) -> Self {
+        Self {
+            context: context.clone(),
+            state: Some(State::Apply(function, args, context.frame().clone(), None)),
+            stack: Vec::new()
+        }
+    }
+
+    /// Runs the evaluation until its result is ready. If a `budget` is given, it pauses
+    /// after that many steps instead, and continues where it left off when run again.
+    pub fn run(&mut self, budget: Option<usize>) -> Poll<EvalResult> {
+        // Unlike the nested calls of a single evaluation, evaluations nested in each other use
+        // the Rust stack: a builtin calling a function starts a new evaluation.
+        if let Err(err) = stack::check() {
+            self.state = None;
+            return Poll::Ready(Err(err));
+        }
+        self.run_steps(budget)
+    }
+
+    /// Runs the evaluation until its result is ready.
+    pub fn finish(mut self) -> EvalResult {
+        match self.run(None) {
+            Poll::Ready(result) => result,
+            Poll::Pending => unreachable!()
+        }
+    }
+
+    fn run_steps(&mut self, mut budget: Option<usize>) -> Poll<EvalResult> {
+        loop {
+            match &mut budget {
+                Some(0) => return Poll::Pending,
+                Some(budget) => *budget -= 1,
+                None => ()
+            }
+            // Errors get the span of the form they're raised in, or of the call for the ones
+            // raised by calling a function.
+            let next = match self.state.take() {
+                Some(State::Eval(form, env)) => self.eval(form.clone(), env).map_err(|err| err.at(&form)),
+                Some(State::Apply(function, args, env, call)) => self.apply_function(function, args, env, call.clone())
+                    .map_err(|err| match &call {
+                        Some(call) => err.at(call),
+                        None => err
+                    }),
+                Some(State::Return(tail)) => match self.stack.pop() {
+                    Some(continuation) => self.resume(continuation, tail),
+                    None => return Poll::Ready(tail.value())
+                },
+                None => return Poll::Ready(Err(EvalError::other("The evaluation is already done")))
+            };
+            match next.or_else(|err| self.unwind(err)) {
+                Ok(state) => self.state = Some(state),
+                Err(err) => return Poll::Ready(Err(err))
+            }
+        }
+    }
+
+    /// Fails with a `StackOverflow` error if the stack can't grow, rather than aborting the
+    /// process. Where memory is overcommitted, the system may kill the process first.
+    fn push(&mut self, continuation: Continuation) -> Result<(), EvalError> {
+        self.stack.try_reserve(1).map_err(|_| EvalError::new(ErrorKind::StackOverflow))?;
+        self.stack.push(continuation);
+        Ok(())
+    }
+
+    /// Pops continuations until a `try` handles `err`, returning what to do next. The
+    /// `finally` bodies on the way are evaluated first. Fails with the error once the stack is
+    /// empty.
+    ///
+    /// The calls left are added to the stack trace of the error.
+    fn unwind(&mut self, mut err: EvalError) -> Result<State, EvalError> {
+        while let Some(continuation) = self.stack.pop() {
+            let (handlers, finally, env) = match continuation {
+                Continuation::Try { handlers, finally, env } => (handlers, finally, env),
+                Continuation::Function { function, call, .. } => {
+                    err.push_frame(call_frame(&function, &call));
+                    continue;
+                },
+                _ => continue
+            };
+            if let Some(handler) = handlers.iter().find(|handler| err.is_a(handler.kind)) {
+                // The caught exception gets the whole stack trace, with the calls the `try`
+                // is in. The frames past a full trace only mark it as truncated.
+                for continuation in self.stack.iter().rev() {
+                    if let Continuation::Function { function, call, .. } = continuation {
+                        let full = err.is_trace_full();
+                        err.push_frame(call_frame(function, call));
+                        if full {
+                            break;
+                        }
+                    }
+                }
+                let exception = err.to_exception();
+                // The `finally` body is still evaluated when the handler is left.
+                if finally.is_some() {
+                    self.stack.push(Continuation::Try { handlers: Rc::new([]), finally, env: env.clone() });
+                }
+                let context = self.context.enter(&env);
+                context.set_var(handler.name, exception);
+                return self.body(&handler.body, context.frame().clone());
+            }
+            if let Some(finally) = finally {
+                self.stack.push(Continuation::Finally { completion: Err(err) });
+                return self.body(&finally, env);
+            }
+        }
+        Err(err)
+    }
+
+    /// Context for running a builtin or a special form from code in `env`.
+    fn context(&self, env: &Rc<Frame>) -> EvalContext {
+        self.context.in_frame(env)
+    }
+
+    fn eval(&mut self, form: NodePtr, env: Rc<Frame>) -> Result<State, EvalError> {
+        let (head, args) = match form.as_ref() {
+            Node::List(head, args) => (head, args),
+            Node::Symbol(symbol) => return Ok(value(resolve(&self.context(&env), *symbol)?)),
+            Node::Vector(_) | Node::Map(_) | Node::Set(_) if !is_constant(&form) => {
+                let elements = literal_elements(&form);
+                return self.collection(form, elements.into(), Vec::new(), env);
+            },
+            _ => return Ok(value(form))
+        };
+        let special = match head.as_ref() {
+            Node::Symbol(symbol) => special::lookup(*symbol),
+            _ => None
+        };
+        match special {
+            Some(SpecialForm::Function(function)) => Ok(value(function(&mut self.context(&env), args)?)),
+            Some(SpecialForm::If) => {
+                let args = arguments("if", args, 2, Some(3))?;
+                self.push(Continuation::If { then: args[1].clone(), otherwise: args.get(2).cloned(), env: env.clone() })?;
+                Ok(State::Eval(args[0].clone(), env))
+            },
+            Some(SpecialForm::Do) => self.body(args, env),
+            Some(SpecialForm::Let) => self.bindings("let", args, env),
+            Some(SpecialForm::AnalyzedLoop) => self.bindings("loop", args, env),
+            Some(SpecialForm::Loop | SpecialForm::LetFn) => Ok(State::Eval(analyze::analyze(&mut self.context(&env), &form)?, env)),
+            Some(SpecialForm::AnalyzedLetFn) => {
+                let (specs, body) = split_bindings("letfn", args)?;
+                let frame = special::letfn_frame(&env, &specs)?;
+                self.body(&body, frame)
+            },
+            Some(SpecialForm::Recur) => self.args(Vec::new(), args, env, &form, true),
+            Some(SpecialForm::Try) => {
+                let (body, handlers, finally) = split_try(args)?;
+                if !handlers.is_empty() || finally.is_some() {
+                    self.push(Continuation::Try { handlers: handlers.into(), finally, env: env.clone() })?;
+                }
+                self.body(&body, env)
+            },
+            Some(SpecialForm::Throw) => {
+                let args = arguments("throw", args, 1, Some(1))?;
+                self.push(Continuation::Throw { form: form.clone() })?;
+                Ok(State::Eval(args[0].clone(), env))
+            },
+            Some(SpecialForm::Case) => {
+                let args = arguments("case*", args, 3, Some(3))?;
+                self.push(Continuation::Case { table: args[1].clone(), default: args[2].clone(), env: env.clone() })?;
+                Ok(State::Eval(args[0].clone(), env))
+            },
+            None => match macros::macroexpand_1(&mut self.context(&env), &form, &[])? {
+                Some(expansion) => Ok(State::Eval(expansion, env)),
+                None => self.args(Vec::new(), &form, env, &form, false)
+            }
+        }
+    }
+
+    /// Evaluates the forms of a body in order, with the last one in tail position.
+    fn body(&mut self, body: &NodePtr, env: Rc<Frame>) -> Result<State, EvalError> {
+        match body.as_ref() {
+            Node::List(form, rest) => {
+                if !matches!(rest.as_ref(), Node::Nil) {
+                    self.push(Continuation::Body { rest: rest.clone(), env: env.clone() })?;
+                }
+                Ok(State::Eval(form.clone(), env))
+            },
+            _ => Ok(value(Rc::new(Node::Nil)))
+        }
+    }
+
+    /// Evaluates the forms left in `rest` one after the other, collecting their values. Then
+    /// calls the first value with the other ones, or returns them as a `recur`.
+    fn args(&mut self, values: Vec<NodePtr>, rest: &NodePtr, env: Rc<Frame>, form: &NodePtr, recur: bool) -> Result<State, EvalError> {
+        match rest.as_ref() {
+            Node::List(arg, rest) => {
+                self.push(Continuation::Args { values, rest: rest.clone(), env: env.clone(), form: form.clone(), recur })?;
+                Ok(State::Eval(arg.clone(), env))
+            },
+            _ if recur => Ok(State::Return(Tail::Recur(values))),
+            _ => {
+                let mut values = values;
+                let function = values.remove(0);
+                Ok(State::Apply(function, values, env, Some(form.clone())))
+            }
+        }
+    }
+
+    /// Evaluates the elements left in a collection literal one after the other, then builds
+    /// the collection of their values.
+    fn collection(&mut self, literal: NodePtr, elements: Rc<[NodePtr]>, values: Vec<NodePtr>, env: Rc<Frame>) -> Result<State, EvalError> {
+        match elements.get(values.len()) {
+            Some(element) => {
+                let element = element.clone();
+                self.push(Continuation::Collection { literal, elements, values, env: env.clone() })?;
+                Ok(State::Eval(element, env))
+            },
+            None => {
+                let collection = match literal.as_ref() {
+                    Node::Vector(_) => Node::Vector(values.into_iter().collect()),
+                    Node::Map(_) => {
+                        let mut map = PersistentHashMap::new();
+                        for entry in values.chunks(2) {
+                            if !map.insert_mut(entry[0].clone(), entry[1].clone()) {
+                                return Err(EvalError::other(&format!("Duplicate key in map literal: {}", entry[0])).at(&literal));
+                            }
+                        }
+                        Node::Map(map)
+                    },
+                    _ => {
+                        let mut set = PersistentHashSet::new();
+                        for element in values {
+                            let duplicate = element.to_string();
+                            if !set.insert_mut(element) {
+                                return Err(EvalError::other(&format!("Duplicate element in set literal: {}", duplicate)).at(&literal));
+                            }
+                        }
+                        Node::Set(set)
+                    }
+                };
+                Ok(value(Rc::new(collection)))
+            }
+        }
+    }
+
+    fn bindings(&mut self, form: &'static str, args: &NodePtr, env: Rc<Frame>) -> Result<State, EvalError> {
+        let (pairs, body) = split_bindings(form, args)?;
+        if !pairs.len().is_multiple_of(2) {
+            return Err(EvalError::syntax(&format!("{}: expected an even number of forms in the binding vector", form)));
+        }
+        let bindings = Bindings { form, pairs: pairs.into(), body, outer: env.clone() };
+        self.next_binding(bindings, 0, env)
+    }
+
+    /// Evaluates the value of the binding at `index` in `env`, or the body once all the
+    /// names are bound. The body of a `loop` can `recur` back to it.
+    fn next_binding(&mut self, bindings: Bindings, index: usize, env: Rc<Frame>) -> Result<State, EvalError> {
+        match bindings.pairs.get(index + 1) {
+            Some(form) => {
+                let form = form.clone();
+                self.push(Continuation::Bind { bindings, index, env: env.clone() })?;
+                Ok(State::Eval(form, env))
+            },
+            None => {
+                let body = bindings.body.clone();
+                if bindings.form == "loop" {
+                    self.push(Continuation::Loop { bindings })?;
+                }
+                self.body(&body, env)
+            }
+        }
+    }
+
+    fn apply_function(&mut self, function: NodePtr, args: Vec<NodePtr>, env: Rc<Frame>, call: Option<NodePtr>) -> Result<State, EvalError> {
+        match function.as_ref() {
+            Node::Function(function) => {
+                let (arity, values) = function.select(args)?;
+                let frame = function.enter(&self.context, arity, values)?;
+                // A function calling itself in tail position, like a `defn` through its var,
+                // takes the place of the call it returns from, so it runs in constant space.
+                // Like with `recur`, stack traces show the call that entered it.
+                let call = match self.stack.last_mut() {
+                    Some(Continuation::Function { function: caller, call: first, .. }) if *caller == *function => {
+                        let first = first.take();
+                        self.stack.pop();
+                        first
+                    },
+                    _ => call
+                };
+                // Only calls can make the stack grow without end, so the limit is checked
+                // here, and the error points at the call.
+                if matches!(self.context.root().max_depth(), Some(max) if self.stack.len() >= max) {
+                    return Err(EvalError::new(ErrorKind::StackOverflow));
+                }
+                self.push(Continuation::Function { function: function.clone(), arity, call })?;
+                self.body(function.body(arity), frame)
+            },
+            Node::Var(var) => match var.get() {
+                Some(value) => Ok(State::Apply(value, args, env, call)),
+                None => Err(EvalError::new(ErrorKind::UnboundVar(var.name())))
+            },
+            _ => Ok(value(apply(&mut self.context(&env), &function, &list_from(args))?))
+        }
+    }
+
+    fn resume(&mut self, continuation: Continuation, tail: Tail) -> Result<State, EvalError> {
+        match continuation {
+            Continuation::If { then, otherwise, env } => {
+                match (tail.value()?.is_truthy(), otherwise) {
+                    (true, _) => Ok(State::Eval(then, env)),
+                    (false, Some(otherwise)) => Ok(State::Eval(otherwise, env)),
+                    (false, None) => Ok(value(Rc::new(Node::Nil)))
+                }
+            },
+            Continuation::Body { rest, env } => {
+                tail.value()?;
+                self.body(&rest, env)
+            },
+            Continuation::Args { mut values, rest, env, form, recur } => {
+                values.push(tail.value()?);
+                self.args(values, &rest, env, &form, recur)
+            },
+            Continuation::Bind { bindings, index, env } => {
+                let mut context = self.context.enter(&env);
+                let pattern = &bindings.pairs[index];
+                bind(&mut context, bindings.form, pattern, tail.value()?).map_err(|err| err.at(pattern))?;
+                self.next_binding(bindings, index + 2, context.frame().clone())
+            },
+            Continuation::Loop { bindings } => match tail {
+                Tail::Value(value) => Ok(State::Return(Tail::Value(value))),
+                Tail::Recur(values) => {
+                    let names = bindings.pairs.len() / 2;
+                    if values.len() != names {
+                        return Err(EvalError::arity("recur", &names.to_string(), values.len()));
+                    }
+                    let mut context = self.context.enter(&bindings.outer);
+                    for (name, value) in bindings.pairs.iter().step_by(2).zip(values) {
+                        bind(&mut context, "loop", name, value)?;
+                    }
+                    let end = bindings.pairs.len();
+                    self.next_binding(bindings, end, context.frame().clone())
+                }
+            },
+            Continuation::Function { function, arity, call } => match tail {
+                Tail::Value(value) => Ok(State::Return(Tail::Value(value))),
+                Tail::Recur(values) => {
+                    let frame = function.enter(&self.context, arity, values)?;
+                    let body = function.body(arity).clone();
+                    self.push(Continuation::Function { function, arity, call })?;
+                    self.body(&body, frame)
+                }
+            },
+            Continuation::Try { finally: Some(finally), env, .. } => {
+                self.push(Continuation::Finally { completion: Ok(tail) })?;
+                self.body(&finally, env)
+            },
+            Continuation::Try { finally: None, .. } => Ok(State::Return(tail)),
+            Continuation::Finally { completion } => {
+                tail.value()?;
+                completion.map(State::Return)
+            },
+            Continuation::Throw { form } => {
+                let exception = tail.value()?;
+                let err = match exception.as_ref() {
+                    Node::Exception(_) => EvalError::user(exception),
+                    node => EvalError::type_error(&format!("throw: expected an exception but got {}", node))
+                };
+                Err(err.at(&form))
+            },
+            Continuation::Case { table, default, env } => {
+                let value = tail.value()?;
+                let branch = match table.as_ref() {
+                    Node::Map(table) => table.get(&value).cloned(),
+                    node => return Err(EvalError::syntax(&format!("case*: expected a map of branches but got {}", node)))
+                };
+                Ok(State::Eval(branch.unwrap_or(default), env))
+            },
+            Continuation::Collection { literal, elements, mut values, env } => {
+                values.push(tail.value()?);
+                self.collection(literal, elements, values, env)
+            }
+        }
+    }
+
+}
+
+/// Splits the arguments of `(try body... (catch kind name handler...)... (finally cleanup...)?)`.
+/// A catch of `:default` catches every exception.
+fn split_try(args: &NodePtr) -> Result<(NodePtr, Vec<Handler>, Option<NodePtr>), EvalError> {
+    let forms = args.list_elements()?;
+    let clauses = forms.iter().position(|form| try_clause(form).is_some()).unwrap_or(forms.len());
+    let mut handlers = Vec::new();
+    let mut finally = None;
+    for form in &forms[clauses..] {
+        match try_clause(form) {
+            _ if finally.is_some() => return Err(EvalError::syntax("try: finally must be the last clause")),
+            Some(("catch", args)) => {
+                let args = arguments("catch", args, 2, None)?;
+                let kind = match args[0].as_ref() {
+                    Node::Symbol(kind) if Exception::is_kind(*kind) => *kind,
+                    Node::Keyword(keyword) if keyword.name().as_ref() == "default" => Symbol::intern("Throwable"),
+                    node => return Err(EvalError::syntax(&format!("catch: unknown exception kind {}", node)))
+                };
+                let name = symbol_arg("catch", &args[1])?;
+                handlers.push(Handler { kind, name, body: list_from(args[2..].to_vec()) });
+            },
+            Some((_, cleanup)) => finally = Some(cleanup.clone()),
+            None => return Err(EvalError::syntax(&format!("try: expected a catch or finally clause but got {}", form)))
+        }
+    }
+    Ok((list_from(forms[..clauses].to_vec()), handlers, finally))
+}
+
+/// Checks if evaluating `node` gives `node` itself, so collection literals made of constants
+/// can be returned as they are instead of being built again.
+fn is_constant(node: &NodePtr) -> bool {
+    match node.as_ref() {
+        Node::Symbol(_) | Node::List(_, _) => false,
+        Node::Vector(vector) => vector.iter().all(is_constant),
+        Node::Map(map) => map.iter().all(|(key, value)| is_constant(key) && is_constant(value)),
+        Node::Set(set) => set.iter().all(is_constant),
+        _ => true
+    }
+}
+
+/// Elements of a vector, map or set literal in the order they're evaluated, with the keys and
+/// values of maps in turn. Maps and sets that were read are evaluated in source order.
+pub(super) fn literal_elements(literal: &NodePtr) -> Vec<NodePtr> {
+    if let Some(elements) = elements_of(literal) {
+        return elements.to_vec();
+    }
+    match literal.as_ref() {
+        Node::Vector(vector) => vector.iter().cloned().collect(),
+        Node::Map(map) => map.iter().flat_map(|(key, value)| [key.clone(), value.clone()]).collect(),
+        Node::Set(set) => set.iter().cloned().collect(),
+        _ => Vec::new()
+    }
+}
+
+fn call_frame(function: &Function, call: &Option<NodePtr>) -> TraceFrame {
+    TraceFrame::Call { name: function.name(), span: call.as_ref().and_then(span_of) }
+}
+
+fn value(node: NodePtr) -> State {
+    State::Return(Tail::Value(node))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_file;
+    use crate::eval::testing::eval;
+    use crate::parser::{parse_file, tokenize};
+
+    fn parse(context: &EvalContext, source: &str) -> NodePtr {
+        parse_file(&mut tokenize(source, "test").unwrap().iter().peekable(), context.root().namespace()).unwrap()
+    }
+
+    /// Evaluates `definitions`, then `form` one step at a time, and returns the largest number
+    /// of continuations it had on its stack.
+    fn max_depth(definitions: &str, form: &str) -> usize {
+        let mut context = EvalContext::new_main();
+        let definitions = parse(&context, definitions);
+        eval_file(&mut context, &definitions).unwrap();
+        let form = parse(&context, form).list_elements().unwrap()[0].clone();
+        let mut evaluation = Evaluation::new(&context, form);
+        let mut depth = 0;
+        while evaluation.run(Some(1)).is_pending() {
+            depth = depth.max(evaluation.stack.len());
+        }
+        depth
+    }
+
+    #[test]
+    fn self_calls_through_a_var_run_in_constant_space() {
+        let cnt = "(defn cnt [n acc] (if (= n 0) acc (cnt (dec n) (inc acc))))";
+        assert_eq!(max_depth(cnt, "(cnt 10 0)"), max_depth(cnt, "(cnt 100 0)"));
+        let sum = "(defn sum [n] (if (= n 0) 0 (+ n (sum (dec n)))))";
+        assert!(max_depth(sum, "(sum 100)") > max_depth(sum, "(sum 10)"));
+    }
+
+    #[test]
+    fn wrapped_vars_are_called_every_time() {
+        let source = "(defn f [n] (if (= n 0) 0 (f (dec n))))
+            (def calls (transient []))
+            (alter-var-root #'f (fn [orig] (fn [n] (conj! calls n) (orig n))))
+            (f 3)
+            (count calls)";
+        assert_eq!(eval(source), "4");
+    }
+
+    #[test]
+    fn deep_recursion_doesnt_use_the_rust_stack() {
+        assert_eq!(eval("(defn d [n] (if (= n 0) 0 (+ 1 (d (dec n))))) (d 300000)"), "300000");
+    }
+
+    #[test]
+    fn stack_overflow_can_be_caught() {
+        let context = EvalContext::new_main();
+        context.root().set_max_depth(Some(10000));
+        let inf = parse(&context, "(defn inf [n] (if (= n 0) 0 (+ 1 (inf n))))");
+        eval_file(&mut context.clone(), &inf).unwrap();
+        let caught = parse(&context, "[(try (inf 1) (catch StackOverflowError e :caught)) (inf 0)]");
+        assert_eq!(eval_file(&mut context.clone(), &caught).unwrap().to_string(), "[:caught 0]");
+        // The error points at the call that went too deep.
+        let err = eval_file(&mut context.clone(), &parse(&context, "(inf 1)")).unwrap_err();
+        assert_eq!(err.code(), "stack-overflow");
+        assert_eq!(err.span().map(|span| span.start.column), Some(34));
+        // Evaluations nested in builtins use the Rust stack, which is guarded too.
+        let nested = "(defn m [n] (if (= n 0) 0 (+ 1 (first (map m [(dec n)])))))
+            [(try (m 100000) (catch StackOverflowError e :caught)) (m 10)]";
+        assert_eq!(eval(nested), "[:caught 10]");
+    }
+
+    #[test]
+    fn evaluations_pause_and_resume() {
+        let context = EvalContext::new_main();
+        let form = parse(&context, "(loop [i 0] (if (< i 100) (recur (inc i)) i))").list_elements().unwrap()[0].clone();
+        let form = analyze::analyze(&mut context.clone(), &form).unwrap();
+        let mut evaluation = Evaluation::new(&context, form);
+        let mut pauses = 0;
+        let result = loop {
+            match evaluation.run(Some(10)) {
+                Poll::Pending => pauses += 1,
+                Poll::Ready(result) => break result.unwrap()
+            }
+        };
+        assert_eq!(result.to_string(), "100");
+        assert!(pauses > 10);
+        assert!(evaluation.run(None).is_ready());
+    }
+}
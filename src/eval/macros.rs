@@ -0,0 +1,226 @@
+use crate::nodes::{NodePtr, Node, IntoListIter, list_from};
+use crate::intern::{Symbol, SymbolMap};
+use crate::context::{EvalContext, Frame, Var};
+use crate::collections::{PersistentVector, PersistentHashSet, PersistentHashMap};
+use crate::parser::set_expansion;
+use std::rc::Rc;
+
+use super::{EvalResult, EvalError, ErrorKind, eval_expr, apply, arguments, special};
+use super::seq;
+
+/// Finds the macro called by `form`, if its head is a symbol naming a macro var that isn't
+/// shadowed by a local.
+fn macro_var(context: &EvalContext, form: &NodePtr) -> Option<Rc<Var>> {
+    match form.as_ref() {
+        Node::List(head, _) => match head.as_ref() {
+            Node::Symbol(symbol) if Frame::lookup(context.frame(), *symbol).is_none() => {
+                context.root().find_var(*symbol).filter(|var| var.is_macro())
+            },
+            _ => None
+        },
+        _ => None
+    }
+}
+
+/// Expands `form` once if it's a macro call. The macro gets the whole form as `&form` and a
+/// map of the locals in scope as `&env`, followed by its unevaluated arguments. The locals are
+/// the ones of the current frame, with their values, and `locals`, bound by the enclosing
+/// forms being analyzed, whose values aren't known yet and are nil. The expansion remembers
+/// the form it comes from, and errors raised by the macro are wrapped in a macro expansion
+/// error.
+pub fn macroexpand_1(context: &mut EvalContext, form: &NodePtr, locals: &[Symbol]) -> Result<Option<NodePtr>, EvalError> {
+    let var = match macro_var(context, form) {
+        Some(var) => var,
+        None => return Ok(None)
+    };
+    let function = var.get().ok_or_else(|| EvalError::new(ErrorKind::UnboundVar(var.name())))?;
+    let env = Frame::locals(context.frame()).into_iter()
+        .map(|(name, value)| (Rc::new(Node::Symbol(name)), value))
+        .chain(locals.iter().map(|name| (Rc::new(Node::Symbol(*name)), Rc::new(Node::Nil))))
+        .collect::<PersistentHashMap<NodePtr, NodePtr>>();
+    let args = match form.as_ref() {
+        Node::List(_, args) => args.clone(),
+        _ => unreachable!()
+    };
+    let args = Rc::new(Node::List(form.clone(), Rc::new(Node::List(Rc::new(Node::Map(env)), args))));
+    let expansion = apply(context, &function, &args)
+        .and_then(|expansion| as_form(context, &expansion))
+        .map_err(|err| EvalError::new(ErrorKind::MacroExpansion(var.name())).with_cause(err))?;
+    set_expansion(form, &expansion, var.name());
+    Ok(Some(expansion))
+}
+
+/// Turns the seqs in an expansion into lists, like the forms of the reader, so they can be
+/// evaluated. Lazy seqs are realized, and parts that are already forms are kept as they are.
+fn as_form(context: &mut EvalContext, node: &NodePtr) -> EvalResult {
+    match node.as_ref() {
+        Node::List(_, _) | Node::Seq(_) | Node::LazySeq(_) => {
+            let elements = seq::collect(context, node)?;
+            let forms = elements.iter().map(|element| as_form(context, element)).collect::<Result<Vec<NodePtr>, EvalError>>()?;
+            let same = is_list(node) && elements.iter().zip(&forms).all(|(element, form)| Rc::ptr_eq(element, form));
+            match same {
+                true => Ok(node.clone()),
+                false => Ok(list_from(forms))
+            }
+        },
+        Node::Vector(vec) => {
+            let forms = vec.iter().map(|element| as_form(context, element)).collect::<Result<Vec<NodePtr>, EvalError>>()?;
+            match vec.iter().zip(&forms).all(|(element, form)| Rc::ptr_eq(element, form)) {
+                true => Ok(node.clone()),
+                false => Ok(Rc::new(Node::Vector(forms.into_iter().collect())))
+            }
+        },
+        Node::Map(map) => {
+            let mut changed = false;
+            let mut entries = Vec::with_capacity(map.len());
+            for (key, value) in map.iter() {
+                let entry = (as_form(context, key)?, as_form(context, value)?);
+                changed |= !Rc::ptr_eq(key, &entry.0) || !Rc::ptr_eq(value, &entry.1);
+                entries.push(entry);
+            }
+            match changed {
+                true => Ok(Rc::new(Node::Map(entries.into_iter().collect()))),
+                false => Ok(node.clone())
+            }
+        },
+        Node::Set(set) => {
+            let forms = set.iter().map(|element| as_form(context, element)).collect::<Result<Vec<NodePtr>, EvalError>>()?;
+            match set.iter().zip(&forms).all(|(element, form)| Rc::ptr_eq(element, form)) {
+                true => Ok(node.clone()),
+                false => Ok(Rc::new(Node::Set(forms.into_iter().collect())))
+            }
+        },
+        _ => Ok(node.clone())
+    }
+}
+
+/// Checks if `node` is a list made of `Node::List` cells all the way to its end.
+fn is_list(node: &NodePtr) -> bool {
+    let mut node = node;
+    loop {
+        match node.as_ref() {
+            Node::List(_, rest) => node = rest,
+            Node::Nil => return true,
+            _ => return false
+        }
+    }
+}
+
+/// Expands `form` until it isn't a macro call anymore.
+pub fn macroexpand(context: &mut EvalContext, form: &NodePtr) -> EvalResult {
+    let mut form = form.clone();
+    while let Some(expansion) = macroexpand_1(context, &form, &[])? {
+        form = expansion;
+    }
+    Ok(form)
+}
+
+/// `` `form `` returns `form` like `quote`, except that `~x` is replaced by the value of `x`
+/// and `~@xs` by the elements of `xs`. Symbols are qualified with the namespace of the var
+/// they name, or else the current one, unless they name a special form or a builtin, and
+/// symbols ending with `#` are replaced with unique names, the same for the whole form.
+pub fn syntax_quote(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("syntax-quote", args, 1, Some(1))?;
+    expand(context, &mut SymbolMap::default(), &args[0])
+}
+
+/// Checks if `node` is a call like `(unquote x)`, returning `x`.
+fn unquoted(node: &NodePtr, name: Symbol) -> Option<&NodePtr> {
+    match node.as_ref() {
+        Node::List(head, args) => match (head.as_ref(), args.as_ref()) {
+            (Node::Symbol(symbol), Node::List(arg, rest)) if *symbol == name && matches!(rest.as_ref(), Node::Nil) => Some(arg),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn expand(context: &mut EvalContext, gensyms: &mut SymbolMap<Symbol>, node: &NodePtr) -> EvalResult {
+    if let Some(form) = unquoted(node, Symbol::UNQUOTE) {
+        return eval_expr(context, form);
+    }
+    if unquoted(node, Symbol::UNQUOTE_SPLICING).is_some() {
+        return Err(EvalError::syntax("syntax-quote: ~@ can only be used inside a collection"));
+    }
+    match node.as_ref() {
+        Node::Symbol(symbol) => Ok(Rc::new(Node::Symbol(qualify(context, gensyms, *symbol)))),
+        Node::List(_, _) => {
+            let elements = node.list_elements()?;
+            Ok(list_from(expand_elements(context, gensyms, elements)?))
+        },
+        Node::Vector(vec) => {
+            let elements = expand_elements(context, gensyms, vec.iter().cloned().collect())?;
+            Ok(Rc::new(Node::Vector(elements.into_iter().collect::<PersistentVector<NodePtr>>())))
+        },
+        Node::Set(set) => {
+            let elements = expand_elements(context, gensyms, set.iter().cloned().collect())?;
+            Ok(Rc::new(Node::Set(elements.into_iter().collect::<PersistentHashSet<NodePtr>>())))
+        },
+        Node::Map(map) => {
+            let mut entries = Vec::with_capacity(map.len());
+            for (key, value) in map.iter() {
+                entries.push((expand(context, gensyms, key)?, expand(context, gensyms, value)?));
+            }
+            Ok(Rc::new(Node::Map(entries.into_iter().collect())))
+        },
+        _ => Ok(node.clone())
+    }
+}
+
+/// Expands the elements of a collection, splicing the ones written `~@xs`.
+fn expand_elements(context: &mut EvalContext, gensyms: &mut SymbolMap<Symbol>, elements: Vec<NodePtr>) -> Result<Vec<NodePtr>, EvalError> {
+    let mut expanded = Vec::with_capacity(elements.len());
+    for element in elements {
+        match unquoted(&element, Symbol::UNQUOTE_SPLICING) {
+            Some(form) => {
+                let spliced = eval_expr(context, form)?;
+                expanded.extend(seq::collect(context, &spliced)?);
+            },
+            None => expanded.push(expand(context, gensyms, &element)?)
+        }
+    }
+    Ok(expanded)
+}
+
+fn qualify(context: &EvalContext, gensyms: &mut SymbolMap<Symbol>, symbol: Symbol) -> Symbol {
+    let name = symbol.name();
+    let root = context.root();
+    let is_builtin = root.get_builtin(symbol).is_some() && root.find_var(symbol).is_none();
+    if name.len() > 1 && name.ends_with('#') {
+        *gensyms.entry(symbol).or_insert_with(|| {
+            Symbol::intern(&format!("{}__{}__auto__", &name[..name.len() - 1], root.next_id()))
+        })
+    } else if symbol.split().0.is_some() || symbol == Symbol::AMPERSAND || special::lookup(symbol).is_some() || is_builtin {
+        symbol
+    } else {
+        // Names of vars from other namespaces, like the core macros, resolve to those vars.
+        root.find_var(symbol).map_or_else(|| root.qualify(symbol), |var| var.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn defmacro_and_expansion() {
+        assert_eq!(eval("(defmacro unless [c & body] `(if ~c nil (do ~@body))) [(unless false 1 2) (macroexpand-1 '(unless x y))]"), "[2 (if x nil (do y))]");
+        assert_eq!(eval("[(macroexpand '(when x y)) (macroexpand '(+ 1 2))]"), "[(if x (do y)) (+ 1 2)]");
+        assert_eq!(eval("(defmacro inner [x] `(when ~x 1)) [(macroexpand-1 '(inner y)) (macroexpand '(inner y))]"), "[(lispure.core/when y 1) (if y (do 1))]");
+    }
+
+    #[test]
+    fn gensyms_are_unique() {
+        assert_eq!(eval("(let [a (gensym) b (gensym \"p\")] [(symbol? a) (= a b) (first (name b))])"), "[true false \\p]");
+        assert_eq!(eval("(defmacro my-or [a b] `(let [x# ~a] (if x# x# ~b))) (let [x 5] (my-or nil x))"), "5");
+    }
+
+    #[test]
+    fn form_and_env() {
+        assert_eq!(eval("(defmacro whole [& args] (list 'quote &form)) (whole 1 2)"), "(whole 1 2)");
+        assert_eq!(eval("(defmacro locals [] (list 'quote (set (keys &env)))) [(= '#{a b} (let [a 1 b 2] (locals))) (= '#{p} ((fn [p] (locals)) 1))]"), "[true true]");
+        // A recur in a macro passes &form and &env too, like a call would.
+        assert_eq!(eval("(defmacro down [n] (if (= n 0) :done (recur &form &env (dec n)))) (down 3)"), ":done");
+        assert_eq!(eval_err("(defmacro down [n] (if (= n 0) :done (recur (dec n))))"), "recur: expected 3 arguments but got 1");
+    }
+}
@@ -1,45 +1,192 @@
+mod analyze;
+mod destructure;
 mod error;
+mod exception;
+mod function;
+mod machine;
+pub mod macros;
+pub mod seq;
+mod special;
+pub mod stack;
 
-pub use error::EvalError;
+pub use error::{EvalError, ErrorKind, TraceFrame};
+pub use exception::Exception;
+pub use seq::LazySeq;
+pub use function::{Function, Lambda};
+pub use machine::Evaluation;
 use super::nodes::NodePtr;
-use crate::nodes::Node;
+use crate::nodes::{Node, IntoListIter, list_from};
 use std::rc::Rc;
 use crate::context::EvalContext;
+use crate::intern::Symbol;
 
 pub type EvalResult = std::result::Result<NodePtr, EvalError>;
 
-pub fn eval_file(context: &mut EvalContext, mut node: &NodePtr) -> EvalResult {
-
+/// Evaluates the top-level forms of a file. Each form is analyzed right before it's evaluated,
+/// so it can use the macros defined by the forms before it.
+pub fn eval_file(context: &mut EvalContext, node: &NodePtr) -> EvalResult {
     let mut result: NodePtr = Rc::new(Node::Nil);
-
-    while let Node::List(left, right, _) = node.as_ref() {
-        result = eval_expr(context, left)?;
-        node = right;
+    for form in node.list_iter() {
+        let form = form?;
+        result = match form.as_ref() {
+            // The forms of a top-level `do` are top-level forms too.
+            Node::List(head, body) if matches!(head.as_ref(), Node::Symbol(Symbol::DO)) => {
+                eval_file(context, body)?
+            },
+            _ => {
+                let form = analyze::analyze(context, &form)?;
+                eval_expr(context, &form)?
+            }
+        };
     }
-
     Ok(result)
+}
 
+/// Evaluates the forms of a body in order and returns the last result.
+fn eval_body(context: &mut EvalContext, body: &NodePtr) -> EvalResult {
+    let form = Rc::new(Node::List(Rc::new(Node::Symbol(Symbol::DO)), body.clone()));
+    Evaluation::new(context, form).finish()
 }
 
 fn eval_expr(context: &mut EvalContext, node: &NodePtr) -> EvalResult {
-    match node.as_ref() {
-        Node::List(left, right, literal) if !*literal => {
-            call(context, left, right)
-        }
-        _ => Ok(node.clone())
+    Evaluation::new(context, node.clone()).finish()
+}
+
+/// Finds the value of a symbol in the locals, then the vars, then the builtins.
+fn resolve(context: &EvalContext, symbol: Symbol) -> EvalResult {
+    if let Some(value) = context.lookup(symbol) {
+        Ok(value)
+    } else if let Some(var) = context.root().find_var(symbol) {
+        Err(EvalError::new(ErrorKind::UnboundVar(var.name())))
+    } else if let Some(builtin) = context.root().get_builtin(symbol) {
+        Ok(Rc::new(Node::Builtin(symbol, *builtin)))
+    } else {
+        Err(EvalError::new(ErrorKind::UnboundSymbol(symbol)))
     }
 }
 
-fn call(context: &mut EvalContext, _left: &NodePtr, right: &NodePtr) -> EvalResult {
-    match _left.as_ref() {
-        Node::List(_, _, _) => unimplemented!(),
-        Node::Symbol(symbol) => {
-            if let Some(function) = context.root().get_builtin(symbol) {
-                function(context, right)
-            } else {
-                Err(EvalError::new(&format!("Function '{}' not found", symbol)))
-            }
+/// Collects the arguments of a call, failing if there are fewer than `min` or more than `max`.
+pub fn arguments(name: &str, args: &NodePtr, min: usize, max: Option<usize>) -> Result<Vec<NodePtr>, EvalError> {
+    let args = args.list_elements()?;
+    if args.len() < min || max.is_some_and(|max| args.len() > max) {
+        let expected = match max {
+            Some(max) if max == min => format!("{}", min),
+            Some(max) => format!("{} to {}", min, max),
+            None => format!("at least {}", min)
+        };
+        Err(EvalError::arity(name, &expected, args.len()))
+    } else {
+        Ok(args)
+    }
+}
+
+/// Calls the builtin registered under `name`.
+fn call_builtin(context: &mut EvalContext, name: &str, args: Vec<NodePtr>) -> EvalResult {
+    match context.root().get_builtin(Symbol::intern(name)).copied() {
+        Some(function) => function(context, &list_from(args)),
+        None => Err(EvalError::other(&format!("Function '{}' not found", name)))
+    }
+}
+
+/// Calls `function` with a list of already evaluated arguments.
+///
+/// Besides builtins and functions, keywords and symbols look themselves up in their argument
+/// like `(:a m)`, maps and sets look up their argument like `(m :a)`, and vectors return the
+/// element at an index.
+pub fn apply(context: &mut EvalContext, function: &NodePtr, args: &NodePtr) -> EvalResult {
+    match function.as_ref() {
+        Node::Builtin(_, builtin) => builtin(context, args),
+        Node::Function(_) => Evaluation::apply(context, function.clone(), args.list_elements()?).finish(),
+        Node::Var(var) => match var.get() {
+            Some(value) => apply(context, &value, args),
+            None => Err(EvalError::new(ErrorKind::UnboundVar(var.name())))
         },
-        node => Err(EvalError::new(&format!("Node {:?} is not a function", node)))
+        Node::Keyword(_) | Node::Symbol(_) => {
+            let mut args = arguments(&function.to_string(), args, 1, Some(2))?;
+            args.insert(1, function.clone());
+            call_builtin(context, "get", args)
+        },
+        Node::Map(_) | Node::SortedMap(_, _) => {
+            let mut args = arguments(&function.to_string(), args, 1, Some(2))?;
+            args.insert(0, function.clone());
+            call_builtin(context, "get", args)
+        },
+        Node::Set(_) | Node::SortedSet(_, _) => {
+            let mut args = arguments(&function.to_string(), args, 1, Some(1))?;
+            args.insert(0, function.clone());
+            call_builtin(context, "get", args)
+        },
+        Node::Vector(_) => {
+            let mut args = arguments(&function.to_string(), args, 1, Some(1))?;
+            args.insert(0, function.clone());
+            call_builtin(context, "nth", args)
+        },
+        _ => Err(EvalError::new(ErrorKind::NotCallable(function.clone())))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub mod testing {
+    use crate::context::EvalContext;
+    use crate::parser::{parse_file, tokenize};
+    use super::{eval_file, seq, EvalError};
+
+    fn run(source: &str) -> Result<String, EvalError> {
+        let mut context = EvalContext::new_main();
+        let tokens = tokenize(source, "test").expect("could not tokenize the test source");
+        let forms = parse_file(&mut tokens.iter().peekable(), context.root().namespace())
+            .expect("could not parse the test source");
+        let result = eval_file(&mut context, &forms)?;
+        seq::realize_deep(&mut context, &result)?;
+        Ok(result.to_string())
+    }
+
+    /// Evaluates the forms of `source` in a new context and prints the value of the last one.
+    pub fn eval(source: &str) -> String {
+        run(source).unwrap_or_else(|err| panic!("{} failed: {}", source, err.kind()))
+    }
+
+    /// Evaluates the forms of `source`, which must fail, and returns the error.
+    pub fn error(source: &str) -> EvalError {
+        match run(source) {
+            Ok(result) => panic!("{} should have failed, but returned {}", source, result),
+            Err(err) => err
+        }
+    }
+
+    /// Evaluates the forms of `source`, which must fail, and returns the message of the error.
+    pub fn eval_err(source: &str) -> String {
+        error(source).kind().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{eval, eval_err};
+
+    #[test]
+    fn anything_callable_in_head_position() {
+        assert_eq!(eval("[((fn [x] (* x 2)) 3) ((if true inc dec) 1)]"), "[6 2]");
+        assert_eq!(eval("[(:a {:a 1}) (:b {:a 1} :d) ({:a 1} :a) ({:a 1} :b :d)]"), "[1 :d 1 :d]");
+        assert_eq!(eval("[(#{1 2} 2) (#{1 2} 3) ([10 20] 1) ('a {'a 1})]"), "[2 nil 20 1]");
+        assert_eq!(eval("[(map :a [{:a 1} {:a 2}]) (filter #{1 3} [1 2 3])]"), "[(1 2) (1 3)]");
+    }
+
+    #[test]
+    fn builtins_are_values() {
+        assert_eq!(eval("[inc (= inc inc) (= inc dec) (let [f +] (f 1 2))]"), "[#<fn inc> true false 3]");
+        assert_eq!(eval("(def plus +) (plus 1 2)"), "3");
+    }
+
+    #[test]
+    fn uniform_arity_errors() {
+        assert_eq!(eval_err("(:a)"), ":a: expected 1 to 2 arguments but got 0");
+        assert_eq!(eval_err("({:a 1})"), "{:a 1}: expected 1 to 2 arguments but got 0");
+        assert_eq!(eval_err("([1] 1 2)"), "[1]: expected 1 argument but got 2");
+        assert_eq!(eval_err("(inc 1 2)"), "inc: expected 1 argument but got 2");
+        assert_eq!(eval_err("((fn [x] x))"), "fn: expected 1 argument but got 0");
+        assert_eq!(eval_err("([10 20] 5)"), "nth: index 5 out of bounds");
+        assert_eq!(eval_err("(1 2)"), "Node 1 is not a function");
+        assert_eq!(eval_err("(nil)"), "Node nil is not a function");
+    }
+}
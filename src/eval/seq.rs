@@ -0,0 +1,326 @@
+use std::cell::{Cell, OnceCell, RefCell};
+use std::rc::Rc;
+use crate::nodes::{Node, NodePtr, map_entry};
+use crate::collections::PersistentVector;
+use crate::context::{EvalContext, Frame};
+use super::{EvalResult, EvalError, eval_body, stack};
+
+pub type NativeThunk = Rc<dyn Fn(&mut EvalContext) -> EvalResult>;
+
+/// Computation that produces the contents of a lazy sequence.
+pub enum Thunk {
+    /// Body of a `lazy-seq` form, evaluated like a `do` block in the environment it was
+    /// created in.
+    Form(NodePtr, Rc<Frame>),
+    /// Closure used by builtins like `range` or `map`.
+    Native(NativeThunk)
+}
+
+/// Sequence whose contents are computed the first time they are needed.
+///
+/// The result of the thunk is memoized, so every element is computed at most once. It is
+/// stored already converted with `seq`, which means a realized lazy sequence holds either
+/// `nil` or a non-empty seq.
+pub struct LazySeq {
+    thunk: RefCell<Option<Thunk>>,
+    value: OnceCell<NodePtr>
+}
+
+impl LazySeq {
+
+    pub fn new(thunk: Thunk) -> Self {
+        Self {
+            thunk: RefCell::new(Some(thunk)),
+            value: OnceCell::new()
+        }
+    }
+
+    pub fn realized(&self) -> Option<&NodePtr> {
+        self.value.get()
+    }
+
+    /// Takes the realized value out, so that long chains can be dropped iteratively.
+    pub(crate) fn take_value(&mut self) -> Option<NodePtr> {
+        self.value.take()
+    }
+
+}
+
+thread_local! {
+    /// Thunks of lazy seqs dropped while another one was being dropped.
+    static PENDING: RefCell<Vec<Thunk>> = const { RefCell::new(Vec::new()) };
+    /// Whether a lazy seq is being dropped on this thread.
+    static DROPPING: Cell<bool> = const { Cell::new(false) };
+}
+
+impl Drop for LazySeq {
+    /// A thunk can hold other lazy seqs, like the one `map` reads from, so dropping a long chain
+    /// of them would recurse. Nested drops queue their thunk instead, and the outermost one drops
+    /// the queue in a loop.
+    fn drop(&mut self) {
+        let thunk = match self.thunk.get_mut().take() {
+            Some(thunk) => thunk,
+            None => return
+        };
+        match DROPPING.try_with(|dropping| dropping.replace(true)) {
+            Ok(false) => {
+                drop(thunk);
+                while let Some(thunk) = PENDING.with(|pending| pending.borrow_mut().pop()) {
+                    drop(thunk);
+                }
+                DROPPING.with(|dropping| dropping.set(false));
+            },
+            Ok(true) => {
+                let mut thunk = Some(thunk);
+                // If the thread is exiting, the thunk is dropped right away.
+                let _ = PENDING.try_with(|pending| pending.borrow_mut().extend(thunk.take()));
+            },
+            Err(_) => ()
+        }
+    }
+}
+
+impl std::fmt::Debug for LazySeq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.realized() {
+            Some(value) => write!(f, "LazySeq({:?})", value),
+            None => write!(f, "LazySeq(<pending>)")
+        }
+    }
+}
+
+/// Builds a lazy sequence out of a native closure.
+pub fn lazy<F>(thunk: F) -> NodePtr
+    where F: Fn(&mut EvalContext) -> EvalResult + 'static
+{
+    Rc::new(Node::LazySeq(LazySeq::new(Thunk::Native(Rc::new(thunk)))))
+}
+
+pub fn cons(value: NodePtr, seq: NodePtr) -> NodePtr {
+    Rc::new(Node::List(value, seq))
+}
+
+/// The empty list `()`, returned by `rest` when there is nothing left. Unlike `nil`, it's a
+/// seq and counts as true, and `seq` turns it into `nil`.
+pub fn empty_list() -> NodePtr {
+    Rc::new(Node::Seq(PersistentVector::new()))
+}
+
+/// Computes the contents of a lazy sequence, or returns them if they are already known.
+///
+/// If the thunk fails, the sequence stays unrealized and the next attempt runs it again.
+pub fn realize(context: &mut EvalContext, lazy: &LazySeq) -> EvalResult {
+    if let Some(value) = lazy.realized() {
+        return Ok(value.clone());
+    }
+    // Lazy seqs built on other ones, like `map` over `map`, realize them on the Rust stack.
+    stack::check()?;
+    // A thunk can return another lazy seq, like a `lazy-seq` body calling a function that
+    // returns one. Those are realized in this loop rather than recursively, and all the seqs
+    // of the chain get the same value.
+    let mut chain: Vec<NodePtr> = Vec::new();
+    let mut thunks = Vec::new();
+    let result = loop {
+        let current = chain.last().map_or(lazy, |node| as_lazy(node));
+        let thunk = match current.thunk.borrow_mut().take() {
+            Some(thunk) => thunk,
+            None => break Err(EvalError::other("lazy-seq: sequence needs its own value to be realized"))
+        };
+        let value = match &thunk {
+            Thunk::Form(body, env) => eval_body(&mut context.enter(env), body),
+            Thunk::Native(native) => native(context)
+        };
+        thunks.push(thunk);
+        let value = match value {
+            Ok(value) => value,
+            Err(err) => break Err(err)
+        };
+        match value.as_ref() {
+            Node::LazySeq(next) => match next.realized() {
+                Some(realized) => break Ok(realized.clone()),
+                None => chain.push(value.clone())
+            },
+            _ => break seq(context, &value)
+        }
+    };
+    let lazies = std::iter::once(lazy).chain(chain.iter().map(as_lazy));
+    match result {
+        Ok(value) => {
+            let value = lazy.value.get_or_init(|| value).clone();
+            for lazy in lazies.skip(1) {
+                lazy.value.get_or_init(|| value.clone());
+            }
+            Ok(value)
+        },
+        Err(err) => {
+            for (lazy, thunk) in lazies.zip(thunks) {
+                *lazy.thunk.borrow_mut() = Some(thunk);
+            }
+            Err(err)
+        }
+    }
+}
+
+fn as_lazy(node: &NodePtr) -> &LazySeq {
+    match node.as_ref() {
+        Node::LazySeq(lazy) => lazy,
+        _ => unreachable!("only lazy seqs are chained")
+    }
+}
+
+/// Returns `nil` if `coll` is empty, or a seq over its elements otherwise.
+///
+/// Lists are their own seq, and vectors are wrapped without copying. Maps, sets and strings
+/// get a lazy seq walking them in place, so that taking the first elements of a large one
+/// is cheap. Queues are copied to a vector.
+pub fn seq(context: &mut EvalContext, coll: &NodePtr) -> EvalResult {
+    let elements = match coll.as_ref() {
+        Node::Seq(vec) if vec.is_empty() => return Ok(Rc::new(Node::Nil)),
+        Node::Nil | Node::List(_, _) | Node::Seq(_) => return Ok(coll.clone()),
+        Node::LazySeq(lazy) => return realize(context, lazy),
+        Node::Vector(vec) => vec.clone(),
+        Node::String(_) => return Ok(walk_from(Chars { string: coll.clone(), offset: 0 })),
+        Node::Set(set) => return Ok(walk_from(set.cursor())),
+        Node::SortedSet(set, _) => return Ok(walk_from(set.cursor())),
+        Node::Map(map) => return Ok(walk_from(map.cursor().map(|(key, value)| map_entry(&key, &value)))),
+        Node::SortedMap(map, _) => return Ok(walk_from(map.cursor().map(|(key, value)| map_entry(&key, &value)))),
+        Node::Queue(queue) => queue.iter().cloned().collect(),
+        Node::PriorityQueue(queue, _) => queue.iter().cloned().collect(),
+        node => return Err(EvalError::type_error(&format!("seq: cannot create a seq from {}", node)))
+    };
+    if elements.is_empty() {
+        Ok(Rc::new(Node::Nil))
+    } else {
+        Ok(Rc::new(Node::Seq(elements)))
+    }
+}
+
+/// Seq of the elements left in `cursor`: `nil` if there are none, or the next one followed
+/// by a lazy seq of the others.
+fn walk_from<I>(mut cursor: I) -> NodePtr
+    where I: Iterator<Item = NodePtr> + Clone + 'static
+{
+    match cursor.next() {
+        Some(element) => cons(element, lazy(move |_| Ok(walk_from(cursor.clone())))),
+        None => Rc::new(Node::Nil)
+    }
+}
+
+/// Characters of a string node, from a byte offset.
+#[derive(Clone)]
+struct Chars {
+    string: NodePtr,
+    offset: usize
+}
+
+impl Iterator for Chars {
+    type Item = NodePtr;
+
+    fn next(&mut self) -> Option<NodePtr> {
+        let char = match self.string.as_ref() {
+            Node::String(string) => string[self.offset..].chars().next()?,
+            _ => return None
+        };
+        self.offset += char.len_utf8();
+        Some(Rc::new(Node::Char(char)))
+    }
+}
+
+pub fn first(context: &mut EvalContext, coll: &NodePtr) -> EvalResult {
+    match seq(context, coll)?.as_ref() {
+        Node::List(left, _) => Ok(left.clone()),
+        Node::Seq(vec) => Ok(vec.first().unwrap().clone()),
+        _ => Ok(Rc::new(Node::Nil))
+    }
+}
+
+/// Returns everything but the first element, or `()` if there is nothing left. The rest of
+/// a list is not realized.
+pub fn rest(context: &mut EvalContext, coll: &NodePtr) -> EvalResult {
+    match seq(context, coll)?.as_ref() {
+        Node::List(_, right) if !matches!(right.as_ref(), Node::Nil) => Ok(right.clone()),
+        Node::Seq(vec) if vec.len() > 1 => Ok(Rc::new(Node::Seq(vec.subvec(1, vec.len()).unwrap()))),
+        _ => Ok(empty_list())
+    }
+}
+
+pub fn next(context: &mut EvalContext, coll: &NodePtr) -> EvalResult {
+    let rest = rest(context, coll)?;
+    seq(context, &rest)
+}
+
+/// Walks a seq one element at a time, realizing it as it goes.
+///
+/// This isn't an `Iterator` because every step needs the context, which the caller usually
+/// needs as well between steps.
+pub struct SeqIter {
+    node: NodePtr
+}
+
+impl SeqIter {
+
+    pub fn new(coll: &NodePtr) -> Self {
+        Self {
+            node: coll.clone()
+        }
+    }
+
+    pub fn next(&mut self, context: &mut EvalContext) -> Result<Option<NodePtr>, EvalError> {
+        let node = seq(context, &self.node)?;
+        match node.as_ref() {
+            Node::Nil => Ok(None),
+            _ => {
+                self.node = rest(context, &node)?;
+                Ok(Some(first(context, &node)?))
+            }
+        }
+    }
+
+}
+
+/// Collects all the elements of `coll`, which must be finite.
+pub fn collect(context: &mut EvalContext, coll: &NodePtr) -> Result<Vec<NodePtr>, EvalError> {
+    let mut iter = SeqIter::new(coll);
+    let mut elements = Vec::new();
+    while let Some(element) = iter.next(context)? {
+        elements.push(element);
+    }
+    Ok(elements)
+}
+
+/// Realizes every lazy sequence reachable from `node`, so that it can be printed or compared.
+pub fn realize_deep(context: &mut EvalContext, node: &NodePtr) -> Result<(), EvalError> {
+    match node.as_ref() {
+        Node::List(_, _) | Node::Seq(_) | Node::LazySeq(_) => {
+            let mut iter = SeqIter::new(node);
+            while let Some(element) = iter.next(context)? {
+                realize_deep(context, &element)?;
+            }
+        },
+        Node::Vector(vec) => for element in vec.iter() {
+            realize_deep(context, element)?;
+        },
+        Node::Set(set) => for element in set.iter() {
+            realize_deep(context, element)?;
+        },
+        Node::SortedSet(set, _) => for element in set.iter() {
+            realize_deep(context, element)?;
+        },
+        Node::Queue(queue) => for element in queue.iter() {
+            realize_deep(context, element)?;
+        },
+        Node::PriorityQueue(queue, _) => for element in queue.iter() {
+            realize_deep(context, element)?;
+        },
+        Node::Map(map) => for (key, value) in map.iter() {
+            realize_deep(context, key)?;
+            realize_deep(context, value)?;
+        },
+        Node::SortedMap(map, _) => for (key, value) in map.iter() {
+            realize_deep(context, key)?;
+            realize_deep(context, value)?;
+        },
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,328 @@
+use crate::nodes::{NodePtr, Node, IntoListIter};
+use crate::intern::{Symbol, SymbolMap};
+use crate::context::{EvalContext, Frame, Var};
+use std::rc::Rc;
+
+use super::{EvalResult, EvalError, ErrorKind, eval_expr, arguments};
+use super::function::{Function, Lambda, Arity};
+use super::{analyze, macros};
+use super::seq::{LazySeq, Thunk};
+
+/// Form evaluated with its arguments left unevaluated.
+#[derive(Clone, Copy)]
+pub enum SpecialForm {
+    Function(fn(&mut EvalContext, &NodePtr) -> EvalResult),
+    /// Forms evaluating their subforms on the stack of the evaluation, so they can nest
+    /// without using the Rust stack. Their last subform is in tail position.
+    If,
+    Do,
+    Let,
+    Loop,
+    /// `loop*`, a `loop` whose body was already analyzed.
+    AnalyzedLoop,
+    LetFn,
+    /// `letfn*`, a `letfn` whose functions and body were already analyzed.
+    AnalyzedLetFn,
+    Recur,
+    Try,
+    Throw,
+    /// `(case* value {key branch...} default)`, the dispatch of `case`: evaluates the branch
+    /// found under the value of `value` in the map, which is left unevaluated, or `default`.
+    Case
+}
+
+thread_local! {
+    /// Special forms by name, interned once so that finding the one of a form compares ids.
+    static SPECIAL_FORMS: SymbolMap<SpecialForm> = special_forms();
+}
+
+fn special_forms() -> SymbolMap<SpecialForm> {
+    let function = SpecialForm::Function;
+    let forms = [
+        (Symbol::DEF, function(def)),
+        (Symbol::DEFONCE, function(defonce)),
+        (Symbol::DEFN, function(defn)),
+        (Symbol::DECLARE, function(declare)),
+        (Symbol::VAR, function(var)),
+        (Symbol::FN, function(fn_form)),
+        (Symbol::FN_STAR, function(analyzed_fn)),
+        (Symbol::QUOTE, function(quote)),
+        (Symbol::SYNTAX_QUOTE, function(macros::syntax_quote)),
+        (Symbol::DEFMACRO, function(defmacro)),
+        (Symbol::LAZY_SEQ, function(lazy_seq)),
+        (Symbol::IF, SpecialForm::If),
+        (Symbol::DO, SpecialForm::Do),
+        (Symbol::LET, SpecialForm::Let),
+        (Symbol::LOOP, SpecialForm::Loop),
+        (Symbol::LOOP_STAR, SpecialForm::AnalyzedLoop),
+        (Symbol::LETFN, SpecialForm::LetFn),
+        (Symbol::LETFN_STAR, SpecialForm::AnalyzedLetFn),
+        (Symbol::RECUR, SpecialForm::Recur),
+        (Symbol::TRY, SpecialForm::Try),
+        (Symbol::THROW, SpecialForm::Throw),
+        (Symbol::CASE_STAR, SpecialForm::Case)
+    ];
+    forms.iter().copied().collect()
+}
+
+/// Finds the special form named by `symbol`. Special forms can't be shadowed by locals.
+pub fn lookup(symbol: Symbol) -> Option<SpecialForm> {
+    SPECIAL_FORMS.with(|forms| forms.get(&symbol).copied())
+}
+
+/// Checks if `form` is a `(catch ...)` or `(finally ...)` clause of a `try`, returning its
+/// name and its arguments.
+pub fn try_clause(form: &NodePtr) -> Option<(&'static str, &NodePtr)> {
+    match form.as_ref() {
+        Node::List(head, args) => match head.as_ref() {
+            Node::Symbol(Symbol::CATCH) => Some(("catch", args)),
+            Node::Symbol(Symbol::FINALLY) => Some(("finally", args)),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+/// Rebuilds the form `(name args...)` from the arguments of a special form.
+fn with_head(name: &str, args: &NodePtr) -> NodePtr {
+    Rc::new(Node::List(Rc::new(Node::Symbol(Symbol::intern(name))), args.clone()))
+}
+
+/// Finds or creates the var defined by a form like `def`. Vars can only be defined in the
+/// current namespace.
+fn var_to_define(context: &EvalContext, form: &str, node: &NodePtr) -> Result<Rc<Var>, EvalError> {
+    let name = symbol_arg(form, node)?;
+    let root = context.root();
+    match name.split() {
+        (Some(namespace), _) if namespace != root.namespace() => {
+            Err(EvalError::other(&format!("{}: can't define {} outside of the current namespace", form, name)))
+        },
+        _ => Ok(root.intern_var(name))
+    }
+}
+
+/// `(def name value?)` sets the var `name` in the current namespace and returns it. The var
+/// exists while the value is evaluated, so the value can refer to it. Without a value, the
+/// var is created unbound or left as it is.
+fn def(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("def", args, 1, Some(2))?;
+    let var = var_to_define(context, "def", &args[0])?;
+    if let Some(value) = args.get(1) {
+        var.set(eval_expr(context, value)?);
+    }
+    Ok(Rc::new(Node::Var(var)))
+}
+
+/// `(defonce name value)` is like `def`, but does nothing if the var already has a value.
+fn defonce(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("defonce", args, 2, Some(2))?;
+    let var = var_to_define(context, "defonce", &args[0])?;
+    if !var.is_bound() {
+        var.set(eval_expr(context, &args[1])?);
+    }
+    Ok(Rc::new(Node::Var(var)))
+}
+
+/// Splits a form like `(defn name doc? ...)` into the var it defines and the rest of the
+/// form after the optional docstring.
+fn split_definition(context: &EvalContext, form: &str, args: &NodePtr) -> Result<(Rc<Var>, NodePtr), EvalError> {
+    let (var, rest) = match args.as_ref() {
+        Node::List(name, rest) => (var_to_define(context, form, name)?, rest),
+        _ => return Err(EvalError::syntax(&format!("{}: expected a name", form)))
+    };
+    match rest.as_ref() {
+        Node::List(doc, rest) if matches!(doc.as_ref(), Node::String(_)) => Ok((var, rest.clone())),
+        _ => Ok((var, rest.clone()))
+    }
+}
+
+/// `(defn name doc? [params...] body...)` defines a function. Calls to `name` in the body go
+/// through the var, so they see later redefinitions.
+fn defn(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let (var, rest) = split_definition(context, "defn", args)?;
+    let name = var.name().split().1;
+    let rest = analyze::analyze_arities(context, &rest, &[])?;
+    var.set(make_function(context, Some(name), false, parse_arities(&rest)?)?);
+    Ok(Rc::new(Node::Var(var)))
+}
+
+/// `(defmacro name doc? [params...] body...)` defines a macro: a function called with the
+/// unevaluated arguments of the forms using it, returning the form to evaluate instead. Its
+/// body can also refer to the whole form as `&form` and to the locals in scope as `&env`.
+fn defmacro(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let (var, rest) = split_definition(context, "defmacro", args)?;
+    let name = var.name().split().1;
+    let implicit = [Symbol::FORM, Symbol::ENV];
+    let rest = analyze::analyze_arities(context, &rest, &implicit)?;
+    let implicit = implicit.map(|name| Rc::new(Node::Symbol(name)));
+    let arities = parse_arities(&rest)?.into_iter().map(|arity| arity.with_leading_params(&implicit)).collect();
+    var.set(make_function(context, Some(name), false, arities)?);
+    var.set_macro(true);
+    Ok(Rc::new(Node::Var(var)))
+}
+
+/// `(declare names...)` creates unbound vars, so they can be referred to before being defined.
+fn declare(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let mut result = Rc::new(Node::Nil);
+    for name in arguments("declare", args, 1, None)? {
+        result = Rc::new(Node::Var(var_to_define(context, "declare", &name)?));
+    }
+    Ok(result)
+}
+
+/// `(var name)`, or `#'name`, returns the var itself instead of its value.
+fn var(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let args = arguments("var", args, 1, Some(1))?;
+    let name = symbol_arg("var", &args[0])?;
+    match context.root().find_var(name) {
+        Some(var) => Ok(Rc::new(Node::Var(var))),
+        None => Err(EvalError::new(ErrorKind::UnboundSymbol(name)))
+    }
+}
+
+/// Splits a form like `(let [bindings] body...)` into its binding vector and its body.
+pub fn split_bindings(name: &str, args: &NodePtr) -> Result<(Vec<NodePtr>, NodePtr), EvalError> {
+    match args.as_ref() {
+        Node::List(bindings, body) => match bindings.as_ref() {
+            Node::Vector(vec) => Ok((vec.iter().cloned().collect(), body.clone())),
+            node => Err(EvalError::syntax(&format!("{}: expected a binding vector but got {}", name, node)))
+        },
+        _ => Err(EvalError::syntax(&format!("{}: expected a binding vector", name)))
+    }
+}
+
+pub fn symbol_arg(name: &str, node: &NodePtr) -> Result<Symbol, EvalError> {
+    match node.as_ref() {
+        Node::Symbol(symbol) => Ok(*symbol),
+        node => Err(EvalError::syntax(&format!("{}: expected a symbol but got {}", name, node)))
+    }
+}
+
+/// `(fn name? [params...] body...)` or `(fn name? ([params...] body...) ...)` creates a
+/// function closing over the current frame. A named function can call itself through its
+/// name, and calls to itself in tail position run in constant stack space.
+fn fn_form(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let form = analyze::analyze(context, &with_head("fn", args))?;
+    eval_expr(context, &form)
+}
+
+/// `(fn* name? arities...)` is a `fn` whose body was already analyzed.
+fn analyzed_fn(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    match args.as_ref() {
+        Node::List(name, rest) if matches!(name.as_ref(), Node::Symbol(_)) => {
+            make_function(context, Some(symbol_arg("fn", name)?), true, parse_arities(rest)?)
+        },
+        _ => make_function(context, None, false, parse_arities(args)?)
+    }
+}
+
+/// Reads `([params...] body...)`, or a list of such forms for each arity of a function.
+fn parse_arities(args: &NodePtr) -> Result<Vec<Arity>, EvalError> {
+    let parse_arity = |form: &NodePtr| {
+        let (params, body) = split_bindings("fn", form)?;
+        Arity::new(&params, body)
+    };
+    match args.as_ref() {
+        Node::List(first, _) if matches!(first.as_ref(), Node::List(_, _)) => {
+            args.list_iter().map(|arity| parse_arity(&arity?)).collect()
+        },
+        _ => Ok(vec![parse_arity(args)?])
+    }
+}
+
+/// Creates a function closing over the current frame. If `recursive`, its name is bound to
+/// the function itself in its body.
+fn make_function(context: &EvalContext, name: Option<Symbol>, recursive: bool, arities: Vec<Arity>) -> EvalResult {
+    let lambda = Rc::new(Lambda::new(name, arities)?);
+    let env = match name {
+        Some(name) if recursive => {
+            let frame = context.new_child().frame().clone();
+            frame.set_recursive(name, lambda.clone());
+            frame
+        },
+        _ => context.frame().clone()
+    };
+    Ok(Rc::new(Node::Function(Function::new(lambda, env))))
+}
+
+/// Splits a function of a `letfn` like `(name [params...] body...)` into its name and its
+/// arities.
+pub fn split_letfn(spec: &NodePtr) -> Result<(Symbol, NodePtr), EvalError> {
+    match spec.as_ref() {
+        Node::List(name, arities) => Ok((symbol_arg("letfn", name)?, arities.clone())),
+        node => Err(EvalError::syntax(&format!("letfn: expected a function like (name [params...] body...) but got {}", node)))
+    }
+}
+
+/// Binds the functions of a `letfn*` in a new frame nested in `env`. They all close over that
+/// frame, so they can call each other and themselves by name.
+pub fn letfn_frame(env: &Rc<Frame>, specs: &[NodePtr]) -> Result<Rc<Frame>, EvalError> {
+    let frame = Rc::new(Frame::new(Some(env.clone())));
+    for spec in specs {
+        let (name, arities) = split_letfn(spec)?;
+        frame.set_recursive(name, Rc::new(Lambda::new(Some(name), parse_arities(&arities)?)?));
+    }
+    Ok(frame)
+}
+
+/// `(quote form)` returns the form without evaluating it.
+fn quote(_: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    let mut args = arguments("quote", args, 1, Some(1))?;
+    Ok(args.remove(0))
+}
+
+/// `(lazy-seq body...)` keeps the body unevaluated until the seq is realized.
+fn lazy_seq(context: &mut EvalContext, args: &NodePtr) -> EvalResult {
+    Ok(Rc::new(Node::LazySeq(LazySeq::new(Thunk::Form(args.clone(), context.frame().clone())))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::testing::{eval, eval_err};
+
+    #[test]
+    fn core_forms() {
+        assert_eq!(eval("(def x 1) x"), "1");
+        assert_eq!(eval("[(if false 1 2) (if nil 1) (if 0 1 2) (do 1 2 3)]"), "[2 nil 1 3]");
+        assert_eq!(eval("[(quote (a b)) '(1 (2 c))]"), "[(a b) (1 (2 c))]");
+        assert_eq!(eval("[((fn [x] x) 1) (fn [x] x)]"), "[1 #<fn>]");
+    }
+
+    #[test]
+    fn lexical_scope() {
+        assert_eq!(eval("(let [x 2 y (+ x 1)] [x y])"), "[2 3]");
+        assert_eq!(eval("(let [x 2] [(let [x 3] x) x])"), "[3 2]");
+        assert_eq!(eval("(def x 1) [(let [x 5] x) x]"), "[5 1]");
+        assert_eq!(eval("(let [x 1] ((fn [] ((fn [] x)))))"), "1");
+    }
+
+    #[test]
+    fn malformed_forms() {
+        assert_eq!(eval_err("y"), "Unable to resolve symbol: y");
+        assert_eq!(eval_err("(let [x] x)"), "let: expected an even number of forms in the binding vector");
+        assert_eq!(eval_err("(if)"), "if: expected 2 to 3 arguments but got 0");
+        assert_eq!(eval_err("(def)"), "def: expected 1 to 2 arguments but got 0");
+    }
+
+    #[test]
+    fn letfn_functions_see_each_other() {
+        let parity = "(ev? [n] (if (= n 0) true (od? (dec n)))) (od? [n] (if (= n 0) false (ev? (dec n))))";
+        assert_eq!(eval(&format!("(letfn [{}] [(ev? 10) (od? 7) (ev? 3)])", parity)), "[true true false]");
+        let bouncing = "(ev? [n] (if (= n 0) true (fn [] (od? (dec n))))) (od? [n] (if (= n 0) false (fn [] (ev? (dec n)))))";
+        assert_eq!(eval(&format!("(letfn [{}] (trampoline ev? 10001))", bouncing)), "false");
+        assert_eq!(eval("(letfn [(count-down [n] (if (= n 0) :done (count-down (dec n))))] (count-down 10000))"), ":done");
+        assert_eq!(eval("(defn make [] (letfn [(a [] (b)) (b [] :b)] a)) ((make))"), ":b");
+        assert_eq!(eval("(defn outer [n] (letfn [(helper [k] (if (= k 0) n (helper (dec k))))] (helper 10000))) (outer 7)"), "7");
+    }
+
+    #[test]
+    fn letfn_functions_are_full_functions() {
+        assert_eq!(eval("(letfn [(f ([] (f 1)) ([x] [x :one]))] (f))"), "[1 :one]");
+        assert_eq!(eval("[(letfn [(f [& xs] xs)] (f 1 2)) (letfn [(f [{:keys [a]}] a)] (f {:a 5}))]"), "[(1 2) 5]");
+        assert_eq!(eval("(let [x 10] (letfn [(add [y] (+ x y))] (map add [1 2])))"), "(11 12)");
+        assert_eq!(eval("[(letfn [(f [])] (f)) (letfn [(f [f] f)] (f 3))]"), "[nil 3]");
+        assert_eq!(eval("(loop [i 0] (letfn [(g [] i)] (if (< i 3) (recur (inc i)) (g))))"), "3");
+        assert_eq!(eval("(macroexpand '(letfn [(f [] 1)] (f)))"), "(letfn [(f [] 1)] (f))");
+        assert_eq!(eval_err("(letfn [f 1] 2)"), "letfn: expected a function like (name [params...] body...) but got f");
+    }
+}
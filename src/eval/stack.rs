@@ -0,0 +1,50 @@
+use std::cell::Cell;
+
+use super::{EvalError, ErrorKind};
+
+/// Size of the Rust stack of the threads running evaluations, which `main` gives to the
+/// thread of the REPL. Only the parts actually used take memory.
+pub const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+/// Stack left for the code running between two checks, like a builtin called by a function.
+const RESERVE: usize = 1024 * 1024;
+
+/// Stack assumed to be usable on threads that didn't call `enter`, like the main thread.
+const DEFAULT_USABLE: usize = 1024 * 1024;
+
+thread_local! {
+    /// Address near the start of the stack of the thread, and the number of bytes that can be
+    /// used below it.
+    static BOUNDS: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// Address of a local of the caller, which is about where the stack currently ends.
+#[inline(never)]
+fn stack_pointer() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// Records that the current thread has a stack of `size` bytes, starting about here.
+pub fn enter(size: usize) {
+    BOUNDS.with(|bounds| bounds.set(Some((stack_pointer(), size.saturating_sub(RESERVE)))));
+}
+
+/// Fails with a `StackOverflow` error if the thread is about to run out of stack. Evaluations
+/// nested in builtins and lazy seqs realized by other lazy seqs use the Rust stack, so they
+/// check it before going deeper, and fail instead of crashing the process.
+pub fn check() -> Result<(), EvalError> {
+    let here = stack_pointer();
+    let (base, usable) = BOUNDS.with(|bounds| match bounds.get() {
+        Some(bounds) => bounds,
+        None => {
+            bounds.set(Some((here, DEFAULT_USABLE)));
+            (here, DEFAULT_USABLE)
+        }
+    });
+    // The stack grows down from its base.
+    match base.saturating_sub(here) > usable {
+        true => Err(EvalError::new(ErrorKind::StackOverflow)),
+        false => Ok(())
+    }
+}
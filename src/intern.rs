@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::rc::Rc;
+
+/// Interned name of a symbol or a keyword.
+///
+/// Every distinct name is stored once in a global table and identified by its index, so
+/// comparing and hashing symbols never touches the string. Names are never freed, which
+/// is fine since programs only use a bounded set of them.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+macro_rules! predefined {
+    ($($constant:ident = $name:literal),* $(,)?) => {
+        /// Names interned before any other, in this order, so that their ids are known.
+        const PREDEFINED: &[&str] = &[$($name),*];
+
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        enum Predefined { $($constant),* }
+
+        impl Symbol {
+            $(pub const $constant: Symbol = Symbol(Predefined::$constant as u32);)*
+        }
+    };
+}
+
+// Names the evaluator looks for in forms, like the special forms, which it compares as
+// symbols rather than as strings.
+predefined! {
+    QUOTE = "quote",
+    SYNTAX_QUOTE = "syntax-quote",
+    UNQUOTE = "unquote",
+    UNQUOTE_SPLICING = "unquote-splicing",
+    VAR = "var",
+    DEF = "def",
+    DEFONCE = "defonce",
+    DEFN = "defn",
+    DEFMACRO = "defmacro",
+    DECLARE = "declare",
+    IF = "if",
+    DO = "do",
+    LET = "let",
+    LOOP = "loop",
+    LOOP_STAR = "loop*",
+    FN = "fn",
+    FN_STAR = "fn*",
+    LETFN = "letfn",
+    LETFN_STAR = "letfn*",
+    RECUR = "recur",
+    TRY = "try",
+    THROW = "throw",
+    CATCH = "catch",
+    FINALLY = "finally",
+    CASE_STAR = "case*",
+    LAZY_SEQ = "lazy-seq",
+    AMPERSAND = "&",
+    FORM = "&form",
+    ENV = "&env"
+}
+
+struct Entry {
+    name: Rc<str>,
+    /// Namespace of a qualified name like `user/id`, and its name without the namespace, or
+    /// the name itself if it isn't qualified. They are found once, when the name is interned.
+    namespace: Option<Symbol>,
+    local: Symbol
+}
+
+struct Interner {
+    entries: Vec<Entry>,
+    ids: HashMap<Rc<str>, u32>
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        let mut interner = Self {
+            entries: Vec::new(),
+            ids: HashMap::new()
+        };
+        for name in PREDEFINED {
+            interner.intern(name);
+        }
+        interner
+    }
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&id) = self.ids.get(name) {
+            return Symbol(id);
+        }
+        // Qualified names are split at the first slash. Names without a slash, and `/`
+        // itself, have no namespace.
+        let (namespace, local) = match name.find('/') {
+            Some(index) if index > 0 && index + 1 < name.len() => {
+                (Some(self.intern(&name[..index])), Some(self.intern(&name[index + 1..])))
+            },
+            _ => (None, None)
+        };
+        let id = self.entries.len() as u32;
+        let name: Rc<str> = Rc::from(name);
+        self.entries.push(Entry { name: name.clone(), namespace, local: local.unwrap_or(Symbol(id)) });
+        self.ids.insert(name, id);
+        Symbol(id)
+    }
+
+    fn entry(&self, symbol: Symbol) -> &Entry {
+        &self.entries[symbol.0 as usize]
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Symbol {
+
+    pub fn intern(name: &str) -> Self {
+        INTERNER.with(|interner| interner.borrow_mut().intern(name))
+    }
+
+    pub fn name(self) -> Rc<str> {
+        INTERNER.with(|interner| interner.borrow().entry(self).name.clone())
+    }
+
+    pub fn qualified(namespace: &str, name: &str) -> Self {
+        Self::intern(&format!("{}/{}", namespace, name))
+    }
+
+    /// Splits a qualified name like `user/id` into its namespace and its name, at the first
+    /// slash. Names without a slash, and `/` itself, have no namespace.
+    pub fn split(self) -> (Option<Symbol>, Symbol) {
+        INTERNER.with(|interner| {
+            let interner = interner.borrow();
+            let entry = interner.entry(self);
+            (entry.namespace, entry.local)
+        })
+    }
+
+}
+
+/// Symbols sort by name, so that the order doesn't depend on when they were interned.
+/// Names without a namespace go first, the others are sorted by namespace, then by name.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self == other {
+            return Ordering::Equal;
+        }
+        INTERNER.with(|interner| {
+            let interner = interner.borrow();
+            let name = |symbol| interner.entry(symbol).name.as_ref();
+            let (a, b) = (interner.entry(*self), interner.entry(*other));
+            let by_namespace = match (a.namespace, b.namespace) {
+                (Some(a), Some(b)) => name(a).cmp(name(b)),
+                (a, b) => a.is_some().cmp(&b.is_some())
+            };
+            by_namespace.then_with(|| name(a.local).cmp(name(b.local)))
+        })
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Symbol({})", self.name())
+    }
+}
+
+/// Hasher for maps keyed by symbols. Their ids are already unique small integers, so they
+/// are used as the hash directly.
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 << 8) | u64::from(byte);
+        }
+    }
+
+    fn write_u32(&mut self, id: u32) {
+        self.0 = u64::from(id);
+    }
+}
+
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_name_same_symbol() {
+        let a = Symbol::intern("interned-name");
+        assert_eq!(a, Symbol::intern("interned-name"));
+        assert_ne!(a, Symbol::intern("interned-other"));
+        assert_eq!(a.name().as_ref(), "interned-name");
+        assert_eq!(Symbol::qualified("user", "id"), Symbol::intern("user/id"));
+    }
+
+    #[test]
+    fn predefined_names_are_constants() {
+        assert_eq!(Symbol::intern("fn*"), Symbol::FN_STAR);
+        assert_eq!(Symbol::AMPERSAND.name().as_ref(), "&");
+        // Every thread has its own table, with the same ids for them.
+        std::thread::spawn(|| assert_eq!(Symbol::intern("recur"), Symbol::RECUR)).join().unwrap();
+    }
+
+    #[test]
+    fn split_at_the_first_slash() {
+        let split = |name| {
+            let (namespace, name) = Symbol::intern(name).split();
+            (namespace.map(|namespace| namespace.to_string()), name.to_string())
+        };
+        assert_eq!(split("user/id"), (Some("user".to_string()), "id".to_string()));
+        assert_eq!(split("a/b/c"), (Some("a".to_string()), "b/c".to_string()));
+        assert_eq!(split("id"), (None, "id".to_string()));
+        assert_eq!(split("/"), (None, "/".to_string()));
+        assert_eq!(split("clojure.core//"), (Some("clojure.core".to_string()), "/".to_string()));
+    }
+
+    #[test]
+    fn order_is_by_name() {
+        // Interned in the reverse of their order.
+        let names = ["zz/b", "zz/a", "aa/z", "z", "a"];
+        let mut symbols: Vec<Symbol> = names.iter().map(|name| Symbol::intern(name)).collect();
+        symbols.sort();
+        assert_eq!(symbols.iter().map(|symbol| symbol.to_string()).collect::<Vec<_>>(), ["a", "z", "aa/z", "zz/a", "zz/b"]);
+    }
+}
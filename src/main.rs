@@ -1,22 +1,55 @@
 mod parser;
 mod nodes;
+mod collections;
 mod context;
 mod eval;
-
-use parser::tokenize;
-use crate::parser::{parse_file, TokenKind};
-use std::io::Write;
+mod intern;
+mod numbers;
+mod diagnostic;
+
+use parser::{tokenize, tokenize_from};
+use crate::parser::parse_file;
+use crate::diagnostic::{Diagnostic, Sources};
+use std::io::{IsTerminal, Write};
 use crate::eval::eval_file;
 use crate::context::EvalContext;
+use crate::intern::Symbol;
 
 
 fn main() {
-    repl().unwrap();
+    // Evaluations nested in builtins use the Rust stack, so they run in a thread with a large
+    // one. A path runs that file, otherwise the REPL starts.
+    let path = std::env::args().nth(1);
+    let main = std::thread::Builder::new()
+        .stack_size(eval::stack::STACK_SIZE)
+        .spawn(|| {
+            eval::stack::enter(eval::stack::STACK_SIZE);
+            match path {
+                Some(path) => run_file(&path),
+                None => repl()
+            }
+        })
+        .expect("Could not start the evaluation thread");
+    if !main.join().expect("The evaluation panicked").unwrap() {
+        std::process::exit(1);
+    }
 }
 
-fn repl() -> std::io::Result<()> {
+/// Context for evaluating the REPL or a file. Evaluations can go as deep as memory allows,
+/// unless LISPURE_MAX_DEPTH sets a limit.
+fn new_context() -> EvalContext {
+    let context = EvalContext::new_main();
+    let max_depth = std::env::var("LISPURE_MAX_DEPTH").ok().and_then(|max| max.parse().ok());
+    context.root().set_max_depth(max_depth);
+    context
+}
 
-    let mut context = EvalContext::new_main();
+fn repl() -> std::io::Result<bool> {
+
+    let mut context = new_context();
+    // The inputs make up a single source, so errors can show the lines of earlier inputs.
+    let mut sources = Sources::new();
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
 
     loop {
 
@@ -31,57 +64,82 @@ fn repl() -> std::io::Result<()> {
             break;
         }
 
-        let tokens = match tokenize(&code) {
+        let line = sources.append("repl", &code);
+        let tokens = match tokenize_from(&code, "repl", line) {
             Ok(tokens) => tokens,
             Err(err) => {
-                println!("{}", err);
+                print!("{}", Diagnostic::from(&err).render(&sources, color));
                 continue;
             }
         };
 
-        let expr = match parse_file(&mut tokens.iter().peekable()) {
+        let expr = match parse_file(&mut tokens.iter().peekable(), context.root().namespace()) {
             Ok(expr) => expr,
             Err(err) => {
-                println!("{}", err);
+                print!("{}", Diagnostic::from(&err).render(&sources, color));
                 continue;
             }
         };
 
-        let result = eval::eval_file(&mut context, &expr);
+        // Lazy seqs are realized here, since printing can't evaluate anything.
+        let result = eval::eval_file(&mut context, &expr)
+            .and_then(|result| eval::seq::realize_deep(&mut context, &result).map(|_| result));
         match result {
             Ok(result) => {
                 println!("{}", result);
             },
-            Err(err) => println!("{}", err)
+            Err(err) => {
+                print!("{}", Diagnostic::from(&err).render(&sources, color));
+                // The last error is kept in `*e`, with its stack trace.
+                context.root().intern_var(Symbol::intern("*e")).set(err.to_exception());
+            }
         }
 
         std::io::stdout().flush()?;
 
     }
 
-    Ok(())
+    Ok(true)
 
 }
 
-fn _run_file() {
+/// Evaluates the file at `path`, rendering the first error on stderr. Returns whether it
+/// succeeded.
+fn run_file(path: &str) -> std::io::Result<bool> {
 
-    let source = std::fs::read_to_string("./test.clj").expect("Could not open file");
-    let tokens = tokenize(&source).unwrap();
-
-    println!("Source:\n\t{}", source.trim());
-    println!("Tokens:\n\t{:?}", tokens.iter().map(|token| &token.0).collect::<Vec<&TokenKind>>());
-
-    print!("Nodes:\n\t");
-    let parse_result = parse_file(&mut tokens.iter().peekable());
-
-    let mut context = EvalContext::new_main();
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Could not read {}: {}", path, err);
+            return Ok(false);
+        }
+    };
+    let mut sources = Sources::new();
+    sources.set(path, &source);
+    let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let tokens = match tokenize(&source, path) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            eprint!("{}", Diagnostic::from(&err).render(&sources, color));
+            return Ok(false);
+        }
+    };
+
+    let mut context = new_context();
+    let expr = match parse_file(&mut tokens.iter().peekable(), context.root().namespace()) {
+        Ok(expr) => expr,
+        Err(err) => {
+            eprint!("{}", Diagnostic::from(&err).render(&sources, color));
+            return Ok(false);
+        }
+    };
 
-    match &parse_result {
-        Ok(node) => {
-            let result = eval_file(&mut context, node).unwrap();
-            println!("{}", result)
-        },
-        Err(error) => eprintln!("{}", error)
+    match eval_file(&mut context, &expr) {
+        Ok(_) => Ok(true),
+        Err(err) => {
+            eprint!("{}", Diagnostic::from(&err).render(&sources, color));
+            Ok(false)
+        }
     }
 
 }
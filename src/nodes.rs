@@ -1,45 +1,115 @@
 use std::rc::Rc;
-use std::collections::{HashSet, HashMap};
 use std::hash::{Hash, Hasher};
-use std::iter::IntoIterator;
+use std::cmp::Ordering;
+use crate::collections::{
+    PersistentVector, PersistentHashMap, PersistentHashSet, PersistentTreeMap, PersistentTreeSet,
+    PersistentQueue, PersistentPriorityQueue, Transient, hash_of
+};
+use crate::eval::{LazySeq, Function, Exception, EvalError};
+use crate::context::{Var, Builtin};
+use crate::intern::Symbol;
+use crate::numbers::{self, Number, BigInt, Ratio, BigDecimal};
 
 #[derive(Debug)]
 pub enum Node {
     Nil,
-    List(NodePtr, NodePtr, bool),
-    Vector(Vec<NodePtr>),
-    Set(HashSet<NodePtr>),
-    Map(HashMap<NodePtr, NodePtr>),
-    Symbol(String),
-    Ident(String),
+    List(NodePtr, NodePtr),
+    Seq(PersistentVector<NodePtr>),
+    LazySeq(LazySeq),
+    Vector(PersistentVector<NodePtr>),
+    Set(PersistentHashSet<NodePtr>),
+    Map(PersistentHashMap<NodePtr, NodePtr>),
+    SortedSet(PersistentTreeSet<NodePtr>, Option<NodePtr>),
+    SortedMap(PersistentTreeMap<NodePtr, NodePtr>, Option<NodePtr>),
+    Queue(PersistentQueue<NodePtr>),
+    PriorityQueue(PersistentPriorityQueue<NodePtr>, Option<NodePtr>),
+    Transient(Transient<TransientCollection>),
+    Function(Function),
+    Builtin(Symbol, Builtin),
+    Var(Rc<Var>),
+    Exception(Rc<Exception>),
+    Symbol(Symbol),
+    Keyword(Symbol),
     String(String),
     Char(char),
     Integer(i64),
-    Float(f64)
+    BigInt(BigInt),
+    Ratio(Ratio),
+    Decimal(BigDecimal),
+    Float(f64),
+    Bool(bool)
 }
 
 pub type NodePtr = Rc<Node>;
 
+/// Collections that can be edited through a transient.
+#[derive(Debug)]
+pub enum TransientCollection {
+    Vector(PersistentVector<NodePtr>),
+    Set(PersistentHashSet<NodePtr>),
+    Map(PersistentHashMap<NodePtr, NodePtr>)
+}
+
+impl TransientCollection {
+    fn len(&self) -> usize {
+        match self {
+            Self::Vector(vec) => vec.len(),
+            Self::Set(set) => set.len(),
+            Self::Map(map) => map.len()
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Vector(_) => "vector",
+            Self::Set(_) => "set",
+            Self::Map(_) => "map"
+        }
+    }
+}
+
 impl Node {
 
-    pub fn is_list(&self) -> bool {
+    pub fn is_sequential(&self) -> bool {
+        matches!(self, Self::List(_, _) | Self::Seq(_) | Self::LazySeq(_) | Self::Vector(_) | Self::Queue(_))
+    }
+
+    pub fn as_number(&self) -> Option<Number> {
         match self {
-            Self::List(_, _, _) => true,
-            _ => false
+            Self::Integer(int) => Some(Number::Integer(*int)),
+            Self::BigInt(int) => Some(Number::BigInt(int.clone())),
+            Self::Ratio(ratio) => Some(Number::Ratio(ratio.clone())),
+            Self::Decimal(decimal) => Some(Number::Decimal(decimal.clone())),
+            Self::Float(float) => Some(Number::Float(*float)),
+            _ => None
         }
     }
 
+    /// Everything except `nil` and `false` counts as true in conditions.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Nil | Self::Bool(false))
+    }
+
     pub fn len(&self) -> Option<usize> {
         match self {
-            Self::List(_, right, _) => {
-                if let Self::Nil = right.as_ref() {
-                    Some(1)
-                } else{
-                    Some(1 + right.len().expect("List's right node is neither list or nil"))
+            Self::Nil => Some(0),
+            Self::List(_, _) | Self::Seq(_) | Self::LazySeq(_) => {
+                // Counting a partially realized seq would need the context to realize the rest.
+                if is_realized(self) {
+                    Some(sequential_iter(self).count())
+                } else {
+                    None
                 }
             },
             Self::Vector(vec) => Some(vec.len()),
             Self::Set(set) => Some(set.len()),
+            Self::Map(map) => Some(map.len()),
+            Self::SortedSet(set, _) => Some(set.len()),
+            Self::SortedMap(map, _) => Some(map.len()),
+            Self::Queue(queue) => Some(queue.len()),
+            Self::PriorityQueue(queue, _) => Some(queue.len()),
+            Self::Transient(transient) => transient.read(TransientCollection::len),
+            Self::String(string) => Some(string.chars().count()),
             _ => None
         }
     }
@@ -48,6 +118,11 @@ impl Node {
 
 pub trait IntoListIter {
     fn list_iter(&self) -> NodeIter;
+
+    /// Collects the elements of a list, failing if it doesn't end like a seq should.
+    fn list_elements(&self) -> Result<Vec<NodePtr>, EvalError> {
+        self.list_iter().collect()
+    }
 }
 
 impl IntoListIter for NodePtr {
@@ -60,75 +135,400 @@ impl IntoListIter for NodePtr {
 
 }
 
+/// Iterates over the elements of a list, or of a seq that is already realized. Anything else
+/// in the rest of the list is an error, which ends the iteration.
 pub struct NodeIter {
     node: NodePtr
 }
 
+impl NodeIter {
+    fn fail(&mut self, message: &str) -> Option<Result<NodePtr, EvalError>> {
+        let err = EvalError::type_error(&format!("{} {}", message, self.node));
+        self.node = Rc::new(Node::Nil);
+        Some(Err(err))
+    }
+}
+
 impl Iterator for NodeIter {
-    type Item = NodePtr;
+    type Item = Result<NodePtr, EvalError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.node.clone().as_ref() {
-            Node::List(left, right, _) => {
-                self.node = right.clone();
-                Some(left.clone())
-            },
-            Node::Nil => None,
-            _ => panic!("Iterating over non-list node.")
+        loop {
+            match self.node.clone().as_ref() {
+                Node::List(left, right) => {
+                    self.node = right.clone();
+                    return Some(Ok(left.clone()));
+                },
+                Node::Seq(vec) => {
+                    self.node = match vec.subvec(1, vec.len()) {
+                        Some(rest) if !rest.is_empty() => Rc::new(Node::Seq(rest)),
+                        _ => Rc::new(Node::Nil)
+                    };
+                    return vec.first().cloned().map(Ok);
+                },
+                Node::LazySeq(lazy) => match lazy.realized() {
+                    Some(value) => self.node = value.clone(),
+                    None => return self.fail("Expected a list but got the unrealized lazy seq")
+                },
+                Node::Nil => return None,
+                _ => return self.fail("Expected a list but got")
+            }
         }
     }
 }
 
 
+impl From<Number> for Node {
+    fn from(number: Number) -> Self {
+        match number {
+            Number::Integer(int) => Node::Integer(int),
+            Number::BigInt(int) => Node::BigInt(int),
+            Number::Ratio(ratio) => Node::Ratio(ratio),
+            Number::Decimal(decimal) => Node::Decimal(decimal),
+            Number::Float(float) => Node::Float(float)
+        }
+    }
+}
+
 impl std::fmt::Display for Node {
 
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Node::Nil => write!(f, "Nil"),
-            Node::List(_, _, _) => {
-                write!(f, "(")?;
-                let mut node = self;
-                while let Node::List(left, right, _) = node {
-                    write!(f, "{}", left)?;
-                    if let Node::List(_, _, _) = **right {
-                        write!(f, " ")?;
-                    }
-                    node = right;
-                }
-                write!(f, ")")
+            Node::Nil => write!(f, "nil"),
+            Node::List(_, _) | Node::Seq(_) => write_elements(f, "(", sequential_iter(self), ")"),
+            Node::LazySeq(lazy) => match lazy.realized() {
+                Some(_) => write_elements(f, "(", sequential_iter(self), ")"),
+                None => write!(f, "#<lazy-seq>")
             },
-            Node::Vector(vec) => {
-                write!(f, "[")?;
-                let mut iter = vec.iter().peekable();
-                while let Some(node) = iter.next() {
-                    write!(f, "{}", node)?;
-                    if let Some(_) = iter.peek() {
-                        write!(f, " ")?;
-                    }
-                }
-                write!(f, "]")
+            Node::Vector(vec) => write_elements(f, "[", vec.iter(), "]"),
+            Node::Set(set) => write_elements(f, "#{", set.iter(), "}"),
+            Node::SortedSet(set, _) => write_elements(f, "#{", set.iter(), "}"),
+            Node::Map(map) => write_entries(f, common_namespace(map.iter().map(|(key, _)| key)), map.iter()),
+            Node::SortedMap(map, _) => write_entries(f, common_namespace(map.iter().map(|(key, _)| key)), map.iter()),
+            Node::Queue(queue) => write_elements(f, "#queue [", queue.iter(), "]"),
+            Node::PriorityQueue(queue, _) => write_elements(f, "#priority-queue [", queue.iter(), "]"),
+            Node::Transient(transient) => match transient.read(TransientCollection::kind) {
+                Some(kind) => write!(f, "#<transient {}>", kind),
+                None => write!(f, "#<transient>")
+            },
+            Node::Function(function) => match function.name() {
+                Some(name) => write!(f, "#<fn {}>", name),
+                None => write!(f, "#<fn>")
             },
-            Node::Set(_) => unimplemented!(),
-            Node::Map(_) => unimplemented!(),
+            Node::Builtin(name, _) => write!(f, "#<fn {}>", name),
+            Node::Var(var) => write!(f, "#'{}", var.name()),
+            Node::Exception(exception) => write!(f, "{}", exception),
             Node::Symbol(symbol) => write!(f, "{}", symbol),
-            Node::Ident(ident) => write!(f, "{}", ident),
+            Node::Keyword(keyword) => write!(f, ":{}", keyword),
             Node::String(string) => write!(f, "\"{}\"", string),
             Node::Char(char) => write!(f, "\\{}", char),
-            Node::Integer(int) => write!(f, "{}", int),
-            Node::Float(float) => write!(f, "{}", float)
+            Node::Integer(_) | Node::BigInt(_) | Node::Ratio(_) | Node::Decimal(_) | Node::Float(_) => {
+                write!(f, "{}", self.as_number().unwrap())
+            },
+            Node::Bool(bool) => write!(f, "{}", bool)
+        }
+    }
+
+}
+
+fn write_elements<'a, I>(f: &mut std::fmt::Formatter<'_>, open: &str, iter: I, close: &str) -> std::fmt::Result
+    where I: Iterator<Item = &'a NodePtr>
+{
+    write!(f, "{}", open)?;
+    let mut iter = iter.peekable();
+    while let Some(node) = iter.next() {
+        write!(f, "{}", node)?;
+        if iter.peek().is_some() {
+            write!(f, " ")?;
+        }
+    }
+    write!(f, "{}", close)
+}
+
+/// Namespace of the keys of a map when they are all keywords qualified with the same one, in
+/// which case the map is printed with the `#:ns{}` syntax.
+fn common_namespace<'a>(mut keys: impl Iterator<Item = &'a NodePtr>) -> Option<Symbol> {
+    let keyword_namespace = |key: &NodePtr| match key.as_ref() {
+        Node::Keyword(keyword) => keyword.split().0,
+        _ => None
+    };
+    let namespace = keyword_namespace(keys.next()?)?;
+    keys.all(|key| keyword_namespace(key) == Some(namespace)).then_some(namespace)
+}
+
+fn write_entries<'a, I>(f: &mut std::fmt::Formatter<'_>, namespace: Option<Symbol>, iter: I) -> std::fmt::Result
+    where I: Iterator<Item = (&'a NodePtr, &'a NodePtr)>
+{
+    match namespace {
+        Some(namespace) => write!(f, "#:{}{{", namespace)?,
+        None => write!(f, "{{")?
+    }
+    let mut iter = iter.peekable();
+    while let Some((key, value)) = iter.next() {
+        match (namespace, key.as_ref()) {
+            (Some(_), Node::Keyword(keyword)) => write!(f, ":{} {}", keyword.split().1, value)?,
+            _ => write!(f, "{} {}", key, value)?
+        }
+        if iter.peek().is_some() {
+            write!(f, ", ")?;
+        }
+    }
+    write!(f, "}}")
+}
+
+/// Default ordering of values, used by `compare`, `sort` and sorted collections without a
+/// custom comparator. Returns `None` if the values can't be compared to each other.
+pub fn compare(a: &Node, b: &Node) -> Option<Ordering> {
+    match (a, b) {
+        (Node::Nil, Node::Nil) => Some(Ordering::Equal),
+        (Node::Nil, _) => Some(Ordering::Less),
+        (_, Node::Nil) => Some(Ordering::Greater),
+        (Node::Integer(a), Node::Integer(b)) => Some(a.cmp(b)),
+        (a, b) if a.as_number().is_some() && b.as_number().is_some() => {
+            numbers::compare(&a.as_number().unwrap(), &b.as_number().unwrap())
+        },
+        (Node::Bool(a), Node::Bool(b)) => Some(a.cmp(b)),
+        (Node::Char(a), Node::Char(b)) => Some(a.cmp(b)),
+        (Node::String(a), Node::String(b)) => Some(a.cmp(b)),
+        (Node::Symbol(a), Node::Symbol(b)) => Some(a.cmp(b)),
+        (Node::Keyword(a), Node::Keyword(b)) => Some(a.cmp(b)),
+        // Shorter vectors sort first, vectors of the same length compare element by element.
+        (Node::Vector(a), Node::Vector(b)) => {
+            if a.len() != b.len() {
+                return Some(a.len().cmp(&b.len()));
+            }
+            for (a, b) in a.iter().zip(b.iter()) {
+                match compare(a, b)? {
+                    Ordering::Equal => continue,
+                    ordering => return Some(ordering)
+                }
+            }
+            Some(Ordering::Equal)
+        },
+        _ => None
+    }
+}
+
+/// Finds what a hash or sorted map or set has under `key`: the value for maps, the element
+/// for sets. Collections sorted with a comparator are looked up with `lookup`.
+fn unordered_get<E>(coll: &Node, key: &NodePtr, lookup: Option<&mut Lookup<E>>) -> Result<Option<NodePtr>, E> {
+    let mut compare = |a: &NodePtr, b: &NodePtr| compare(a, b).ok_or(());
+    Ok(match coll {
+        Node::Map(map) => map.get(key).cloned(),
+        Node::Set(set) => set.get(key).cloned(),
+        Node::SortedMap(map, None) => map.get(key, &mut compare).ok().flatten().map(|(_, value)| value.clone()),
+        Node::SortedSet(set, None) => set.get(key, &mut compare).ok().flatten().cloned(),
+        _ => match lookup {
+            Some(lookup) => lookup(coll, key)?,
+            None => None
         }
+    })
+}
+
+/// Entries of a hash or sorted map or set, with no value for sets.
+fn unordered_entries(coll: &Node) -> Box<dyn Iterator<Item = (&NodePtr, Option<&NodePtr>)> + '_> {
+    match coll {
+        Node::Map(map) => Box::new(map.iter().map(|(key, value)| (key, Some(value)))),
+        Node::SortedMap(map, _) => Box::new(map.iter().map(|(key, value)| (key, Some(value)))),
+        Node::Set(set) => Box::new(set.iter().map(|element| (element, None))),
+        Node::SortedSet(set, _) => Box::new(set.iter().map(|element| (element, None))),
+        _ => Box::new(std::iter::empty())
     }
+}
 
+fn has_comparator(coll: &Node) -> bool {
+    matches!(coll, Node::SortedMap(_, Some(_)) | Node::SortedSet(_, Some(_)))
+}
+
+/// Compares two maps or two sets of the same size by looking up the entries of one in the
+/// other. The side looked up is the one with a comparator if there's a `lookup` to call it,
+/// and the one without otherwise. When both have one and there's no `lookup`, the entries
+/// are instead found with `=` in a hash index, like keys of a hash map.
+fn unordered_equals<E>(a: &Node, b: &Node, mut lookup: Option<&mut Lookup<E>>) -> Result<bool, E> {
+    let (walked, probed) = match (has_comparator(a), has_comparator(b), lookup.is_some()) {
+        (true, false, true) | (false, true, false) => (b, a),
+        _ => (a, b)
+    };
+    let index: Option<std::collections::HashMap<&NodePtr, Option<&NodePtr>>> = match has_comparator(probed) && lookup.is_none() {
+        true => Some(unordered_entries(probed).collect()),
+        false => None
+    };
+    for (key, value) in unordered_entries(walked) {
+        let found = match &index {
+            Some(index) => index.get(key).map(|found| found.cloned().unwrap_or_else(|| key.clone())),
+            None => unordered_get(probed, key, lookup.as_deref_mut())?
+        };
+        let equal = match (value, found) {
+            (_, None) => false,
+            (Some(value), Some(found)) => equals_with(value, &found, lookup.as_deref_mut())?,
+            (None, Some(_)) => true
+        };
+        if !equal {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+pub fn map_entry(key: &NodePtr, value: &NodePtr) -> NodePtr {
+    Rc::new(Node::Vector(vec![key.clone(), value.clone()].into_iter().collect()))
+}
+
+/// Builds a list out of the given nodes, keeping their order.
+pub fn list_from<I>(nodes: I) -> NodePtr
+    where I: IntoIterator<Item = NodePtr>, I::IntoIter: DoubleEndedIterator
+{
+    nodes.into_iter().rev().fold(Rc::new(Node::Nil), |list, node| {
+        Rc::new(Node::List(node, list))
+    })
+}
+
+/// Checks that a seq has no unrealized lazy part left.
+fn is_realized(mut node: &Node) -> bool {
+    loop {
+        node = match node {
+            Node::List(_, right) => right,
+            Node::LazySeq(lazy) => match lazy.realized() {
+                Some(value) => value,
+                None => return false
+            },
+            _ => return true
+        };
+    }
+}
+
+/// Iterates over the elements of a seq, a vector or a queue.
+///
+/// Unrealized lazy seqs can't be realized without the context, so iteration stops there.
+fn sequential_iter<'a>(node: &'a Node) -> Box<dyn Iterator<Item = &'a NodePtr> + 'a> {
+    match node {
+        Node::Vector(vec) | Node::Seq(vec) => Box::new(vec.iter()),
+        Node::Queue(queue) => Box::new(queue.iter()),
+        _ => {
+            let mut node = node;
+            let mut rest: Option<Box<dyn Iterator<Item = &'a NodePtr> + 'a>> = None;
+            Box::new(std::iter::from_fn(move || loop {
+                if let Some(rest) = &mut rest {
+                    return rest.next();
+                }
+                match node {
+                    Node::List(left, right) => {
+                        node = right;
+                        return Some(left);
+                    },
+                    Node::LazySeq(lazy) => node = lazy.realized()?,
+                    Node::Seq(vec) => rest = Some(Box::new(vec.iter())),
+                    _ => return None
+                }
+            }))
+        }
+    }
+}
+
+thread_local! {
+    static NIL: NodePtr = Rc::new(Node::Nil);
+}
+
+impl Node {
+    /// Detaches the rest of a seq, leaving `nil` in its place.
+    fn take_tail(&mut self) -> Option<NodePtr> {
+        match self {
+            Node::List(_, right) if matches!(right.as_ref(), Node::List(_, _) | Node::LazySeq(_)) => {
+                // Nodes can be dropped by other thread locals after `NIL` is gone.
+                let nil = NIL.try_with(Rc::clone).unwrap_or_else(|_| Rc::new(Node::Nil));
+                Some(std::mem::replace(right, nil))
+            },
+            Node::LazySeq(lazy) => lazy.take_value(),
+            _ => None
+        }
+    }
+}
+
+/// Seqs can be arbitrarily long, and dropping them recursively would overflow the stack.
+/// Instead, the tail is detached and dropped in a loop for as long as nothing else owns it.
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut tail = match self.take_tail() {
+            Some(tail) => tail,
+            None => return
+        };
+        while let Ok(mut node) = Rc::try_unwrap(tail) {
+            tail = match node.take_tail() {
+                Some(tail) => tail,
+                None => return
+            };
+        }
+    }
+}
+
+/// Finds the value of a key in a map sorted with a comparator, or the element equal to a
+/// value in such a set, which needs a context to call the comparator.
+pub type Lookup<'a, E> = dyn FnMut(&Node, &NodePtr) -> Result<Option<NodePtr>, E> + 'a;
+
+/// Equality of nodes, the one of `=`. Maps and sets sorted with a comparator are looked up
+/// with `lookup` if there's one, see `unordered_equals`.
+pub fn equals_with<E>(a: &Node, b: &Node, mut lookup: Option<&mut Lookup<E>>) -> Result<bool, E> {
+    Ok(match (a, b) {
+        (Node::Nil, Node::Nil) => true,
+        (Node::Symbol(a), Node::Symbol(b)) => a == b,
+        (Node::Keyword(a), Node::Keyword(b)) => a == b,
+        (Node::String(a), Node::String(b)) => a == b,
+        (Node::Char(a), Node::Char(b)) => a == b,
+        (Node::Integer(a), Node::Integer(b)) => a == b,
+        // NaN is equal to itself, unlike with `==`, so that equality is reflexive as `Eq`
+        // requires and NaN can be found in maps and sets.
+        (Node::Float(a), Node::Float(b)) => a == b || (a.is_nan() && b.is_nan()),
+        (a @ (Node::Integer(_) | Node::BigInt(_) | Node::Ratio(_) | Node::Decimal(_) | Node::Float(_)),
+         b @ (Node::Integer(_) | Node::BigInt(_) | Node::Ratio(_) | Node::Decimal(_) | Node::Float(_))) => {
+            numbers::equals(&a.as_number().unwrap(), &b.as_number().unwrap())
+        },
+        (Node::Bool(a), Node::Bool(b)) => a == b,
+        // Transients are mutable, so they are only equal to themselves.
+        (Node::Transient(_), Node::Transient(_)) => std::ptr::eq(a, b),
+        (Node::Function(a), Node::Function(b)) => a == b,
+        // There is one builtin for each name.
+        (Node::Builtin(a, _), Node::Builtin(b, _)) => a == b,
+        (Node::Var(a), Node::Var(b)) => Rc::ptr_eq(a, b),
+        (Node::Exception(a), Node::Exception(b)) => a.identity() == b.identity(),
+        (Node::PriorityQueue(a, _), Node::PriorityQueue(b, _)) => {
+            a.len() == b.len() && all_equal(a.iter(), b.iter(), lookup)?
+        },
+        (Node::Set(_) | Node::SortedSet(_, _), Node::Set(_) | Node::SortedSet(_, _)) |
+        (Node::Map(_) | Node::SortedMap(_, _), Node::Map(_) | Node::SortedMap(_, _)) => {
+            a.len() == b.len() && unordered_equals(a, b, lookup.as_deref_mut())?
+        },
+        (a, b) if a.is_sequential() && b.is_sequential() => {
+            if let (Some(a), Some(b)) = (a.len(), b.len()) {
+                if a != b {
+                    return Ok(false);
+                }
+            }
+            all_equal(sequential_iter(a), sequential_iter(b), lookup)?
+        },
+        _ => false
+    })
+}
+
+/// Checks if two sequences have the same length and equal elements in the same order.
+fn all_equal<'a, E>(
+    mut a: impl Iterator<Item = &'a NodePtr>,
+    mut b: impl Iterator<Item = &'a NodePtr>,
+    mut lookup: Option<&mut Lookup<E>>
+) -> Result<bool, E> {
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ok(true),
+            (Some(a), Some(b)) if equals_with(a, b, lookup.as_deref_mut())? => continue,
+            _ => return Ok(false)
+        }
+    }
 }
 
 impl PartialEq for Node {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Nil, Self::Nil) => true,
-            (Self::Symbol(a), Self::Symbol(b)) => PartialEq::eq(a, b),
-            (Self::Ident(a), Self::Ident(b)) => PartialEq::eq(a, b),
-            (Self::Integer(a), Self::Integer(b)) => PartialEq::eq(a, b),
-            _ => false
+        match equals_with::<std::convert::Infallible>(self, other, None) {
+            Ok(equal) => equal,
+            Err(never) => match never {}
         }
     }
 }
@@ -139,11 +539,78 @@ impl Hash for Node {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
             Self::Symbol(symbol) => symbol.hash(state),
-            Self::Ident(ident) => ident.hash(state),
+            Self::Keyword(ident) => ident.hash(state),
             Self::String(string) => string.hash(state),
             Self::Char(char) => char.hash(state),
             Self::Integer(int) => int.hash(state),
-            _ => panic!("Trying to hash an unhashable node.")
+            // Big integers that fit in 64 bits must hash like the equal plain integer.
+            Self::BigInt(int) => match int.to_i64() {
+                Some(int) => int.hash(state),
+                None => int.hash(state)
+            },
+            Self::Ratio(ratio) => ratio.hash(state),
+            Self::Decimal(decimal) => decimal.hash(state),
+            // Equal floats must hash the same: -0.0 like 0.0, and every NaN alike.
+            Self::Float(float) => match float {
+                float if *float == 0.0 => 0.0f64.to_bits().hash(state),
+                float if float.is_nan() => f64::NAN.to_bits().hash(state),
+                float => float.to_bits().hash(state)
+            },
+            Self::Bool(bool) => bool.hash(state),
+            Self::Nil => 0.hash(state),
+            Self::List(_, _) | Self::Seq(_) | Self::LazySeq(_) | Self::Vector(_) | Self::Queue(_) => {
+                for node in sequential_iter(self) {
+                    node.hash(state);
+                }
+            },
+            Self::PriorityQueue(queue, _) => {
+                for node in queue.iter() {
+                    node.hash(state);
+                }
+            },
+            Self::Transient(_) => std::ptr::hash(self, state),
+            Self::Function(function) => function.hash(state),
+            Self::Builtin(name, _) => name.hash(state),
+            Self::Var(var) => var.name().hash(state),
+            Self::Exception(exception) => std::ptr::hash(exception.identity(), state),
+            // Unordered collections combine their element hashes with a commutative operation,
+            // so sorted and hash collections with the same contents hash the same.
+            Self::Set(set) => hash_unordered(set.iter()).hash(state),
+            Self::SortedSet(set, _) => hash_unordered(set.iter()).hash(state),
+            Self::Map(map) => hash_entries(map.iter()).hash(state),
+            Self::SortedMap(map, _) => hash_entries(map.iter()).hash(state)
         }
     }
-}
\ No newline at end of file
+}
+fn hash_unordered<'a>(nodes: impl Iterator<Item = &'a NodePtr>) -> u32 {
+    nodes.fold(0u32, |sum, node| sum.wrapping_add(hash_of(node)))
+}
+
+fn hash_entries<'a>(entries: impl Iterator<Item = (&'a NodePtr, &'a NodePtr)>) -> u32 {
+    entries.fold(0u32, |sum, (key, value)| {
+        sum.wrapping_add(hash_of(key) ^ hash_of(value).rotate_left(16))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_iter_reports_a_bad_tail() {
+        let list = Rc::new(Node::List(Rc::new(Node::Integer(1)), Rc::new(Node::Integer(2))));
+        let mut iter = list.list_iter();
+        assert_eq!(iter.next().unwrap().unwrap(), Rc::new(Node::Integer(1)));
+        assert_eq!(iter.next().unwrap().unwrap_err().kind().to_string(), "Expected a list but got 2");
+        assert!(iter.next().is_none());
+        assert!(list.list_elements().is_err());
+    }
+
+    #[test]
+    fn node_iter_walks_seq_tails() {
+        let tail = Rc::new(Node::Seq((2..5).map(|int| Rc::new(Node::Integer(int))).collect()));
+        let list = Rc::new(Node::List(Rc::new(Node::Integer(1)), tail));
+        let elements = list.list_elements().unwrap();
+        assert_eq!(elements, (1..5).map(|int| Rc::new(Node::Integer(int))).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,437 @@
+use std::convert::TryFrom;
+use std::cmp::Ordering;
+
+/// Arbitrary precision integer, stored as a sign and a magnitude in base 2^32.
+///
+/// The magnitude is little-endian and never has leading zero digits, so zero is an empty
+/// vector and is never negative. That keeps the derived `Eq` and `Hash` consistent with the
+/// value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    digits: Vec<u32>
+}
+
+const BASE: u64 = 1 << 32;
+
+fn trim(digits: &mut Vec<u32>) {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut digits = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0;
+    for (i, &digit) in long.iter().enumerate() {
+        let sum = u64::from(digit) + u64::from(short.get(i).copied().unwrap_or(0)) + carry;
+        digits.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        digits.push(carry as u32);
+    }
+    digits
+}
+
+/// Subtracts `b` from `a`, which must have the larger magnitude.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut digits = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, &digit) in a.iter().enumerate() {
+        let diff = i64::from(digit) - i64::from(b.get(i).copied().unwrap_or(0)) - borrow;
+        digits.push(diff as u32);
+        borrow = if diff < 0 { 1 } else { 0 };
+    }
+    trim(&mut digits);
+    digits
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut digits = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, &y) in b.iter().enumerate() {
+            let product = u64::from(x) * u64::from(y) + u64::from(digits[i + j]) + carry;
+            digits[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        digits[i + b.len()] = carry as u32;
+    }
+    trim(&mut digits);
+    digits
+}
+
+/// Divides by a single digit, returning the quotient and the remainder.
+fn divrem_digit(a: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+    for (i, &digit) in a.iter().enumerate().rev() {
+        let current = (remainder << 32) | u64::from(digit);
+        quotient[i] = (current / u64::from(divisor)) as u32;
+        remainder = current % u64::from(divisor);
+    }
+    trim(&mut quotient);
+    (quotient, remainder as u32)
+}
+
+fn shl_bits(a: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return a.to_vec();
+    }
+    let mut digits = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0;
+    for &digit in a {
+        digits.push((digit << shift) | carry);
+        carry = digit >> (32 - shift);
+    }
+    digits.push(carry);
+    digits
+}
+
+fn shr_bits(a: &[u32], shift: u32) -> Vec<u32> {
+    let mut digits = a.to_vec();
+    if shift > 0 {
+        for i in 0..digits.len() {
+            let high = digits.get(i + 1).copied().unwrap_or(0);
+            digits[i] = (digits[i] >> shift) | (high << (32 - shift));
+        }
+    }
+    trim(&mut digits);
+    digits
+}
+
+/// Long division of magnitudes, following Knuth's algorithm D.
+fn divrem_magnitude(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_magnitude(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if b.len() == 1 {
+        let (quotient, remainder) = divrem_digit(a, b[0]);
+        let remainder = if remainder == 0 { Vec::new() } else { vec![remainder] };
+        return (quotient, remainder);
+    }
+
+    // Normalizing so that the top digit of the divisor has its high bit set keeps the
+    // estimated quotient digits off by at most two.
+    let shift = b.last().unwrap().leading_zeros();
+    let v = shl_bits(b, shift);
+    let v = &v[..b.len()];
+    let mut u = shl_bits(a, shift);
+    if u.len() == a.len() {
+        u.push(0);
+    }
+    let n = v.len();
+    let m = u.len() - n - 1;
+    let mut quotient = vec![0u32; m + 1];
+
+    for j in (0..=m).rev() {
+        let numerator = (u64::from(u[j + n]) << 32) | u64::from(u[j + n - 1]);
+        let mut estimate = numerator / u64::from(v[n - 1]);
+        let mut rest = numerator % u64::from(v[n - 1]);
+        while estimate >= BASE
+            || u128::from(estimate) * u128::from(v[n - 2]) > (u128::from(rest) << 32) | u128::from(u[j + n - 2])
+        {
+            estimate -= 1;
+            rest += u64::from(v[n - 1]);
+            if rest >= BASE {
+                break;
+            }
+        }
+
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = estimate * u64::from(v[i]) + carry;
+            carry = product >> 32;
+            let diff = i64::from(u[i + j]) - borrow - (product & 0xffff_ffff) as i64;
+            u[i + j] = diff as u32;
+            borrow = if diff < 0 { 1 } else { 0 };
+        }
+        let diff = i64::from(u[j + n]) - borrow - carry as i64;
+        u[j + n] = diff as u32;
+
+        // The estimate was one too large, so the divisor is added back once.
+        if diff < 0 {
+            estimate -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = u64::from(u[i + j]) + u64::from(v[i]) + carry;
+                u[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = estimate as u32;
+    }
+
+    trim(&mut quotient);
+    (quotient, shr_bits(&u[..n], shift))
+}
+
+impl BigInt {
+
+    pub fn zero() -> Self {
+        Self {
+            negative: false,
+            digits: Vec::new()
+        }
+    }
+
+    fn from_parts(negative: bool, mut digits: Vec<u32>) -> Self {
+        trim(&mut digits);
+        Self {
+            negative: negative && !digits.is_empty(),
+            digits
+        }
+    }
+
+    pub fn from_i64(value: i64) -> Self {
+        let magnitude = value.unsigned_abs();
+        Self::from_parts(value < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+
+    /// Returns the value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let magnitude = self.digits.iter().rev().fold(0u64, |acc, &digit| (acc << 32) | u64::from(digit));
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Going through the decimal representation gives a correctly rounded result.
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// Parses an optionally signed string of decimal digits.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.as_bytes().first()? {
+            b'-' => (true, &text[1..]),
+            b'+' => (false, &text[1..]),
+            _ => (false, text)
+        };
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let mut magnitude = Vec::new();
+        for chunk in digits.as_bytes().chunks(9) {
+            let chunk_value = chunk.iter().fold(0u32, |acc, c| acc * 10 + u32::from(c - b'0'));
+            let scale = 10u32.pow(chunk.len() as u32);
+            magnitude = add_magnitude(&mul_magnitude(&magnitude, &[scale]), &[chunk_value]);
+            trim(&mut magnitude);
+        }
+        Some(Self::from_parts(negative, magnitude))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_one(&self) -> bool {
+        !self.negative && self.digits == [1]
+    }
+
+    pub fn neg(&self) -> Self {
+        Self::from_parts(!self.negative, self.digits.clone())
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::from_parts(false, self.digits.clone())
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        if self.negative == other.negative {
+            return Self::from_parts(self.negative, add_magnitude(&self.digits, &other.digits));
+        }
+        match cmp_magnitude(&self.digits, &other.digits) {
+            Ordering::Less => Self::from_parts(other.negative, sub_magnitude(&other.digits, &self.digits)),
+            _ => Self::from_parts(self.negative, sub_magnitude(&self.digits, &other.digits))
+        }
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        Self::from_parts(self.negative != other.negative, mul_magnitude(&self.digits, &other.digits))
+    }
+
+    /// Truncating division, where the remainder has the sign of the dividend.
+    /// Returns `None` when dividing by zero.
+    pub fn div_rem(&self, other: &Self) -> Option<(Self, Self)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = divrem_magnitude(&self.digits, &other.digits);
+        Some((
+            Self::from_parts(self.negative != other.negative, quotient),
+            Self::from_parts(self.negative, remainder)
+        ))
+    }
+
+    pub fn gcd(&self, other: &Self) -> Self {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.is_zero() {
+            let (_, remainder) = a.div_rem(&b).unwrap();
+            a = b;
+            b = remainder;
+        }
+        a
+    }
+
+    pub fn pow10(exponent: u32) -> Self {
+        let mut digits = vec![1];
+        for _ in 0..exponent {
+            digits = mul_magnitude(&digits, &[10]);
+        }
+        Self::from_parts(false, digits)
+    }
+
+    /// Checks if the value is divisible by a small number.
+    pub fn is_multiple_of(&self, divisor: u32) -> bool {
+        divrem_digit(&self.digits, divisor).1 == 0
+    }
+
+    /// Divides by a small number, assuming there is no remainder.
+    pub fn div_exact(&self, divisor: u32) -> Self {
+        Self::from_parts(self.negative, divrem_digit(&self.digits, divisor).0)
+    }
+
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.digits, &other.digits),
+            (true, true) => cmp_magnitude(&other.digits, &self.digits)
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for BigInt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Chunks of nine decimal digits, least significant first.
+        let mut chunks = Vec::new();
+        let mut digits = self.digits.clone();
+        while !digits.is_empty() {
+            let (quotient, remainder) = divrem_digit(&digits, 1_000_000_000);
+            chunks.push(remainder);
+            digits = quotient;
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(text: &str) -> BigInt {
+        BigInt::parse(text).unwrap()
+    }
+
+    fn values() -> Vec<i128> {
+        let mut values = vec![0, 1, 7, 1 << 31, u32::MAX as i128, 1 << 32, (1 << 32) + 1, i64::MAX as i128, 1 << 63, u64::MAX as i128, 1 << 64, 1 << 90];
+        values.extend(values.clone().iter().map(|value| -value));
+        values.push(i64::MIN as i128);
+        values
+    }
+
+    #[test]
+    fn arithmetic_matches_i128() {
+        for &a in &values() {
+            for &b in &values() {
+                let (x, y) = (big(&a.to_string()), big(&b.to_string()));
+                assert_eq!(x.add(&y).to_string(), (a + b).to_string(), "{} + {}", a, b);
+                assert_eq!(x.sub(&y).to_string(), (a - b).to_string(), "{} - {}", a, b);
+                if let Some(product) = a.checked_mul(b) {
+                    assert_eq!(x.mul(&y).to_string(), product.to_string(), "{} * {}", a, b);
+                }
+                match x.div_rem(&y) {
+                    Some((quotient, remainder)) => {
+                        assert_eq!(quotient.to_string(), (a / b).to_string(), "{} / {}", a, b);
+                        assert_eq!(remainder.to_string(), (a % b).to_string(), "{} % {}", a, b);
+                    },
+                    None => assert_eq!(b, 0)
+                }
+                assert_eq!(x.cmp(&y), a.cmp(&b), "{} <=> {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn converts_to_i64_only_when_it_fits() {
+        assert_eq!(BigInt::from_i64(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(BigInt::from_i64(i64::MAX).to_i64(), Some(i64::MAX));
+        assert_eq!(BigInt::from_i64(i64::MAX).add(&BigInt::from_i64(1)).to_i64(), None);
+        assert_eq!(BigInt::from_i64(i64::MIN).sub(&BigInt::from_i64(1)).to_i64(), None);
+        assert_eq!(BigInt::from_i64(-5).to_i64(), Some(-5));
+        assert_eq!(big("0").to_i64(), Some(0));
+    }
+
+    #[test]
+    fn zero_is_never_negative() {
+        assert_eq!(big("-0"), BigInt::zero());
+        assert_eq!(BigInt::from_i64(5).sub(&BigInt::from_i64(5)), BigInt::zero());
+        assert_eq!(BigInt::from_i64(-3).mul(&BigInt::zero()).to_string(), "0");
+    }
+
+    #[test]
+    fn long_division() {
+        let divisor = big("340282366920938463463374607431768211507");
+        let quotient = big("-99999999999999999999999999999999999999999999999999");
+        let remainder = big("-123456789012345678901234567890");
+        let dividend = quotient.mul(&divisor).add(&remainder);
+        assert_eq!(dividend.div_rem(&divisor), Some((quotient, remainder)));
+        assert_eq!(big("99999999999999999999").mul(&big("99999999999999999999")).to_string(), "9999999999999999999800000000000000000001");
+        assert_eq!(BigInt::pow10(30).to_string(), format!("1{}", "0".repeat(30)));
+        assert_eq!(big("123456789012345678901234567890").gcd(&big("-987654321098765432109876543210")).to_string(), "9000000000900000000090");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_digits() {
+        assert_eq!(big("+42").to_string(), "42");
+        assert_eq!(big("000123").to_string(), "123");
+        assert!(BigInt::parse("").is_none());
+        assert!(BigInt::parse("-").is_none());
+        assert!(BigInt::parse("12a").is_none());
+    }
+}
@@ -0,0 +1,227 @@
+use std::convert::TryFrom;
+use std::cmp::Ordering;
+use super::{BigInt, Ratio};
+
+/// Exact decimal number, `unscaled * 10^-scale`.
+///
+/// Like Java's `BigDecimal`, the scale is part of the representation, so `1.5` and `1.50`
+/// print differently, but they compare and hash as equal.
+#[derive(Debug, Clone)]
+pub struct BigDecimal {
+    unscaled: BigInt,
+    scale: u32
+}
+
+impl BigDecimal {
+
+    pub fn new(unscaled: BigInt, scale: u32) -> Self {
+        Self {
+            unscaled,
+            scale
+        }
+    }
+
+    pub fn from_integer(value: BigInt) -> Self {
+        Self::new(value, 0)
+    }
+
+    /// Converts a fraction to a decimal. Returns `None` if the decimal expansion of the
+    /// fraction doesn't terminate, which is when its denominator has prime factors other
+    /// than 2 and 5.
+    pub fn from_ratio(ratio: &Ratio) -> Option<Self> {
+        let mut denom = ratio.denom().clone();
+        let (mut twos, mut fives) = (0u32, 0u32);
+        while denom.is_multiple_of(2) {
+            denom = denom.div_exact(2);
+            twos += 1;
+        }
+        while denom.is_multiple_of(5) {
+            denom = denom.div_exact(5);
+            fives += 1;
+        }
+        if !denom.is_one() {
+            return None;
+        }
+        // numer / (2^twos * 5^fives) = numer * 2^(scale - twos) * 5^(scale - fives) / 10^scale
+        let scale = twos.max(fives);
+        let mut unscaled = ratio.numer().clone();
+        for _ in twos..scale {
+            unscaled = unscaled.mul(&BigInt::from_i64(2));
+        }
+        for _ in fives..scale {
+            unscaled = unscaled.mul(&BigInt::from_i64(5));
+        }
+        Some(Self::new(unscaled, scale))
+    }
+
+    /// Parses a decimal literal like `-1.50` or `1.5e3`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (mantissa, exponent) = match text.find(['e', 'E']) {
+            Some(index) => (&text[..index], text[index + 1..].parse::<i32>().ok()?),
+            None => (text, 0)
+        };
+        let (digits, scale) = match mantissa.find('.') {
+            Some(index) => {
+                let fraction = &mantissa[index + 1..];
+                if fraction.starts_with(['+', '-']) {
+                    return None;
+                }
+                (format!("{}{}", &mantissa[..index], fraction), i32::try_from(fraction.len()).ok()?)
+            },
+            None => (mantissa.to_string(), 0)
+        };
+        let unscaled = BigInt::parse(&digits)?;
+        // Like Java, the scale must fit in 32 bits.
+        let scale = scale.checked_sub(exponent)?;
+        if scale >= 0 {
+            Some(Self::new(unscaled, scale as u32))
+        } else {
+            Some(Self::new(unscaled.mul(&BigInt::pow10(scale.unsigned_abs())), 0))
+        }
+    }
+
+    pub fn to_ratio(&self) -> Ratio {
+        Ratio::new(self.unscaled.clone(), BigInt::pow10(self.scale)).unwrap()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.unscaled.is_zero()
+    }
+
+    fn rescale(&self, scale: u32) -> BigInt {
+        self.unscaled.mul(&BigInt::pow10(scale - self.scale))
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        let scale = self.scale.max(other.scale);
+        Self::new(self.rescale(scale).add(&other.rescale(scale)), scale)
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        let scale = self.scale.max(other.scale);
+        Self::new(self.rescale(scale).sub(&other.rescale(scale)), scale)
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        Self::new(self.unscaled.mul(&other.unscaled), self.scale + other.scale)
+    }
+
+    /// Exact division. Returns `None` if `other` is zero or if the quotient has no
+    /// terminating decimal expansion.
+    pub fn div(&self, other: &Self) -> Option<Self> {
+        Self::from_ratio(&self.to_ratio().div(&other.to_ratio())?)
+    }
+
+    /// Same value with trailing zeros after the decimal point removed.
+    fn normalized(&self) -> Self {
+        let mut value = self.clone();
+        while value.scale > 0 && value.unscaled.is_multiple_of(10) {
+            value.unscaled = value.unscaled.div_exact(10);
+            value.scale -= 1;
+        }
+        value
+    }
+
+}
+
+impl PartialEq for BigDecimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for BigDecimal {}
+
+impl std::hash::Hash for BigDecimal {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let normalized = self.normalized();
+        normalized.unscaled.hash(state);
+        normalized.scale.hash(state);
+    }
+}
+
+impl Ord for BigDecimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        self.rescale(scale).cmp(&other.rescale(scale))
+    }
+}
+
+impl PartialOrd for BigDecimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for BigDecimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = self.unscaled.abs().to_string();
+        let sign = if self.unscaled.is_negative() { "-" } else { "" };
+        let scale = self.scale as usize;
+        if scale == 0 {
+            write!(f, "{}{}", sign, digits)
+        } else if digits.len() > scale {
+            let (int, fraction) = digits.split_at(digits.len() - scale);
+            write!(f, "{}{}.{}", sign, int, fraction)
+        } else {
+            write!(f, "{}0.{}{}", sign, "0".repeat(scale - digits.len()), digits)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(text: &str) -> BigDecimal {
+        BigDecimal::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parse_keeps_the_scale() {
+        assert_eq!(decimal("1.50").to_string(), "1.50");
+        assert_eq!(decimal("-0.05").to_string(), "-0.05");
+        assert_eq!(decimal("1.5e3").to_string(), "1500");
+        assert_eq!(decimal("15e-3").to_string(), "0.015");
+        assert!(BigDecimal::parse("1.-5").is_none());
+        assert!(BigDecimal::parse("1.5x").is_none());
+        assert!(BigDecimal::parse("1.5e-2147483647").is_none());
+        assert!(BigDecimal::parse("1e-2147483648").is_none());
+    }
+
+    #[test]
+    fn equal_regardless_of_scale() {
+        assert_eq!(decimal("1.5"), decimal("1.500"));
+        assert_eq!(hash_of(&decimal("1.5")), hash_of(&decimal("1.500")));
+        assert!(decimal("1.49") < decimal("1.5"));
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(decimal("1.10").add(&decimal("2.005")).to_string(), "3.105");
+        assert_eq!(decimal("1.10").sub(&decimal("2.005")).to_string(), "-0.905");
+        assert_eq!(decimal("1.10").mul(&decimal("2")).to_string(), "2.20");
+        assert_eq!(decimal("1").div(&decimal("8")).map(|quotient| quotient.to_string()), Some("0.125".to_string()));
+        assert!(decimal("1").div(&decimal("3")).is_none());
+        assert!(decimal("1").div(&decimal("0")).is_none());
+    }
+
+    #[test]
+    fn from_ratio_needs_a_terminating_expansion() {
+        let ratio = |numer, denom| Ratio::new(BigInt::from_i64(numer), BigInt::from_i64(denom)).unwrap();
+        assert_eq!(BigDecimal::from_ratio(&ratio(3, 40)).map(|decimal| decimal.to_string()), Some("0.075".to_string()));
+        assert!(BigDecimal::from_ratio(&ratio(1, 6)).is_none());
+        assert_eq!(decimal("0.075").to_ratio(), ratio(3, 40));
+    }
+
+    fn hash_of(value: &BigDecimal) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+}
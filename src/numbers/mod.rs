@@ -0,0 +1,416 @@
+mod bigint;
+mod ratio;
+mod decimal;
+
+pub use bigint::BigInt;
+pub use ratio::Ratio;
+pub use decimal::BigDecimal;
+
+use std::cmp::Ordering;
+
+/// Any of the numeric types of the language.
+///
+/// When an operation mixes types, both operands are converted to the widest one in the
+/// order `Integer < BigInt < Ratio < Decimal < Float`, so exact values only turn into
+/// floats when a float is involved. Operations on integers are checked: they fail on
+/// overflow unless promotion to `BigInt` is asked for.
+#[derive(Debug, Clone)]
+pub enum Number {
+    Integer(i64),
+    BigInt(BigInt),
+    Ratio(Ratio),
+    Decimal(BigDecimal),
+    Float(f64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticError {
+    Overflow,
+    DivideByZero,
+    NonTerminatingDecimal
+}
+
+impl std::fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Overflow => write!(f, "integer overflow"),
+            Self::DivideByZero => write!(f, "divide by zero"),
+            Self::NonTerminatingDecimal => write!(f, "non-terminating decimal expansion, no exact decimal result")
+        }
+    }
+}
+
+pub type ArithmeticResult = Result<Number, ArithmeticError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Category {
+    Integer,
+    BigInt,
+    Ratio,
+    Decimal,
+    Float
+}
+
+impl Number {
+
+    fn category(&self) -> Category {
+        match self {
+            Self::Integer(_) => Category::Integer,
+            Self::BigInt(_) => Category::BigInt,
+            Self::Ratio(_) => Category::Ratio,
+            Self::Decimal(_) => Category::Decimal,
+            Self::Float(_) => Category::Float
+        }
+    }
+
+    /// Only valid for integers.
+    fn to_bigint(&self) -> BigInt {
+        match self {
+            Self::Integer(int) => BigInt::from_i64(*int),
+            Self::BigInt(int) => int.clone(),
+            _ => unreachable!("Converting a non-integer to a BigInt.")
+        }
+    }
+
+    /// Only valid for exact numbers.
+    fn to_ratio(&self) -> Ratio {
+        match self {
+            Self::Ratio(ratio) => ratio.clone(),
+            Self::Decimal(decimal) => decimal.to_ratio(),
+            _ => Ratio::from_integer(self.to_bigint())
+        }
+    }
+
+    /// Only valid for exact numbers. Fails for ratios that have no exact decimal value.
+    fn to_decimal(&self) -> Result<BigDecimal, ArithmeticError> {
+        match self {
+            Self::Decimal(decimal) => Ok(decimal.clone()),
+            Self::Ratio(ratio) => BigDecimal::from_ratio(ratio).ok_or(ArithmeticError::NonTerminatingDecimal),
+            _ => Ok(BigDecimal::from_integer(self.to_bigint()))
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Self::Integer(int) => *int as f64,
+            Self::BigInt(int) => int.to_f64(),
+            Self::Ratio(ratio) => ratio.to_f64(),
+            Self::Decimal(decimal) => decimal.to_f64(),
+            Self::Float(float) => *float
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::Integer(int) => *int == 0,
+            Self::BigInt(int) => int.is_zero(),
+            Self::Ratio(_) => false,
+            Self::Decimal(decimal) => decimal.is_zero(),
+            Self::Float(float) => *float == 0.0
+        }
+    }
+
+    /// Sign of the number as -1, 0 or 1. `None` for NaN.
+    pub fn signum(&self) -> Option<Ordering> {
+        compare(self, &Number::Integer(0))
+    }
+
+    /// Parses a number literal: integers, `N` big integers, ratios, floats and `M` decimals.
+    /// Integers too large for 64 bits are read as big integers.
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some(int) = text.strip_suffix('N') {
+            return BigInt::parse(int).map(Self::BigInt);
+        }
+        if let Some(decimal) = text.strip_suffix('M') {
+            return BigDecimal::parse(decimal).map(Self::Decimal);
+        }
+        if let Some((numer, denom)) = text.split_once('/') {
+            if !denom.bytes().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            return Ratio::new(BigInt::parse(numer)?, BigInt::parse(denom)?).map(|ratio| {
+                if ratio.is_integer() {
+                    Self::demote(ratio.numer().clone())
+                } else {
+                    Self::Ratio(ratio)
+                }
+            });
+        }
+        if text.contains(['.', 'e', 'E']) {
+            return text.parse().ok().map(Self::Float);
+        }
+        match text.parse() {
+            Ok(int) => Some(Self::Integer(int)),
+            Err(_) => BigInt::parse(text).map(Self::BigInt)
+        }
+    }
+
+    /// Turns a big integer into a plain one if it fits.
+    fn demote(int: BigInt) -> Self {
+        match int.to_i64() {
+            Some(int) => Self::Integer(int),
+            None => Self::BigInt(int)
+        }
+    }
+
+    /// Ratio results that are whole numbers become big integers.
+    fn from_ratio(ratio: Ratio) -> Self {
+        if ratio.is_integer() {
+            Self::BigInt(ratio.numer().clone())
+        } else {
+            Self::Ratio(ratio)
+        }
+    }
+
+}
+
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Integer(int) => write!(f, "{}", int),
+            Self::BigInt(int) => write!(f, "{}N", int),
+            Self::Ratio(ratio) => write!(f, "{}", ratio),
+            Self::Decimal(decimal) => write!(f, "{}M", decimal),
+            Self::Float(float) if float.is_nan() => write!(f, "##NaN"),
+            Self::Float(float) if float.is_infinite() => {
+                write!(f, "{}", if *float > 0.0 { "##Inf" } else { "##-Inf" })
+            },
+            Self::Float(float) => write!(f, "{:?}", float)
+        }
+    }
+}
+
+/// Operation on two numbers, with one implementation per numeric type.
+struct Operation {
+    integers: fn(i64, i64) -> Option<i64>,
+    bigints: fn(&BigInt, &BigInt) -> BigInt,
+    ratios: fn(&Ratio, &Ratio) -> Ratio,
+    decimals: fn(&BigDecimal, &BigDecimal) -> BigDecimal,
+    floats: fn(f64, f64) -> f64
+}
+
+fn apply(operation: &Operation, a: &Number, b: &Number, promote: bool) -> ArithmeticResult {
+    match a.category().max(b.category()) {
+        Category::Integer => {
+            let (x, y) = match (a, b) {
+                (Number::Integer(x), Number::Integer(y)) => (*x, *y),
+                _ => unreachable!()
+            };
+            match (operation.integers)(x, y) {
+                Some(result) => Ok(Number::Integer(result)),
+                None if promote => Ok(Number::BigInt((operation.bigints)(&a.to_bigint(), &b.to_bigint()))),
+                None => Err(ArithmeticError::Overflow)
+            }
+        },
+        Category::BigInt => Ok(Number::BigInt((operation.bigints)(&a.to_bigint(), &b.to_bigint()))),
+        Category::Ratio => Ok(Number::from_ratio((operation.ratios)(&a.to_ratio(), &b.to_ratio()))),
+        Category::Decimal => Ok(Number::Decimal((operation.decimals)(&a.to_decimal()?, &b.to_decimal()?))),
+        Category::Float => Ok(Number::Float((operation.floats)(a.to_f64(), b.to_f64())))
+    }
+}
+
+const ADD: Operation = Operation {
+    integers: i64::checked_add,
+    bigints: BigInt::add,
+    ratios: Ratio::add,
+    decimals: BigDecimal::add,
+    floats: |a, b| a + b
+};
+
+const SUB: Operation = Operation {
+    integers: i64::checked_sub,
+    bigints: BigInt::sub,
+    ratios: Ratio::sub,
+    decimals: BigDecimal::sub,
+    floats: |a, b| a - b
+};
+
+const MUL: Operation = Operation {
+    integers: i64::checked_mul,
+    bigints: BigInt::mul,
+    ratios: Ratio::mul,
+    decimals: BigDecimal::mul,
+    floats: |a, b| a * b
+};
+
+/// Adds two numbers. With `promote`, integer overflow gives a big integer instead of an error.
+pub fn add(a: &Number, b: &Number, promote: bool) -> ArithmeticResult {
+    apply(&ADD, a, b, promote)
+}
+
+pub fn sub(a: &Number, b: &Number, promote: bool) -> ArithmeticResult {
+    apply(&SUB, a, b, promote)
+}
+
+pub fn mul(a: &Number, b: &Number, promote: bool) -> ArithmeticResult {
+    apply(&MUL, a, b, promote)
+}
+
+/// Divides two numbers. Dividing integers gives a ratio unless the division is exact.
+pub fn div(a: &Number, b: &Number) -> ArithmeticResult {
+    let category = a.category().max(b.category());
+    if category != Category::Float && b.is_zero() {
+        return Err(ArithmeticError::DivideByZero);
+    }
+    match category {
+        Category::Integer => match (a, b) {
+            (Number::Integer(x), Number::Integer(y)) if x.checked_rem(*y) == Some(0) => {
+                Ok(Number::Integer(x / y))
+            },
+            _ => div(&Number::BigInt(a.to_bigint()), b)
+        },
+        Category::BigInt | Category::Ratio => {
+            Ok(Number::from_ratio(a.to_ratio().div(&b.to_ratio()).unwrap()))
+        },
+        Category::Decimal => {
+            a.to_decimal()?.div(&b.to_decimal()?).map(Number::Decimal).ok_or(ArithmeticError::NonTerminatingDecimal)
+        },
+        Category::Float => Ok(Number::Float(a.to_f64() / b.to_f64()))
+    }
+}
+
+/// Quotient of the division rounded towards zero.
+pub fn quot(a: &Number, b: &Number) -> ArithmeticResult {
+    let category = a.category().max(b.category());
+    if b.is_zero() {
+        return Err(ArithmeticError::DivideByZero);
+    }
+    match (category, a, b) {
+        (Category::Integer, Number::Integer(x), Number::Integer(y)) => {
+            x.checked_div(*y).map(Number::Integer).ok_or(ArithmeticError::Overflow)
+        },
+        (Category::Float, _, _) => Ok(Number::Float((a.to_f64() / b.to_f64()).trunc())),
+        _ => {
+            let ratio = a.to_ratio().div(&b.to_ratio()).unwrap();
+            let quotient = ratio.numer().div_rem(ratio.denom()).unwrap().0;
+            match category {
+                Category::Decimal => Ok(Number::Decimal(BigDecimal::from_integer(quotient))),
+                _ => Ok(Number::BigInt(quotient))
+            }
+        }
+    }
+}
+
+/// Remainder of `quot`, with the sign of the dividend.
+pub fn rem(a: &Number, b: &Number) -> ArithmeticResult {
+    match (a, b) {
+        (Number::Integer(x), Number::Integer(y)) if *y != 0 => Ok(Number::Integer(x.checked_rem(*y).unwrap_or(0))),
+        _ if a.category().max(b.category()) == Category::Float && !b.is_zero() => {
+            Ok(Number::Float(a.to_f64() % b.to_f64()))
+        },
+        _ => sub(a, &mul(&quot(a, b)?, b, true)?, true)
+    }
+}
+
+/// Remainder of the division rounded towards negative infinity, with the sign of the divisor.
+pub fn modulo(a: &Number, b: &Number) -> ArithmeticResult {
+    let remainder = rem(a, b)?;
+    match (remainder.signum(), b.signum()) {
+        (Some(Ordering::Less), Some(Ordering::Greater)) | (Some(Ordering::Greater), Some(Ordering::Less)) => {
+            add(&remainder, b, true)
+        },
+        _ => Ok(remainder)
+    }
+}
+
+pub fn negate(a: &Number, promote: bool) -> ArithmeticResult {
+    sub(&Number::Integer(0), a, promote)
+}
+
+/// Numeric ordering across all types. Only NaN can't be compared.
+pub fn compare(a: &Number, b: &Number) -> Option<Ordering> {
+    if let (Number::Integer(x), Number::Integer(y)) = (a, b) {
+        return Some(x.cmp(y));
+    }
+    match a.category().max(b.category()) {
+        Category::Integer | Category::BigInt => Some(a.to_bigint().cmp(&b.to_bigint())),
+        Category::Ratio | Category::Decimal => Some(a.to_ratio().cmp(&b.to_ratio())),
+        Category::Float => a.to_f64().partial_cmp(&b.to_f64())
+    }
+}
+
+/// Equality used by `=`: numbers are only equal within the same group of integers, ratios,
+/// decimals or floats, so `(= 1 1N)` is true but `(= 1 1.0)` is not.
+pub fn equals(a: &Number, b: &Number) -> bool {
+    let group = |category| match category {
+        Category::Integer | Category::BigInt => Category::Integer,
+        category => category
+    };
+    group(a.category()) == group(b.category()) && compare(a, b) == Some(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(text: &str) -> Number {
+        Number::parse(text).unwrap()
+    }
+
+    fn show(result: ArithmeticResult) -> String {
+        match result {
+            Ok(number) => number.to_string(),
+            Err(err) => err.to_string()
+        }
+    }
+
+    #[test]
+    fn integer_overflow_fails_unless_promoted() {
+        let (max, min, one) = (Number::Integer(i64::MAX), Number::Integer(i64::MIN), Number::Integer(1));
+        assert_eq!(show(add(&max, &one, false)), "integer overflow");
+        assert_eq!(show(add(&max, &one, true)), "9223372036854775808N");
+        assert_eq!(show(sub(&min, &one, false)), "integer overflow");
+        assert_eq!(show(sub(&min, &one, true)), "-9223372036854775809N");
+        assert_eq!(show(mul(&max, &Number::Integer(2), true)), "18446744073709551614N");
+        assert_eq!(show(negate(&min, false)), "integer overflow");
+        assert_eq!(show(negate(&min, true)), "9223372036854775808N");
+        assert_eq!(show(quot(&min, &Number::Integer(-1))), "integer overflow");
+        assert_eq!(show(rem(&min, &Number::Integer(-1))), "0");
+        assert_eq!(show(add(&Number::Integer(2), &one, false)), "3");
+    }
+
+    #[test]
+    fn contagion() {
+        assert_eq!(show(add(&number("1"), &number("1N"), false)), "2N");
+        assert_eq!(show(add(&number("1N"), &number("1/2"), false)), "3/2");
+        assert_eq!(show(add(&number("1/2"), &number("1.5M"), false)), "2.0M");
+        assert_eq!(show(add(&number("1.5M"), &number("1"), false)), "2.5M");
+        assert_eq!(show(add(&number("1/2"), &number("0.5"), false)), "1.0");
+        assert_eq!(show(add(&number("1.5M"), &number("0.5"), false)), "2.0");
+        assert_eq!(show(add(&number("1/3"), &number("1.5M"), false)), "non-terminating decimal expansion, no exact decimal result");
+        assert_eq!(show(mul(&number("1/2"), &number("2"), false)), "1N");
+    }
+
+    #[test]
+    fn division() {
+        assert_eq!(show(div(&number("4"), &number("2"))), "2");
+        assert_eq!(show(div(&number("1"), &number("3"))), "1/3");
+        assert_eq!(show(div(&number("-9223372036854775808"), &number("-1"))), "9223372036854775808N");
+        assert_eq!(show(div(&number("1"), &number("0"))), "divide by zero");
+        assert_eq!(show(div(&number("1.0"), &number("0"))), "##Inf");
+        assert_eq!(show(div(&number("1M"), &number("8"))), "0.125M");
+        assert_eq!(show(div(&number("1M"), &number("3"))), "non-terminating decimal expansion, no exact decimal result");
+        assert_eq!(show(quot(&number("-7/2"), &number("1"))), "-3N");
+        assert_eq!(show(modulo(&number("-7"), &number("2"))), "1");
+        assert_eq!(show(rem(&number("-7"), &number("2"))), "-1");
+    }
+
+    #[test]
+    fn parse_literals() {
+        assert_eq!(number("9223372036854775808").to_string(), "9223372036854775808N");
+        assert_eq!(number("4/2").to_string(), "2");
+        assert_eq!(number("-1/3").to_string(), "-1/3");
+        assert!(Number::parse("1/0").is_none());
+        assert!(Number::parse("1/-2").is_none());
+    }
+
+    #[test]
+    fn equality_and_ordering() {
+        assert!(equals(&number("1"), &number("1N")));
+        assert!(!equals(&number("1"), &number("1.0")));
+        assert!(!equals(&number("1/2"), &number("0.5")));
+        assert!(equals(&number("1.5M"), &number("1.50M")));
+        assert_eq!(compare(&number("1/3"), &number("0.3M")), Some(Ordering::Greater));
+        assert_eq!(compare(&Number::Float(f64::NAN), &number("1")), None);
+    }
+}
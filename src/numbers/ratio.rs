@@ -0,0 +1,123 @@
+use std::cmp::Ordering;
+use super::BigInt;
+
+/// Exact fraction, always kept in lowest terms with a positive denominator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ratio {
+    numer: BigInt,
+    denom: BigInt
+}
+
+impl Ratio {
+
+    /// Builds the fraction `numer / denom` in lowest terms. Returns `None` if `denom` is zero.
+    pub fn new(numer: BigInt, denom: BigInt) -> Option<Self> {
+        if denom.is_zero() {
+            return None;
+        }
+        let gcd = numer.gcd(&denom);
+        let (mut numer, mut denom) = if gcd.is_one() || gcd.is_zero() {
+            (numer, denom)
+        } else {
+            (numer.div_rem(&gcd).unwrap().0, denom.div_rem(&gcd).unwrap().0)
+        };
+        if denom.is_negative() {
+            numer = numer.neg();
+            denom = denom.neg();
+        }
+        Some(Self {
+            numer,
+            denom
+        })
+    }
+
+    pub fn from_integer(value: BigInt) -> Self {
+        Self {
+            numer: value,
+            denom: BigInt::from_i64(1)
+        }
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.numer
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.denom
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.denom.is_one()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.numer.to_f64() / self.denom.to_f64()
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        let numer = self.numer.mul(&other.denom).add(&other.numer.mul(&self.denom));
+        Self::new(numer, self.denom.mul(&other.denom)).unwrap()
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        let numer = self.numer.mul(&other.denom).sub(&other.numer.mul(&self.denom));
+        Self::new(numer, self.denom.mul(&other.denom)).unwrap()
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        Self::new(self.numer.mul(&other.numer), self.denom.mul(&other.denom)).unwrap()
+    }
+
+    /// Returns `None` when dividing by zero.
+    pub fn div(&self, other: &Self) -> Option<Self> {
+        Self::new(self.numer.mul(&other.denom), self.denom.mul(&other.numer))
+    }
+
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.numer.mul(&other.denom).cmp(&other.numer.mul(&self.denom))
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for Ratio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.numer, self.denom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(numer: i64, denom: i64) -> Ratio {
+        Ratio::new(BigInt::from_i64(numer), BigInt::from_i64(denom)).unwrap()
+    }
+
+    #[test]
+    fn kept_in_lowest_terms() {
+        assert_eq!(ratio(2, 4), ratio(1, 2));
+        assert_eq!(ratio(3, -6).to_string(), "-1/2");
+        assert_eq!(ratio(-3, -6).to_string(), "1/2");
+        assert_eq!(ratio(0, 5), Ratio::from_integer(BigInt::zero()));
+        assert!(ratio(6, 3).is_integer());
+        assert!(Ratio::new(BigInt::from_i64(1), BigInt::zero()).is_none());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(ratio(1, 3).add(&ratio(1, 6)), ratio(1, 2));
+        assert_eq!(ratio(1, 3).sub(&ratio(1, 2)), ratio(-1, 6));
+        assert_eq!(ratio(2, 3).mul(&ratio(3, 4)), ratio(1, 2));
+        assert_eq!(ratio(2, 3).div(&ratio(-4, 3)), Some(ratio(-1, 2)));
+        assert_eq!(ratio(2, 3).div(&ratio(0, 1)), None);
+        assert!(ratio(1, 3) < ratio(1, 2) && ratio(-1, 2) < ratio(-1, 3));
+    }
+}
@@ -1,7 +1,10 @@
 mod tokens;
+mod spans;
+#[allow(clippy::module_inception)]
 mod parser;
 mod tokenizer;
 
 pub use parser::*;
+pub use spans::*;
 pub use tokenizer::*;
 pub use tokens::*;
\ No newline at end of file
@@ -1,30 +1,65 @@
-use crate::nodes::{Node, NodePtr};
+use crate::nodes::{Node, NodePtr, list_from};
+use crate::intern::Symbol;
 use super::tokens::Token;
 use std::iter::Peekable;
 use std::slice::Iter;
 use std::rc::Rc;
 use crate::parser::tokens::TokenKind;
-use crate::parser::TokenPos;
+use crate::parser::{TokenPos, Span};
+use super::spans::{set_span, span_of, set_elements};
+use crate::collections::{PersistentVector, PersistentHashMap, PersistentHashSet};
 
 #[derive(Debug)]
 pub struct ParseError {
     message: String,
-    pos: Option<TokenPos>
+    pos: Option<TokenPos>,
+    /// Explains what's wrong at `pos`, for diagnostics.
+    label: Option<String>,
+    /// Other positions related to the error, like where an unclosed list was opened.
+    secondary: Vec<(TokenPos, String)>
 }
 
 impl ParseError {
     fn new(message: &str, pos: Option<TokenPos>) -> Self {
         Self {
             message: String::from(message),
-            pos
+            pos,
+            label: None,
+            secondary: Vec::new()
         }
     }
+
+    fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    fn with_secondary(mut self, pos: TokenPos, label: &str) -> Self {
+        self.secondary.push((pos, label.to_string()));
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn pos(&self) -> Option<TokenPos> {
+        self.pos
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn secondary(&self) -> &[(TokenPos, String)] {
+        &self.secondary
+    }
 }
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(pos) = self.pos {
-            write!(f, "Parse Error ({}:{}) {}", pos.line, pos.column, self.message)
+            write!(f, "Parse Error ({}) {}", pos, self.message)
         } else {
             write!(f, "Parse Error: {}", self.message)
         }
@@ -42,58 +77,55 @@ fn ptr(node: Node) -> NodePtr {
     Rc::new(node)
 }
 
-pub fn parse_file(tokens: &mut TokenIter) -> ParseResult<NodePtr> {
+/// Remembers that `node` was read from `start` up to `end`.
+fn spanned(node: NodePtr, start: TokenPos, end: TokenPos) -> NodePtr {
+    set_span(&node, Span { start, end });
+    node
+}
+
+/// Parses all the forms in `tokens`. Auto-resolved keywords like `::id` are read in `namespace`.
+pub fn parse_file(tokens: &mut TokenIter, namespace: Symbol) -> ParseResult<NodePtr> {
     let node = match tokens.peek() {
         Some(_) => {
-            let left = parse_expr(tokens)?;
-            let right = parse_file(tokens)?;
-            Node::List(left, right, false)
+            let left = parse_expr(tokens, namespace)?;
+            let right = parse_file(tokens, namespace)?;
+            Node::List(left, right)
         },
         None => Node::Nil
     };
     Ok(ptr(node))
 }
 
-pub fn parse_expr(tokens: &mut TokenIter) -> ParseResult<NodePtr> {
+pub fn parse_expr(tokens: &mut TokenIter, namespace: Symbol) -> ParseResult<NodePtr> {
 
     if let Some(token) = tokens.next() {
         let node = match token {
             Token(TokenKind::LParen, pos) => {
-                parse_list(tokens, pos, false)?
+                parse_list(tokens, pos, namespace)?
             },
             Token(TokenKind::LBrack, pos) => {
-                parse_vector(tokens, pos)?
-            },
-            Token(TokenKind::Hash, _) => {
-                parse_set(tokens)?
-            },
-            Token(TokenKind::LCurl, _) => {
-                parse_map(tokens)?
-            },
-            Token(TokenKind::SingleQuote, _) => {
-                match tokens.next() {
-                    Some(Token(TokenKind::LParen, pos)) => {
-                        parse_list(tokens, pos, true)?
-                    },
-                    Some(Token(kind, pos)) => {
-                        return Err(ParseError::new(
-                            &format!("Expected a list but got {}", kind),
-                            Some(pos.clone())
-                        ))
-                    },
-                    None => {
-                        return Err(ParseError::new(
-                            "Expected a list but got nothing.",
-                            None
-                        ))
-                    }
-                }
-            }
-            Token(TokenKind::Ident(ident), _) => {
-                ptr(Node::Ident(ident.clone()))
+                parse_vector(tokens, pos, namespace)?
+            },
+            Token(TokenKind::Hash, pos) => {
+                parse_dispatch(tokens, pos, namespace)?
             },
-            Token(TokenKind::Symbol(symbol), _) => {
-                ptr(Node::Symbol(symbol.clone()))
+            Token(TokenKind::LCurl, pos) => {
+                parse_map(tokens, pos, namespace)?
+            },
+            Token(TokenKind::SingleQuote, pos) => wrap_form(Symbol::QUOTE, parse_quoted(tokens, pos, namespace)?, pos),
+            Token(TokenKind::Backquote, pos) => wrap_form(Symbol::SYNTAX_QUOTE, parse_quoted(tokens, pos, namespace)?, pos),
+            Token(TokenKind::Tilde, pos) => wrap_form(Symbol::UNQUOTE, parse_quoted(tokens, pos, namespace)?, pos),
+            Token(TokenKind::TildeAt, pos) => wrap_form(Symbol::UNQUOTE_SPLICING, parse_quoted(tokens, pos, namespace)?, pos),
+            Token(TokenKind::Keyword(keyword), pos) => {
+                ptr(Node::Keyword(read_keyword(keyword, namespace, pos)?))
+            },
+            Token(TokenKind::Symbol(symbol), pos) => {
+                match symbol.as_str() {
+                    "nil" => ptr(Node::Nil),
+                    "true" => ptr(Node::Bool(true)),
+                    "false" => ptr(Node::Bool(false)),
+                    _ => spanned(ptr(Node::Symbol(Symbol::intern(symbol))), *pos, pos.advance(symbol.chars().count()))
+                }
             },
             Token(TokenKind::String(str), _) => {
                 ptr(Node::String(str.clone()))
@@ -104,13 +136,22 @@ pub fn parse_expr(tokens: &mut TokenIter) -> ParseResult<NodePtr> {
             Token(TokenKind::Integer(int), _) => {
                 ptr(Node::Integer(*int))
             },
+            Token(TokenKind::BigInt(int), _) => {
+                ptr(Node::BigInt(int.clone()))
+            },
+            Token(TokenKind::Ratio(ratio), _) => {
+                ptr(Node::Ratio(ratio.clone()))
+            },
+            Token(TokenKind::Decimal(decimal), _) => {
+                ptr(Node::Decimal(decimal.clone()))
+            },
             Token(TokenKind::Float(float), _) => {
                 ptr(Node::Float(*float))
             },
             Token(token, pos) => {
                 return Err(ParseError::new(
                     &format!("Unexpected token {}", token),
-                    Some(pos.clone())
+                    Some(*pos)
                 ))
             }
         };
@@ -121,46 +162,165 @@ pub fn parse_expr(tokens: &mut TokenIter) -> ParseResult<NodePtr> {
 
 }
 
-fn parse_list(tokens: &mut TokenIter, pos: &TokenPos, literal: bool) -> ParseResult<NodePtr> {
-    let node = match tokens.peek() {
-        Some(Token(TokenKind::RParen, _)) => {
-            tokens.next().unwrap();
-            Node::Nil
-        },
-        Some(_) => {
-            let left = parse_expr(tokens)?;
-            let right = parse_list(tokens, pos, false)?;
-            Node::List(left, right, literal)
-        },
-        None => return Err(ParseError::new("Unexpected End of Token List while parsing List", Some(pos.clone())))
-    };
-    Ok(ptr(node))
+/// Parses the form following a reader shorthand like the quote in `'x`. The syntax quote
+/// `` `x ``, `~x` and `~@x` are read as `(syntax-quote x)`, `(unquote x)` and
+/// `(unquote-splicing x)`.
+fn parse_quoted(tokens: &mut TokenIter, pos: &TokenPos, namespace: Symbol) -> ParseResult<NodePtr> {
+    match tokens.peek() {
+        Some(_) => parse_expr(tokens, namespace),
+        None => Err(ParseError::new("Expected a form after a quote but got nothing.", Some(*pos)))
+    }
+}
+
+fn parse_list(tokens: &mut TokenIter, pos: &TokenPos, namespace: Symbol) -> ParseResult<NodePtr> {
+    let (nodes, end) = parse_until(tokens, pos, |kind| matches!(kind, TokenKind::RParen), "List", namespace)?;
+    Ok(spanned(list_from(nodes), *pos, end))
 }
 
-fn parse_vector(tokens: &mut TokenIter, pos: &TokenPos) -> ParseResult<NodePtr> {
-    let mut vector = Vec::new();
-    loop {
+/// Parses forms until the closing token of a collection, consuming it. Also returns the
+/// position following the closing token.
+fn parse_until(
+    tokens: &mut TokenIter,
+    pos: &TokenPos,
+    closing: fn(&TokenKind) -> bool,
+    what: &str,
+    namespace: Symbol
+) -> ParseResult<(Vec<NodePtr>, TokenPos)> {
+    let mut nodes = Vec::new();
+    let end = loop {
         match tokens.peek() {
-            Some(Token(TokenKind::RBrack, _)) => {
+            Some(Token(kind, end)) if closing(kind) => {
                 tokens.next().unwrap();
-                break;
+                break end.advance(1);
+            },
+            Some(Token(kind @ (TokenKind::RParen | TokenKind::RBrack | TokenKind::RCurl), other)) => {
+                return Err(ParseError::new(&format!("Unexpected token {} while parsing {}", kind, what), Some(*other))
+                    .with_label("doesn't close the collection")
+                    .with_secondary(*pos, &format!("{} opened here", what)))
             },
             Some(_) => {
-                let node = parse_expr(tokens)?;
-                vector.push(node);
+                nodes.push(parse_expr(tokens, namespace)?);
             },
             None => {
-                return Err(ParseError::new("Unexpected End of Token List while parsing Vector", Some(pos.clone())))
+                return Err(ParseError::new(&format!("Unexpected End of Token List while parsing {}", what), Some(*pos))
+                    .with_label(&format!("{} opened here is never closed", what)))
             }
         }
+    };
+    Ok((nodes, end))
+}
+
+fn parse_vector(tokens: &mut TokenIter, pos: &TokenPos, namespace: Symbol) -> ParseResult<NodePtr> {
+    let (nodes, end) = parse_until(tokens, pos, |kind| matches!(kind, TokenKind::RBrack), "Vector", namespace)?;
+    Ok(spanned(ptr(Node::Vector(nodes.into_iter().collect::<PersistentVector<NodePtr>>())), *pos, end))
+}
+
+/// Builds the call `(head form)`, for reader shorthands like `'x` for `(quote x)` starting
+/// at `pos`.
+fn wrap_form(head: Symbol, form: NodePtr, pos: &TokenPos) -> NodePtr {
+    let end = span_of(&form).map_or(*pos, |span| span.end);
+    let args = ptr(Node::List(form, ptr(Node::Nil)));
+    spanned(ptr(Node::List(ptr(Node::Symbol(head)), args)), *pos, end)
+}
+
+/// Reads the text of a keyword token. `:id` has no namespace, `:user/id` is qualified with
+/// `user`, and `::id` is qualified with the current namespace.
+fn read_keyword(text: &str, namespace: Symbol, pos: &TokenPos) -> ParseResult<Symbol> {
+    let invalid = || ParseError::new(&format!("Invalid keyword: {}", text), Some(*pos));
+    match text.strip_prefix("::") {
+        Some(name) if name.is_empty() || name.starts_with(':') => Err(invalid()),
+        Some(name) => match Symbol::intern(name).split() {
+            (None, name) => Ok(Symbol::qualified(&namespace.name(), &name.name())),
+            (Some(alias), _) => Err(ParseError::new(&format!("Unknown namespace alias {} in {}", alias, text), Some(*pos)))
+        },
+        None => match &text[1..] {
+            "" => Err(invalid()),
+            name if name.ends_with('/') || name.starts_with(':') => Err(invalid()),
+            name => Ok(Symbol::intern(name))
+        }
+    }
+}
+
+/// Parses the forms that start with '#': sets, namespaced maps like `#:user{:id 1}`, var
+/// references like `#'x` and tagged literals like `#queue [1 2]`. Errors about a whole
+/// literal, like a missing closing brace, point at its `#`.
+fn parse_dispatch(tokens: &mut TokenIter, pos: &TokenPos, namespace: Symbol) -> ParseResult<NodePtr> {
+    match tokens.next() {
+        Some(Token(TokenKind::SingleQuote, _)) => Ok(wrap_form(Symbol::VAR, parse_expr(tokens, namespace)?, pos)),
+        Some(Token(TokenKind::Keyword(prefix), prefix_pos)) => {
+            let map_namespace = match prefix.as_str() {
+                "::" => namespace,
+                _ => match read_keyword(prefix, namespace, prefix_pos)?.split() {
+                    (None, map_namespace) => map_namespace,
+                    _ => return Err(ParseError::new(&format!("Invalid namespaced map prefix: {}", prefix), Some(*prefix_pos)))
+                }
+            };
+            match tokens.next() {
+                Some(Token(TokenKind::LCurl, _)) => {
+                    let (nodes, end) = parse_until(tokens, pos, |kind| matches!(kind, TokenKind::RCurl), "Map", namespace)?;
+                    // Keys without a namespace get the map's one, and `:_/id` opts out of it.
+                    let nodes = nodes.into_iter().enumerate().map(|(index, node)| match node.as_ref() {
+                        Node::Keyword(keyword) if index.is_multiple_of(2) => match keyword.split() {
+                            (None, name) => ptr(Node::Keyword(Symbol::qualified(&map_namespace.name(), &name.name()))),
+                            (Some(key_namespace), name) if key_namespace.name().as_ref() == "_" => ptr(Node::Keyword(name)),
+                            _ => node
+                        },
+                        _ => node
+                    }).collect();
+                    Ok(spanned(build_map(nodes, pos)?, *pos, end))
+                },
+                _ => Err(ParseError::new(&format!("Expected a map after #{}", prefix), Some(*prefix_pos)))
+            }
+        },
+        Some(Token(TokenKind::Symbol(tag), tag_pos)) if tag == "queue" => {
+            match tokens.next() {
+                Some(Token(TokenKind::LBrack, _)) => {
+                    let (nodes, end) = parse_until(tokens, pos, |kind| matches!(kind, TokenKind::RBrack), "Queue", namespace)?;
+                    Ok(spanned(ptr(Node::Queue(nodes.into_iter().collect())), *pos, end))
+                },
+                _ => Err(ParseError::new("Expected a vector after #queue", Some(*tag_pos)))
+            }
+        },
+        Some(Token(TokenKind::LCurl, _)) => {
+            let mut set = PersistentHashSet::new();
+            let (nodes, end) = parse_until(tokens, pos, |kind| matches!(kind, TokenKind::RCurl), "Set", namespace)?;
+            let elements = nodes.clone();
+            for node in nodes {
+                let duplicate = node.to_string();
+                if !set.insert_mut(node) {
+                    return Err(ParseError::new(&format!("Duplicate element in set literal: {}", duplicate), Some(*pos)));
+                }
+            }
+            let set = ptr(Node::Set(set));
+            set_elements(&set, elements);
+            Ok(spanned(set, *pos, end))
+        },
+        Some(Token(kind, pos)) => {
+            Err(ParseError::new(&format!("Expected '{{' after '#' but got {}", kind), Some(*pos)))
+        },
+        None => Err(ParseError::new("Unexpected End of Token List after '#'", Some(*pos)))
     }
-    Ok(ptr(Node::Vector(vector)))
 }
 
-fn parse_set(_tokens: &mut TokenIter) -> ParseResult<NodePtr> {
-    unimplemented!()
+fn parse_map(tokens: &mut TokenIter, pos: &TokenPos, namespace: Symbol) -> ParseResult<NodePtr> {
+    let (nodes, end) = parse_until(tokens, pos, |kind| matches!(kind, TokenKind::RCurl), "Map", namespace)?;
+    Ok(spanned(build_map(nodes, pos)?, *pos, end))
 }
 
-fn parse_map(_tokens: &mut TokenIter) -> ParseResult<NodePtr> {
-    unimplemented!()
+fn build_map(nodes: Vec<NodePtr>, pos: &TokenPos) -> ParseResult<NodePtr> {
+    if !nodes.len().is_multiple_of(2) {
+        return Err(ParseError::new("Map literal must contain an even number of forms", Some(*pos)));
+    }
+    let mut map = PersistentHashMap::new();
+    let elements = nodes.clone();
+    let mut nodes = nodes.into_iter();
+    while let (Some(key), Some(value)) = (nodes.next(), nodes.next()) {
+        let duplicate = key.to_string();
+        if !map.insert_mut(key, value) {
+            return Err(ParseError::new(&format!("Duplicate key in map literal: {}", duplicate), Some(*pos)));
+        }
+    }
+    let map = ptr(Node::Map(map));
+    set_elements(&map, elements);
+    Ok(map)
 }
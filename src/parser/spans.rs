@@ -0,0 +1,111 @@
+use crate::nodes::{Node, NodePtr};
+use crate::intern::Symbol;
+use super::tokens::Span;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// Where a form comes from: the source it was read from, and the macro call it was expanded
+/// from if a macro produced it. Map and set literals also keep their elements in the order
+/// they were read, which their hashed contents lose.
+#[derive(Clone)]
+struct Location {
+    span: Option<Span>,
+    expansion: Option<(Symbol, NodePtr)>,
+    elements: Option<Rc<[NodePtr]>>
+}
+
+/// Locations of the parsed forms and macro expansions, found by the address of their node.
+///
+/// Nodes don't hold their location, so the table keeps a weak reference to each of them.
+/// It also keeps the address from being reused by another node, until the dead entries are
+/// removed as the table grows.
+#[derive(Default)]
+struct Locations {
+    locations: HashMap<*const Node, (Weak<Node>, Location)>,
+    /// Number of entries after the dead ones were last removed.
+    live: usize
+}
+
+thread_local! {
+    static LOCATIONS: RefCell<Locations> = RefCell::new(Locations::default());
+}
+
+fn set_location(node: &NodePtr, location: Location) {
+    LOCATIONS.with(|locations| {
+        let mut locations = locations.borrow_mut();
+        locations.locations.insert(Rc::as_ptr(node), (Rc::downgrade(node), location));
+        if locations.locations.len() > 2 * locations.live.max(1024) {
+            locations.locations.retain(|_, (node, _)| node.strong_count() > 0);
+            locations.live = locations.locations.len();
+        }
+    })
+}
+
+fn location(node: &NodePtr) -> Option<Location> {
+    LOCATIONS.with(|locations| {
+        locations.borrow().locations.get(&Rc::as_ptr(node)).map(|(_, location)| location.clone())
+    })
+}
+
+/// Remembers that `node` was read from `span`.
+pub fn set_span(node: &NodePtr, span: Span) {
+    let elements = location(node).and_then(|location| location.elements);
+    set_location(node, Location { span: Some(span), expansion: None, elements });
+}
+
+/// Finds the span `node` was read from. Macro expansions have the span of the macro call.
+pub fn span_of(node: &NodePtr) -> Option<Span> {
+    location(node).and_then(|location| location.span)
+}
+
+/// Gives `to` the location of `from`, for forms rebuilt from another one. Does nothing if
+/// `to` already has one.
+pub fn copy_span(from: &NodePtr, to: &NodePtr) {
+    if let (Some(location), None) = (location(from), location(to)) {
+        set_location(to, location);
+    }
+}
+
+/// Remembers that `expansion`, and the forms nested in it, were produced by the macro `name`
+/// called by `form`. Forms that already have a location, like the arguments of the macro, are
+/// left as they are.
+pub fn set_expansion(form: &NodePtr, expansion: &NodePtr, name: Symbol) {
+    if location(expansion).is_some() {
+        return;
+    }
+    set_location(expansion, Location { span: span_of(form), expansion: Some((name, form.clone())), elements: None });
+    match expansion.as_ref() {
+        Node::List(_, _) => {
+            let mut cell = expansion;
+            while let Node::List(element, rest) = cell.as_ref() {
+                set_expansion(form, element, name);
+                cell = rest;
+            }
+        },
+        Node::Vector(vector) => vector.iter().for_each(|element| set_expansion(form, element, name)),
+        Node::Set(set) => set.iter().for_each(|element| set_expansion(form, element, name)),
+        Node::Map(map) => map.iter().for_each(|(key, value)| {
+            set_expansion(form, key, name);
+            set_expansion(form, value, name);
+        }),
+        _ => ()
+    }
+}
+
+/// Remembers the elements of a map or set literal in source order, with the keys and values
+/// of maps in turn.
+pub fn set_elements(node: &NodePtr, elements: Vec<NodePtr>) {
+    let location = location(node).unwrap_or(Location { span: None, expansion: None, elements: None });
+    set_location(node, Location { elements: Some(elements.into()), ..location });
+}
+
+/// Finds the elements of a map or set literal in source order.
+pub fn elements_of(node: &NodePtr) -> Option<Rc<[NodePtr]>> {
+    location(node).and_then(|location| location.elements)
+}
+
+/// Finds the macro and the call `node` was expanded from.
+pub fn expansion_of(node: &NodePtr) -> Option<(Symbol, NodePtr)> {
+    location(node).and_then(|location| location.expansion)
+}
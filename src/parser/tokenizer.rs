@@ -2,6 +2,8 @@ use std::iter::Peekable;
 use std::str::Chars;
 use super::tokens::{Token, TokenPos};
 use crate::parser::tokens::TokenKind;
+use crate::numbers::Number;
+use crate::intern::Symbol;
 
 #[derive(Debug)]
 pub struct TokenizeError {
@@ -16,11 +18,19 @@ impl TokenizeError {
             pos
         }
     }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn pos(&self) -> TokenPos {
+        self.pos
+    }
 }
 
 impl std::fmt::Display for TokenizeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
+        write!(f, "Tokenize Error ({}) {}", self.pos, self.message)
     }
 }
 
@@ -30,6 +40,7 @@ pub type TokenizeResult<T> = std::result::Result<T, TokenizeError>;
 
 struct Tokenizer<'a> {
     chars: Peekable<Chars<'a>>,
+    source: Symbol,
 
     pub line: usize,
     pub column: usize,
@@ -37,10 +48,11 @@ struct Tokenizer<'a> {
 
 
 impl<'a> Tokenizer<'a> {
-    fn new(chars: Peekable<Chars<'a>>) -> Self {
+    fn new(chars: Peekable<Chars<'a>>, source: Symbol, line: usize) -> Self {
         Self {
             chars,
-            line: 1,
+            source,
+            line,
             column: 1
         }
     }
@@ -49,8 +61,12 @@ impl<'a> Tokenizer<'a> {
         self.chars.peek()
     }
 
+    fn peek_second(&self) -> Option<char> {
+        self.chars.clone().nth(1)
+    }
+
     fn next(&mut self) -> Option<char> {
-        if self.peek().map_or(false, |c| *c == '\n') {
+        if self.peek() == Some(&'\n') {
             self.line += 1;
             self.column = 1;
         } else {
@@ -61,20 +77,28 @@ impl<'a> Tokenizer<'a> {
 
     fn pos(&self) -> TokenPos {
         TokenPos {
+            source: self.source,
             line: self.line,
             column: self.column
         }
     }
 }
 
-pub fn tokenize(source: &str) -> TokenizeResult<Vec<Token>> {
+/// Splits `source` into tokens. Their positions refer to the source by `name`.
+pub fn tokenize(source: &str, name: &str) -> TokenizeResult<Vec<Token>> {
+    tokenize_from(source, name, 1)
+}
+
+/// Splits `source` into tokens, for a source starting at `line` of the one named `name`, like
+/// the inputs of a REPL.
+pub fn tokenize_from(source: &str, name: &str, line: usize) -> TokenizeResult<Vec<Token>> {
 
-    let mut tokenizer = Tokenizer::new(source.chars().peekable());
+    let mut tokenizer = Tokenizer::new(source.chars().peekable(), Symbol::intern(name), line);
     skip_spaces(&mut tokenizer);
 
     let mut tokens = Vec::new();
 
-    while let Some(_) = tokenizer.peek() {
+    while tokenizer.peek().is_some() {
         let token = next_token(&mut tokenizer)?;
         tokens.push(token);
 
@@ -86,16 +110,23 @@ pub fn tokenize(source: &str) -> TokenizeResult<Vec<Token>> {
 }
 
 fn skip_spaces(tokenizer: &mut Tokenizer) {
-    while tokenizer.peek().map_or(false, |c| c.is_ascii_whitespace()) {
+    // Commas are whitespace, they only help readability in literals like {:a 1, :b 2}
+    while tokenizer.peek().is_some_and(|c| c.is_ascii_whitespace() || *c == ',') {
         tokenizer.next();
     }
 }
 
 fn next_token(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
-    if let Some(c) = tokenizer.peek() {
-        match *c {
+    if let Some(&c) = tokenizer.peek() {
+        match c {
+            '+' | '-' if tokenizer.peek_second().is_some_and(|c| c.is_ascii_digit()) => {
+                read_number(tokenizer)
+            },
             'a'..='z' | 'A'..='Z' | ':' => {
-                read_symbol_or_ident(tokenizer)
+                read_symbol_or_keyword(tokenizer)
+            },
+            c if is_symbol_char(c) => {
+                read_symbol_or_keyword(tokenizer)
             },
             '0'..='9' => {
                 read_number(tokenizer)
@@ -115,19 +146,23 @@ fn next_token(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
 
 #[inline]
 fn is_reserved_char(c: char) -> bool {
-    match c {
-        '[' | ']' | '(' | ')' | '{' | '}' | '\'' | '"' => true,
-        _ => false
-    }
+    matches!(c, '[' | ']' | '(' | ')' | '{' | '}' | '\'' | '"' | '`' | '~')
+}
+
+/// Characters other than letters that can start a symbol, like in `+`, `<=` or `*out*`.
+#[inline]
+fn is_symbol_char(c: char) -> bool {
+    matches!(c, '*' | '+' | '!' | '-' | '_' | '?' | '<' | '>' | '=' | '/' | '.' | '&' | '%' | '$')
 }
 
-fn read_symbol_or_ident(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
+fn read_symbol_or_keyword(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
     let mut value = String::new();
 
     let pos = tokenizer.pos();
 
+    // A quote can't start a symbol, but can be part of one, like in `+'`.
     while let Some(c) = tokenizer.peek() {
-        if c.is_ascii_whitespace() || is_reserved_char(*c) {
+        if c.is_ascii_whitespace() || *c == ',' || (is_reserved_char(*c) && *c != '\'') {
             break;
         } else {
             value.push(tokenizer.next().unwrap());
@@ -135,26 +170,36 @@ fn read_symbol_or_ident(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
     }
 
     if value.starts_with(':') {
-        Ok(Token(TokenKind::Ident(value), pos))
+        Ok(Token(TokenKind::Keyword(value), pos))
     } else {
         Ok(Token(TokenKind::Symbol(value), pos))
     }
 }
 
 fn read_number(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
-    let mut value: i64 = 0;
+    let mut text = String::new();
     let pos = tokenizer.pos();
 
-    // TODO: Different representations
-    while let Some(c) = tokenizer.peek() {
-        if c.is_digit(10) {
-            value = value * 10 + i64::from(tokenizer.next().unwrap().to_digit(10).unwrap());
-        } else {
+    // The whole literal is read first, so that something like `12abc` is an error
+    // instead of a number followed by a symbol.
+    while let Some(&c) = tokenizer.peek() {
+        if c.is_ascii_whitespace() || c == ',' || is_reserved_char(c) {
             break;
         }
+        text.push(c);
+        tokenizer.next();
     }
 
-    Ok(Token(TokenKind::Integer(value), pos))
+    let kind = match Number::parse(&text) {
+        Some(Number::Integer(int)) => TokenKind::Integer(int),
+        Some(Number::BigInt(int)) => TokenKind::BigInt(int),
+        Some(Number::Ratio(ratio)) => TokenKind::Ratio(ratio),
+        Some(Number::Decimal(decimal)) => TokenKind::Decimal(decimal),
+        Some(Number::Float(float)) => TokenKind::Float(float),
+        None => return Err(TokenizeError::new(&format!("Invalid number: {}", text), pos))
+    };
+
+    Ok(Token(kind, pos))
 }
 
 fn read_string(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
@@ -192,7 +237,7 @@ fn read_char(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
 
 fn single_char_token(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
     let pos = tokenizer.pos();
-    let token = match tokenizer.peek() {
+    let token = match tokenizer.peek().copied() {
         Some('(') => Ok(Token(TokenKind::LParen, pos)),
         Some(')') => Ok(Token(TokenKind::RParen, pos)),
         Some('[') => Ok(Token(TokenKind::LBrack, pos)),
@@ -200,8 +245,13 @@ fn single_char_token(tokenizer: &mut Tokenizer) -> TokenizeResult<Token> {
         Some('{') => Ok(Token(TokenKind::LCurl, pos)),
         Some('}') => Ok(Token(TokenKind::RCurl, pos)),
         Some('#') => Ok(Token(TokenKind::Hash, pos)),
-        Some(',') => Ok(Token(TokenKind::Comma, pos)),
         Some('\'') => Ok(Token(TokenKind::SingleQuote, pos)),
+        Some('`') => Ok(Token(TokenKind::Backquote, pos)),
+        Some('~') if tokenizer.peek_second() == Some('@') => {
+            tokenizer.next();
+            Ok(Token(TokenKind::TildeAt, pos))
+        },
+        Some('~') => Ok(Token(TokenKind::Tilde, pos)),
         Some(c) => Err(TokenizeError::new(&format!("Unexpected Character: {}", c), pos)),
         _ => Err(TokenizeError::new("Unexpected End of File", pos))
     };
@@ -1,10 +1,44 @@
+use crate::numbers::{BigInt, Ratio, BigDecimal};
+use crate::intern::Symbol;
 
-#[derive(Debug, Copy, Clone)]
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct TokenPos {
+    /// Name of the source, like the path of a file.
+    pub source: Symbol,
     pub line: usize,
     pub column: usize,
 }
 
+impl TokenPos {
+    /// Position `count` columns further on the same line.
+    pub fn advance(self, count: usize) -> Self {
+        Self {
+            column: self.column + count,
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for TokenPos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.line, self.column)
+    }
+}
+
+/// Part of a source covered by a form, from `start` up to `end` excluded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: TokenPos,
+    pub end: TokenPos,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
 #[derive(Debug)]
 pub enum TokenKind {
     LParen,
@@ -14,13 +48,18 @@ pub enum TokenKind {
     LCurl,
     RCurl,
     SingleQuote,
+    Backquote,
+    Tilde,
+    TildeAt,
     Hash,
-    Comma,
     Symbol(String),
-    Ident(String),
+    Keyword(String),
     String(String),
     Char(char),
     Integer(i64),
+    BigInt(BigInt),
+    Ratio(Ratio),
+    Decimal(BigDecimal),
     Float(f64),
 }
 
@@ -43,13 +82,18 @@ impl std::fmt::Display for TokenKind {
             TokenKind::LCurl => write!(f, "'{{'"),
             TokenKind::RCurl => write!(f, "'}}'"),
             TokenKind::SingleQuote => write!(f, "\"'\""),
+            TokenKind::Backquote => write!(f, "'`'"),
+            TokenKind::Tilde => write!(f, "'~'"),
+            TokenKind::TildeAt => write!(f, "'~@'"),
             TokenKind::Hash => write!(f, "'#'"),
-            TokenKind::Comma => write!(f, "','"),
             TokenKind::Symbol(s) => write!(f, "'{}'", s),
-            TokenKind::Ident(i) => write!(f, "'{}'", i),
+            TokenKind::Keyword(i) => write!(f, "'{}'", i),
             TokenKind::String(s) => write!(f, "'{}'", s),
             TokenKind::Char(c) => write!(f, "'{}'", c),
             TokenKind::Integer(i) => write!(f, "'{}'", i),
+            TokenKind::BigInt(n) => write!(f, "'{}N'", n),
+            TokenKind::Ratio(n) => write!(f, "'{}'", n),
+            TokenKind::Decimal(n) => write!(f, "'{}M'", n),
             TokenKind::Float(n) => write!(f, "'{}'", n),
         }
     }